@@ -0,0 +1,75 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(25);",
+    ),
+    (
+        "loop",
+        "var sum = 0; for (var i = 0; i < 1000000; i = i + 1) { sum = sum + i; } print sum;",
+    ),
+    (
+        "closures",
+        "
+        fun counter() { var count = 0; fun increment() { count = count + 1; return count; } return increment; }
+        var c = counter();
+        for (var i = 0; i < 300000; i = i + 1) c();
+        print c();
+        ",
+    ),
+    (
+        "methods",
+        "
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            add(other) { return Point(this.x + other.x, this.y + other.y); }
+        }
+        var p = Point(0, 0);
+        for (var i = 0; i < 100000; i = i + 1) p = p.add(Point(1, 2));
+        print p.x;
+        ",
+    ),
+];
+
+/// Writes a program to a temporary file, for rlox to run.
+fn program_file(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rlox-bench-{}.lox", name));
+    std::fs::write(&path, source).expect("unable to write the bench program");
+    path
+}
+
+fn run(path: &Path, decode: bool) {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rlox"));
+    command.arg("run").arg(path).env("RUST_LOG", "off");
+    if decode {
+        command.arg("--decode");
+    }
+    let status = command.status().expect("unable to run rlox");
+    assert!(status.success());
+}
+
+/// Compares running the raw bytecode against decoding each instruction first. Each sample runs
+/// the binary, so the programs are large enough for starting it to not matter.
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.sample_size(10);
+    for (name, source) in PROGRAMS {
+        let path = program_file(name, source);
+        group.bench_with_input(BenchmarkId::new("raw", name), &path, |b, path| {
+            b.iter(|| run(path, false))
+        });
+        group.bench_with_input(BenchmarkId::new("decoded", name), &path, |b, path| {
+            b.iter(|| run(path, true))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
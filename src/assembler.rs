@@ -0,0 +1,682 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter::Peekable,
+    rc::Rc,
+    str::Chars,
+};
+
+use thiserror::Error;
+
+use crate::{
+    bytecode::{
+        core::{Capture, Chunk, OpCode},
+        parser::BytecodeParseError,
+    },
+    compiler::scanner::Location,
+    object::Function,
+    value::Value,
+};
+
+/// The extension of textual bytecode files.
+pub const EXTENSION: &str = "s";
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum AssembleError {
+    #[error("unterminated string at {0}")]
+    UnterminatedString(Location),
+    #[error("invalid escape sequence at {0}")]
+    InvalidEscape(Location),
+    #[error("expected {1} at {0}")]
+    Expected(Location, &'static str),
+    #[error("unknown instruction '{1}' at {0}")]
+    UnknownInstruction(Location, String),
+    #[error("label '{1}' is already defined at {0}")]
+    DuplicateLabel(Location, String),
+    #[error("undefined label '{1}' at {0}")]
+    UndefinedLabel(Location, String),
+    #[error("can't jump to label '{1}' at {0}")]
+    InvalidJump(Location, String),
+    #[error("too many constants at {0}")]
+    TooManyConstants(Location),
+    #[error("function captures {expected} variables, but {found} are listed at {location}")]
+    WrongCaptureCount {
+        location: Location,
+        expected: u8,
+        found: usize,
+    },
+    #[error("{1} at {0}")]
+    InvalidBytecode(Location, BytecodeParseError),
+}
+
+/// Assembles the textual format written by
+/// [`to_assembly`](crate::dissembler::to_assembly) into a chunk.
+///
+/// Each line holds one instruction, written as its mnemonic followed by its operands, e.g.
+/// `CONSTANT 1.5`, `GET_GLOBAL name`, `INVOKE name 2` or `CLOSURE [local 1, upvalue 0] fun f 1 2 {`,
+/// where the function's name, arity and upvalue count are followed by its own chunk up to a
+/// closing `}`. Jumps refer to a `label:` on its own line, `@line` sets the source line of the
+/// instructions after it, and `;` starts a comment.
+///
+/// Constants are added in the same way as the compiler adds them. Alternatively, `.constant value`
+/// adds a constant to the chunk as is, and `#index` refers to a constant by its index.
+pub fn assemble(source: &str) -> Result<Chunk, Vec<AssembleError>> {
+    let mut errors = Vec::new();
+    let tokens = tokenize(source, &mut errors);
+    let mut assembler = Assembler {
+        tokens,
+        current: 0,
+        errors,
+    };
+    let chunk = assembler.chunk(None);
+    if assembler.errors.is_empty() {
+        Ok(chunk)
+    } else {
+        Err(assembler.errors)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
+    Newline,
+}
+
+fn tokenize(source: &str, errors: &mut Vec<AssembleError>) -> Vec<(Location, Token)> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let location = Location { line };
+        let token = match c {
+            '\n' => {
+                line += 1;
+                Token::Newline
+            }
+            ';' => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            '{' => Token::LeftBrace,
+            '}' => Token::RightBrace,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '"' => match string(&mut chars, location) {
+                Ok(s) => Token::String(s),
+                Err(e) => {
+                    errors.push(e);
+                    while chars.next_if(|&c| c != '\n').is_some() {}
+                    // stands in for the string, so the rest of the line doesn't report more errors
+                    Token::String(String::new())
+                }
+            },
+            c if c.is_whitespace() => continue,
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !"{}[],:;\"".contains(c))
+                {
+                    word.push(c);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push((location, token));
+    }
+    tokens
+}
+
+/// Reads a string after its opening quote, undoing the escapes that `{:?}` adds.
+fn string(chars: &mut Peekable<Chars<'_>>, location: Location) -> Result<String, AssembleError> {
+    let mut s = String::new();
+    loop {
+        let c = match chars.next_if(|&c| c != '\n') {
+            None => return Err(AssembleError::UnterminatedString(location)),
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next_if(|&c| c != '\n') {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                Some('u') if chars.next_if_eq(&'{').is_some() => {
+                    let mut hex = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_hexdigit) {
+                        hex.push(c);
+                    }
+                    chars
+                        .next_if_eq(&'}')
+                        .and_then(|_| u32::from_str_radix(&hex, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or(AssembleError::InvalidEscape(location))?
+                }
+                _ => return Err(AssembleError::InvalidEscape(location)),
+            },
+            Some(c) => c,
+        };
+        s.push(c);
+    }
+}
+
+/// A chunk that is being assembled, along with what needs fixing up or checking once it's done.
+struct Body {
+    chunk: Chunk,
+    line: u32,
+    labels: HashMap<String, usize>,
+    jumps: Vec<(Location, usize, String)>,
+    closures: Vec<(Location, u8, usize)>,
+    locations: BTreeMap<usize, Location>,
+}
+
+struct Assembler {
+    tokens: Vec<(Location, Token)>,
+    current: usize,
+    errors: Vec<AssembleError>,
+}
+
+impl Assembler {
+    /// Assembles items up to the end of the source, or to the `}` that closes a function opened
+    /// at `opened`.
+    fn chunk(&mut self, opened: Option<Location>) -> Chunk {
+        let previous_errors = self.errors.len();
+        let mut body = Body {
+            chunk: Chunk::new(),
+            line: 1,
+            labels: HashMap::new(),
+            jumps: Vec::new(),
+            closures: Vec::new(),
+            locations: BTreeMap::new(),
+        };
+
+        loop {
+            let (location, token) = match self.advance() {
+                Some(next) => next,
+                None => {
+                    if let Some(location) = opened {
+                        let error = AssembleError::Expected(location, "'}' to close the function");
+                        self.errors.push(error);
+                    }
+                    break;
+                }
+            };
+            let result = match token {
+                Token::Newline => continue,
+                Token::RightBrace if opened.is_some() => break,
+                Token::Word(word) => self.item(&mut body, location, word),
+                _ => Err(AssembleError::Expected(location, "an instruction")),
+            };
+            if let Err(e) = result {
+                self.errors.push(e);
+                self.skip_line();
+            }
+        }
+
+        self.finish(body, previous_errors)
+    }
+
+    fn item(
+        &mut self,
+        body: &mut Body,
+        location: Location,
+        word: String,
+    ) -> Result<(), AssembleError> {
+        if let Some(line) = word.strip_prefix('@') {
+            body.line = line
+                .parse()
+                .map_err(|_| AssembleError::Expected(location, "a line number"))?;
+        } else if word == ".constant" {
+            let value = self.value(location)?;
+            if !body.chunk.has_room_for_constant() {
+                return Err(AssembleError::TooManyConstants(location));
+            }
+            body.chunk.constants.push(value);
+        } else if self.peek() == Some(&Token::Colon) {
+            self.advance();
+            if body.labels.contains_key(&word) {
+                return Err(AssembleError::DuplicateLabel(location, word));
+            }
+            body.labels.insert(word, body.chunk.code.len());
+        } else {
+            let op = OpCode::from_mnemonic(&word)
+                .ok_or(AssembleError::UnknownInstruction(location, word))?;
+            self.instruction(body, location, op)?;
+        }
+
+        match self.peek() {
+            None | Some(Token::Newline) => Ok(()),
+            Some(_) => Err(AssembleError::Expected(location, "the end of the line")),
+        }
+    }
+
+    fn instruction(
+        &mut self,
+        body: &mut Body,
+        location: Location,
+        op: OpCode,
+    ) -> Result<(), AssembleError> {
+        let pos = body.chunk.code.len();
+        let mut operands = Vec::new();
+        match op {
+            OpCode::Constant => operands.push(self.constant(body, location)?),
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method => operands.push(self.name(body, location)?),
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => operands.push(self.byte(location)?),
+            OpCode::Invoke | OpCode::SuperInvoke => {
+                operands.push(self.name(body, location)?);
+                operands.push(self.byte(location)?);
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfTrue | OpCode::Loop => {
+                let label = match self.operand() {
+                    Some(Token::Word(label)) => label,
+                    _ => return Err(AssembleError::Expected(location, "a label")),
+                };
+                // patched once all the labels are known
+                body.jumps.push((location, pos, label));
+                operands.extend([0, 0]);
+            }
+            OpCode::Closure => {
+                let captures = self.captures(location)?;
+                let index = self.constant(body, location)?;
+                body.closures.push((location, index, captures.len()));
+                operands.push(index);
+                for capture in captures {
+                    operands.extend([capture.is_local.into(), capture.index]);
+                }
+            }
+            _ => (),
+        }
+
+        body.locations.insert(pos, location);
+        body.chunk.add_op(op, body.line);
+        for operand in operands {
+            body.chunk.add_raw(operand, body.line);
+        }
+        Ok(())
+    }
+
+    /// Patches the chunk's jumps, and checks that it can be parsed.
+    fn finish(&mut self, mut body: Body, previous_errors: usize) -> Chunk {
+        let chunk = &mut body.chunk;
+        for (location, pos, label) in body.jumps {
+            let target = match body.labels.get(&label) {
+                Some(&target) => target,
+                None => {
+                    self.errors
+                        .push(AssembleError::UndefinedLabel(location, label));
+                    continue;
+                }
+            };
+            let next = pos + 3;
+            let offset = if chunk.code[pos] == u8::from(OpCode::Loop) {
+                next.checked_sub(target)
+            } else {
+                target.checked_sub(next)
+            };
+            match offset.and_then(|offset| u16::try_from(offset).ok()) {
+                Some(offset) => chunk.code[pos + 1..pos + 3].copy_from_slice(&offset.to_be_bytes()),
+                None => self
+                    .errors
+                    .push(AssembleError::InvalidJump(location, label)),
+            }
+        }
+
+        for (location, index, found) in body.closures {
+            if let Some(function) = chunk
+                .constants
+                .get(index as usize)
+                .and_then(Value::as_function)
+            {
+                if function.upvalue_count as usize != found {
+                    self.errors.push(AssembleError::WrongCaptureCount {
+                        location,
+                        expected: function.upvalue_count,
+                        found,
+                    });
+                }
+            }
+        }
+
+        // constants referred to by index may be missing or of the wrong type
+        if self.errors.len() == previous_errors {
+            for (metadata, parsed) in chunk.iter() {
+                if let Err(e) = parsed {
+                    let location = body
+                        .locations
+                        .range(..=metadata.pos)
+                        .next_back()
+                        .map_or(Location { line: 1 }, |(_, &location)| location);
+                    self.errors
+                        .push(AssembleError::InvalidBytecode(location, e));
+                }
+            }
+        }
+        body.chunk
+    }
+
+    /// A constant operand, either as a value or as `#index`.
+    fn constant(&mut self, body: &mut Body, location: Location) -> Result<u8, AssembleError> {
+        if let Some(index) = self.index(location)? {
+            return Ok(index);
+        }
+        let value = self.value(location)?;
+        self.add_constant(body, location, value)
+    }
+
+    /// A string constant operand, either as a bare word, a quoted string or `#index`.
+    fn name(&mut self, body: &mut Body, location: Location) -> Result<u8, AssembleError> {
+        if let Some(index) = self.index(location)? {
+            return Ok(index);
+        }
+        let name = match self.operand() {
+            Some(Token::Word(name) | Token::String(name)) => name,
+            _ => return Err(AssembleError::Expected(location, "a name")),
+        };
+        self.add_constant(body, location, Value::from(name.as_str()))
+    }
+
+    fn index(&mut self, location: Location) -> Result<Option<u8>, AssembleError> {
+        let index = match self.peek() {
+            Some(Token::Word(word)) => match word.strip_prefix('#') {
+                Some(index) => index.parse(),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.advance();
+        index
+            .map(Some)
+            .map_err(|_| AssembleError::Expected(location, "a constant index"))
+    }
+
+    fn add_constant(
+        &mut self,
+        body: &mut Body,
+        location: Location,
+        value: Value,
+    ) -> Result<u8, AssembleError> {
+        if !body.chunk.has_room_for_constant() {
+            return Err(AssembleError::TooManyConstants(location));
+        }
+        Ok(body.chunk.add_constant(value))
+    }
+
+    fn value(&mut self, location: Location) -> Result<Value, AssembleError> {
+        let value = match self.operand() {
+            Some(Token::String(s)) => Value::from(s.as_str()),
+            Some(Token::Word(word)) => match word.as_str() {
+                "nil" => Value::NIL,
+                "true" => Value::from(true),
+                "false" => Value::from(false),
+                "fun" => Value::from(Rc::new(self.function(location)?)),
+                number => number
+                    .parse::<f64>()
+                    .map(Value::from)
+                    .map_err(|_| AssembleError::Expected(location, "a constant"))?,
+            },
+            _ => return Err(AssembleError::Expected(location, "a constant")),
+        };
+        Ok(value)
+    }
+
+    /// A function's optional name, arity and upvalue count, followed by its chunk in braces.
+    fn function(&mut self, location: Location) -> Result<Function, AssembleError> {
+        let name = match self.peek() {
+            Some(Token::Word(word)) if word.parse::<u8>().is_err() => self.operand(),
+            Some(Token::String(_)) => self.operand(),
+            _ => None,
+        };
+        let name = match name {
+            Some(Token::Word(name) | Token::String(name)) => Some(Rc::from(name)),
+            _ => None,
+        };
+        let arity = self.byte(location)?;
+        let upvalue_count = self.byte(location)?;
+        if self.operand() != Some(Token::LeftBrace) {
+            return Err(AssembleError::Expected(location, "'{'"));
+        }
+        let chunk = self.chunk(Some(location));
+        Ok(Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk,
+        })
+    }
+
+    /// A list of captures like `[local 1, upvalue 0]`.
+    fn captures(&mut self, location: Location) -> Result<Vec<Capture>, AssembleError> {
+        if self.operand() != Some(Token::LeftBracket) {
+            return Err(AssembleError::Expected(location, "a list of captures"));
+        }
+        let mut captures = Vec::new();
+        if self.peek() == Some(&Token::RightBracket) {
+            self.advance();
+            return Ok(captures);
+        }
+        loop {
+            let is_local = match self.operand() {
+                Some(Token::Word(word)) if word == "local" => true,
+                Some(Token::Word(word)) if word == "upvalue" => false,
+                _ => return Err(AssembleError::Expected(location, "'local' or 'upvalue'")),
+            };
+            let index = self.byte(location)?;
+            captures.push(Capture { is_local, index });
+            match self.operand() {
+                Some(Token::Comma) => (),
+                Some(Token::RightBracket) => return Ok(captures),
+                _ => return Err(AssembleError::Expected(location, "',' or ']'")),
+            }
+        }
+    }
+
+    fn byte(&mut self, location: Location) -> Result<u8, AssembleError> {
+        match self.operand() {
+            Some(Token::Word(word)) => word.parse().ok(),
+            _ => None,
+        }
+        .ok_or(AssembleError::Expected(location, "a number from 0 to 255"))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current).map(|(_, token)| token)
+    }
+
+    fn advance(&mut self) -> Option<(Location, Token)> {
+        let next = self.tokens.get(self.current).cloned();
+        if next.is_some() {
+            self.current += 1;
+        }
+        next
+    }
+
+    /// The next token, unless the line has ended.
+    fn operand(&mut self) -> Option<Token> {
+        match self.peek() {
+            None | Some(Token::Newline) => None,
+            _ => self.advance().map(|(_, token)| token),
+        }
+    }
+
+    /// Skips the rest of a line after an error, including any function that starts on it.
+    fn skip_line(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Newline if depth == 0 => break,
+                Token::RightBrace if depth == 0 => break,
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace => depth -= 1,
+                _ => (),
+            }
+            self.current += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bytecode::{
+            core::{BinaryOp, Instruction},
+            peephole::optimize_function,
+        },
+        compiler::{codegen::compile, constant_folding::fold_constants, parser::Parser},
+        dissembler::to_assembly,
+    };
+    use test_case::test_case;
+
+    #[test]
+    fn instructions() {
+        let source = "
+            @1
+                CONSTANT 1.5
+                GET_GLOBAL a      ; a comment
+            loop:
+                JUMP_IF_FALSE end
+            @2
+                INVOKE \"b c\" 2
+                LOOP loop
+            end:
+                CLOSURE [local 1, upvalue 0] fun f 0 2 {
+                @3
+                    NIL
+                    RETURN
+                }
+                RETURN
+        ";
+        let mut function = Function::new(Some(Rc::from("f")));
+        function.upvalue_count = 2;
+        function
+            .chunk
+            .add_instructions(&[(3, Instruction::Nil), (3, Instruction::Return)]);
+
+        let mut expected = Chunk::new();
+        expected.add_instructions(&[
+            (1, Instruction::Constant(Value::from(1.5))),
+            (1, Instruction::GetGlobal(Rc::from("a"))),
+            (1, Instruction::JumpIfFalse(6)),
+            (2, Instruction::Invoke(Rc::from("b c"), 2)),
+            (2, Instruction::Loop(9)),
+            (
+                2,
+                Instruction::Closure(
+                    Rc::new(function),
+                    vec![
+                        Capture {
+                            is_local: true,
+                            index: 1,
+                        },
+                        Capture {
+                            is_local: false,
+                            index: 0,
+                        },
+                    ],
+                ),
+            ),
+            (2, Instruction::Return),
+        ]);
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test_case("print -(2 * 3) + 4 == \"a\\n\";"; "expressions")]
+    #[test_case("var a = 1; { var b = a; a = b; } print a;"; "variables")]
+    #[test_case("for (var i = 0; i < 3; i = i + 1) if (i > 1 and !false) print i; else print nil;"; "control flow")]
+    #[test_case("
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+        }
+        print counter()();
+    "; "closures")]
+    #[test_case("
+        class A { init(n) { this.n = n; } get() { return this.n; } }
+        class B < A { get() { return super.get() + 1; } }
+        print B(1).get();
+    "; "classes")]
+    fn round_trip(source: &str) {
+        for optimize in [false, true] {
+            let mut declarations = Parser::new(source).parse().unwrap();
+            if optimize {
+                declarations = fold_constants(declarations);
+            }
+            let mut function = compile(&declarations).unwrap();
+            if optimize {
+                function = optimize_function(&function).unwrap();
+            }
+
+            let text = to_assembly(&function.chunk);
+            assert_eq!(assemble(&text), Ok(function.chunk), "{}", text);
+        }
+    }
+
+    #[test]
+    fn round_trip_by_index() {
+        let chunk = Chunk {
+            code: vec![OpCode::Constant.into(), 1, OpCode::Return.into()],
+            lines: vec![1, 1, 1],
+            // a duplicate, and an unused constant
+            constants: vec![Value::from(1.0), Value::from(1.0), Value::from("a")],
+        };
+        let text = to_assembly(&chunk);
+        assert!(text.contains("CONSTANT #1"), "{}", text);
+        assert_eq!(assemble(&text), Ok(chunk));
+    }
+
+    #[test]
+    fn label_at_end() {
+        let mut expected = Chunk::new();
+        expected.add_instructions(&[
+            (1, Instruction::Jump(1)),
+            (1, Instruction::BinaryOp(BinaryOp::Add)),
+        ]);
+        let text = to_assembly(&expected);
+        assert_eq!(assemble(&text), Ok(expected));
+    }
+
+    #[test_case("FOO", AssembleError::UnknownInstruction(Location { line: 1 }, "FOO".to_string()); "unknown instruction")]
+    #[test_case("JUMP end", AssembleError::UndefinedLabel(Location { line: 1 }, "end".to_string()); "undefined label")]
+    #[test_case("a:\na:", AssembleError::DuplicateLabel(Location { line: 2 }, "a".to_string()); "duplicate label")]
+    #[test_case("a:\nJUMP a", AssembleError::InvalidJump(Location { line: 2 }, "a".to_string()); "backward jump")]
+    #[test_case("GET_LOCAL 256", AssembleError::Expected(Location { line: 1 }, "a number from 0 to 255"); "byte out of range")]
+    #[test_case("NIL 1", AssembleError::Expected(Location { line: 1 }, "the end of the line"); "extra operand")]
+    #[test_case("CONSTANT \"a", AssembleError::UnterminatedString(Location { line: 1 }); "unterminated string")]
+    #[test_case("CONSTANT \"\\q\"", AssembleError::InvalidEscape(Location { line: 1 }); "invalid escape")]
+    #[test_case("CLOSURE [] fun f 0 1 {\n}", AssembleError::WrongCaptureCount { location: Location { line: 1 }, expected: 1, found: 0 }; "wrong capture count")]
+    #[test_case("CLOSURE [] fun f 0 0 {", AssembleError::Expected(Location { line: 1 }, "'}' to close the function"); "unclosed function")]
+    #[test_case("\n.constant 1\nGET_GLOBAL #0", AssembleError::InvalidBytecode(Location { line: 3 }, BytecodeParseError::ExpectedString(OpCode::GetGlobal, 0)); "wrong constant type")]
+    fn error(source: &str, expected: AssembleError) {
+        assert_eq!(assemble(source), Err(vec![expected]));
+    }
+
+    #[test]
+    fn errors_on_every_line() {
+        let errors = assemble("FOO\nNIL\nCONSTANT\n").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                AssembleError::UnknownInstruction(Location { line: 1 }, "FOO".to_string()),
+                AssembleError::Expected(Location { line: 3 }, "a constant"),
+            ]
+        );
+    }
+}
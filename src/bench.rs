@@ -0,0 +1,205 @@
+use std::{
+    io,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::{
+    object::Function,
+    vm::{InterpreterError, VM},
+};
+
+/// The benchmark suite, by name.
+pub const PROGRAMS: &[(&str, &str)] = &[
+    ("fib", include_str!("../benches/lox/fib.lox")),
+    ("loops", include_str!("../benches/lox/loops.lox")),
+    ("strings", include_str!("../benches/lox/strings.lox")),
+    ("methods", include_str!("../benches/lox/methods.lox")),
+    (
+        "binary_trees",
+        include_str!("../benches/lox/binary_trees.lox"),
+    ),
+    ("zoo", include_str!("../benches/lox/zoo.lox")),
+];
+
+/// How long a benchmark took over its timed runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub name: String,
+    pub runs: usize,
+    pub mean: Duration,
+    pub stddev: Duration,
+    /// the instructions executed by a single run
+    pub instructions: u64,
+}
+
+/// Runs a script `warmups` times, and then times it over `runs` more runs. Every run has a fresh
+/// VM, and what the script prints is discarded.
+pub fn measure(
+    name: &str,
+    function: &Rc<Function>,
+    warmups: usize,
+    runs: usize,
+) -> Result<Measurement, InterpreterError> {
+    assert!(runs > 0, "a benchmark needs at least one timed run");
+    for _ in 0..warmups {
+        VM::with_output(Box::new(io::sink())).interpret(function.clone())?;
+    }
+
+    let mut times = Vec::with_capacity(runs);
+    let mut instructions = 0;
+    for _ in 0..runs {
+        let mut vm = VM::with_output(Box::new(io::sink()));
+        let start = Instant::now();
+        vm.interpret(function.clone())?;
+        times.push(start.elapsed().as_secs_f64());
+        instructions = vm.instructions_executed();
+    }
+
+    let mean = times.iter().sum::<f64>() / runs as f64;
+    let variance =
+        times.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / (runs.max(2) - 1) as f64;
+    Ok(Measurement {
+        name: name.to_string(),
+        runs,
+        mean: Duration::from_secs_f64(mean),
+        stddev: Duration::from_secs_f64(variance.sqrt()),
+        instructions,
+    })
+}
+
+/// Measurements as JSON, which can be read back by [`from_json`] to compare against later.
+pub fn to_json(measurements: &[Measurement]) -> serde_json::Value {
+    let benchmarks: Vec<_> = measurements
+        .iter()
+        .map(|measurement| {
+            json!({
+                "name": measurement.name,
+                "runs": measurement.runs,
+                "mean_ns": measurement.mean.as_nanos() as u64,
+                "stddev_ns": measurement.stddev.as_nanos() as u64,
+                "instructions": measurement.instructions,
+            })
+        })
+        .collect();
+    json!({ "benchmarks": benchmarks })
+}
+
+/// Reads measurements written by [`to_json`], or `None` if they're in some other shape.
+pub fn from_json(json: &serde_json::Value) -> Option<Vec<Measurement>> {
+    json.get("benchmarks")?
+        .as_array()?
+        .iter()
+        .map(|benchmark| {
+            Some(Measurement {
+                name: benchmark.get("name")?.as_str()?.to_string(),
+                runs: benchmark.get("runs")?.as_u64()? as usize,
+                mean: Duration::from_nanos(benchmark.get("mean_ns")?.as_u64()?),
+                stddev: Duration::from_nanos(benchmark.get("stddev_ns")?.as_u64()?),
+                instructions: benchmark.get("instructions")?.as_u64()?,
+            })
+        })
+        .collect()
+}
+
+/// Writes a line for each measurement. When the baseline has a benchmark with the same name, the
+/// change in its mean time is shown as well.
+pub fn report<W: io::Write>(
+    out: &mut W,
+    measurements: &[Measurement],
+    baseline: &[Measurement],
+) -> io::Result<()> {
+    for measurement in measurements {
+        write!(
+            out,
+            "{:<16} {:>10.3} ms ± {:>7.3} ms {:>12} instructions",
+            measurement.name,
+            millis(measurement.mean),
+            millis(measurement.stddev),
+            measurement.instructions
+        )?;
+        let previous = baseline
+            .iter()
+            .find(|previous| previous.name == measurement.name);
+        if let Some(previous) = previous {
+            let change = measurement.mean.as_secs_f64() / previous.mean.as_secs_f64() - 1.0;
+            write!(out, " {:>+7.1}%", change * 100.0)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bytecode::verifier::verify, test_support::compiled};
+
+    #[test]
+    fn programs_compile() {
+        for (name, source) in PROGRAMS {
+            let function = compiled(source);
+            assert_eq!(verify(&function), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn counts_instructions() {
+        // CONSTANT, PRINT, NIL, RETURN
+        let measurement = measure("print", &Rc::new(compiled("print 1;")), 2, 3).unwrap();
+        assert_eq!(measurement.name, "print");
+        assert_eq!(measurement.runs, 3);
+        assert_eq!(measurement.instructions, 4);
+    }
+
+    #[test]
+    fn runtime_error() {
+        let result = measure("error", &Rc::new(compiled("print -nil;")), 0, 1);
+        assert_eq!(result, Err(InterpreterError::OperandMustBeNumber));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let measurements = vec![Measurement {
+            name: "fib".to_string(),
+            runs: 10,
+            mean: Duration::from_micros(1500),
+            stddev: Duration::from_micros(20),
+            instructions: 12345,
+        }];
+        assert_eq!(from_json(&to_json(&measurements)), Some(measurements));
+        assert_eq!(
+            from_json(&json!({ "benchmarks": [{ "name": "fib" }] })),
+            None
+        );
+    }
+
+    #[test]
+    fn report_against_baseline() {
+        let measurement = |name: &str, millis| Measurement {
+            name: name.to_string(),
+            runs: 1,
+            mean: Duration::from_millis(millis),
+            stddev: Duration::ZERO,
+            instructions: 100,
+        };
+        let mut out = Vec::new();
+        report(
+            &mut out,
+            &[measurement("fib", 12), measurement("zoo", 5)],
+            &[measurement("fib", 10)],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "fib                  12.000 ms ±   0.000 ms          100 instructions   +20.0%\n\
+             zoo                   5.000 ms ±   0.000 ms          100 instructions\n"
+        );
+    }
+}
@@ -1,11 +1,11 @@
-use std::fmt::{Binary, Display};
+use std::{fmt::Display, rc::Rc};
 
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
-use crate::value::Value;
+use crate::{object::Function, value::Value};
 
 /// Bytecode instructions.
-#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, IntoPrimitive, TryFromPrimitive)]
 #[non_exhaustive]
 #[repr(u8)]
 pub enum OpCode {
@@ -23,6 +23,135 @@ pub enum OpCode {
     Multiply,
     /// Binary Op, applied to the two values at the top of the stack.
     Divide,
+    /// Push `nil`.
+    Nil,
+    /// Push `true`.
+    True,
+    /// Push `false`.
+    False,
+    /// Discard the value at the top of the stack.
+    Pop,
+    /// Push a local variable, whose stack slot is the next byte.
+    GetLocal,
+    /// Store the value at the top of the stack in a local variable, whose stack slot is the next
+    /// byte.
+    SetLocal,
+    /// Push a global variable, whose name is the constant at the next byte.
+    GetGlobal,
+    /// Pop the top of the stack into a new global variable, whose name is the constant at the
+    /// next byte.
+    DefineGlobal,
+    /// Store the value at the top of the stack in an existing global variable, whose name is the
+    /// constant at the next byte.
+    SetGlobal,
+    /// Push a captured variable, whose index is the next byte.
+    GetUpvalue,
+    /// Store the value at the top of the stack in a captured variable, whose index is the next
+    /// byte.
+    SetUpvalue,
+    /// Replace the instance at the top of the stack with its property, whose name is the constant
+    /// at the next byte.
+    GetProperty,
+    /// Set a property, whose name is the constant at the next byte, on the instance below the top
+    /// of the stack.
+    SetProperty,
+    /// Pop a superclass and bind its method, whose name is the constant at the next byte, to the
+    /// instance below it.
+    GetSuper,
+    /// Binary Op, applied to the two values at the top of the stack.
+    Equal,
+    /// Binary Op, applied to the two values at the top of the stack.
+    Greater,
+    /// Binary Op, applied to the two values at the top of the stack.
+    Less,
+    /// Replace the value at the top of the stack with whether it is falsey.
+    Not,
+    /// Pop and print the value at the top of the stack.
+    Print,
+    /// Jump forward by the next two bytes.
+    Jump,
+    /// Jump forward by the next two bytes if the value at the top of the stack is falsey.
+    JumpIfFalse,
+    /// Jump backward by the next two bytes.
+    Loop,
+    /// Call a value, with the number of arguments in the next byte.
+    Call,
+    /// Call a method, whose name is the constant at the next byte, with the number of arguments in
+    /// the byte after that.
+    Invoke,
+    /// Call a superclass method, whose name is the constant at the next byte, with the number of
+    /// arguments in the byte after that.
+    SuperInvoke,
+    /// Create a closure from the function constant at the next byte, followed by an
+    /// `(is_local, index)` pair of bytes for each variable it captures.
+    Closure,
+    /// Move the local variable at the top of the stack into the closures that captured it, then pop
+    /// it.
+    CloseUpvalue,
+    /// Push a new class, whose name is the constant at the next byte.
+    Class,
+    /// Copy the methods of the superclass below the top of the stack into the class at the top.
+    Inherit,
+    /// Pop a closure into a method, whose name is the constant at the next byte, of the class below
+    /// it.
+    Method,
+    /// Jump forward by the next two bytes if the value at the top of the stack is truthy.
+    JumpIfTrue,
+    /// Replace the list below the top of the stack and the index at the top with its element.
+    Index,
+}
+
+impl OpCode {
+    /// The name of the opcode in textual bytecode.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            OpCode::Return => "RETURN",
+            OpCode::Constant => "CONSTANT",
+            OpCode::Negate => "NEGATE",
+            OpCode::Add => "ADD",
+            OpCode::Subtract => "SUBTRACT",
+            OpCode::Multiply => "MULTIPLY",
+            OpCode::Divide => "DIVIDE",
+            OpCode::Nil => "NIL",
+            OpCode::True => "TRUE",
+            OpCode::False => "FALSE",
+            OpCode::Pop => "POP",
+            OpCode::GetLocal => "GET_LOCAL",
+            OpCode::SetLocal => "SET_LOCAL",
+            OpCode::GetGlobal => "GET_GLOBAL",
+            OpCode::DefineGlobal => "DEFINE_GLOBAL",
+            OpCode::SetGlobal => "SET_GLOBAL",
+            OpCode::GetUpvalue => "GET_UPVALUE",
+            OpCode::SetUpvalue => "SET_UPVALUE",
+            OpCode::GetProperty => "GET_PROPERTY",
+            OpCode::SetProperty => "SET_PROPERTY",
+            OpCode::GetSuper => "GET_SUPER",
+            OpCode::Equal => "EQUAL",
+            OpCode::Greater => "GREATER",
+            OpCode::Less => "LESS",
+            OpCode::Not => "NOT",
+            OpCode::Print => "PRINT",
+            OpCode::Jump => "JUMP",
+            OpCode::JumpIfFalse => "JUMP_IF_FALSE",
+            OpCode::Loop => "LOOP",
+            OpCode::Call => "CALL",
+            OpCode::Invoke => "INVOKE",
+            OpCode::SuperInvoke => "SUPER_INVOKE",
+            OpCode::Closure => "CLOSURE",
+            OpCode::CloseUpvalue => "CLOSE_UPVALUE",
+            OpCode::Class => "CLASS",
+            OpCode::Inherit => "INHERIT",
+            OpCode::Method => "METHOD",
+            OpCode::JumpIfTrue => "JUMP_IF_TRUE",
+            OpCode::Index => "INDEX",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<OpCode> {
+        (1..=u8::MAX)
+            .map_while(|byte| OpCode::try_from(byte).ok())
+            .find(|op| op.mnemonic() == mnemonic)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -31,29 +160,157 @@ pub enum BinaryOp {
     Subtract,
     Multiply,
     Divide,
+    Equal,
+    Greater,
+    Less,
 }
 
 impl Display for BinaryOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let op_str = match self {
-            BinaryOp::Add => '+',
-            BinaryOp::Subtract => '-',
-            BinaryOp::Multiply => '*',
-            BinaryOp::Divide => '/',
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::Greater => ">",
+            BinaryOp::Less => "<",
         };
         write!(f, "{}", op_str)
     }
 }
 
+/// How a closure captures a variable: either a local of the enclosing function, or one of the
+/// enclosing function's own captures.
 #[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capture {
+    pub is_local: bool,
+    pub index: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
     Return,
     Constant(Value),
     Negate,
     BinaryOp(BinaryOp),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    GetGlobal(Rc<str>),
+    DefineGlobal(Rc<str>),
+    SetGlobal(Rc<str>),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    GetProperty(Rc<str>),
+    SetProperty(Rc<str>),
+    GetSuper(Rc<str>),
+    Not,
+    Print,
+    Jump(u16),
+    JumpIfFalse(u16),
+    JumpIfTrue(u16),
+    Loop(u16),
+    Call(u8),
+    Invoke(Rc<str>, u8),
+    SuperInvoke(Rc<str>, u8),
+    Closure(Rc<Function>, Vec<Capture>),
+    CloseUpvalue,
+    Class(Rc<str>),
+    Inherit,
+    Method(Rc<str>),
+    Index,
+}
+
+impl Instruction {
+    pub fn op_code(&self) -> OpCode {
+        match self {
+            Instruction::Return => OpCode::Return,
+            Instruction::Constant(_) => OpCode::Constant,
+            Instruction::Negate => OpCode::Negate,
+            Instruction::BinaryOp(BinaryOp::Add) => OpCode::Add,
+            Instruction::BinaryOp(BinaryOp::Subtract) => OpCode::Subtract,
+            Instruction::BinaryOp(BinaryOp::Multiply) => OpCode::Multiply,
+            Instruction::BinaryOp(BinaryOp::Divide) => OpCode::Divide,
+            Instruction::BinaryOp(BinaryOp::Equal) => OpCode::Equal,
+            Instruction::BinaryOp(BinaryOp::Greater) => OpCode::Greater,
+            Instruction::BinaryOp(BinaryOp::Less) => OpCode::Less,
+            Instruction::Nil => OpCode::Nil,
+            Instruction::True => OpCode::True,
+            Instruction::False => OpCode::False,
+            Instruction::Pop => OpCode::Pop,
+            Instruction::GetLocal(_) => OpCode::GetLocal,
+            Instruction::SetLocal(_) => OpCode::SetLocal,
+            Instruction::GetGlobal(_) => OpCode::GetGlobal,
+            Instruction::DefineGlobal(_) => OpCode::DefineGlobal,
+            Instruction::SetGlobal(_) => OpCode::SetGlobal,
+            Instruction::GetUpvalue(_) => OpCode::GetUpvalue,
+            Instruction::SetUpvalue(_) => OpCode::SetUpvalue,
+            Instruction::GetProperty(_) => OpCode::GetProperty,
+            Instruction::SetProperty(_) => OpCode::SetProperty,
+            Instruction::GetSuper(_) => OpCode::GetSuper,
+            Instruction::Not => OpCode::Not,
+            Instruction::Print => OpCode::Print,
+            Instruction::Jump(_) => OpCode::Jump,
+            Instruction::JumpIfFalse(_) => OpCode::JumpIfFalse,
+            Instruction::JumpIfTrue(_) => OpCode::JumpIfTrue,
+            Instruction::Loop(_) => OpCode::Loop,
+            Instruction::Call(_) => OpCode::Call,
+            Instruction::Invoke(..) => OpCode::Invoke,
+            Instruction::SuperInvoke(..) => OpCode::SuperInvoke,
+            Instruction::Closure(..) => OpCode::Closure,
+            Instruction::CloseUpvalue => OpCode::CloseUpvalue,
+            Instruction::Class(_) => OpCode::Class,
+            Instruction::Inherit => OpCode::Inherit,
+            Instruction::Method(_) => OpCode::Method,
+            Instruction::Index => OpCode::Index,
+        }
+    }
+
+    /// The number of bytes the instruction takes up in a chunk, including its operands.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Return
+            | Instruction::Negate
+            | Instruction::BinaryOp(_)
+            | Instruction::Nil
+            | Instruction::True
+            | Instruction::False
+            | Instruction::Pop
+            | Instruction::Not
+            | Instruction::Print
+            | Instruction::CloseUpvalue
+            | Instruction::Inherit
+            | Instruction::Index => 1,
+            Instruction::Constant(_)
+            | Instruction::GetLocal(_)
+            | Instruction::SetLocal(_)
+            | Instruction::GetGlobal(_)
+            | Instruction::DefineGlobal(_)
+            | Instruction::SetGlobal(_)
+            | Instruction::GetUpvalue(_)
+            | Instruction::SetUpvalue(_)
+            | Instruction::GetProperty(_)
+            | Instruction::SetProperty(_)
+            | Instruction::GetSuper(_)
+            | Instruction::Call(_)
+            | Instruction::Class(_)
+            | Instruction::Method(_) => 2,
+            Instruction::Jump(_)
+            | Instruction::JumpIfFalse(_)
+            | Instruction::JumpIfTrue(_)
+            | Instruction::Loop(_)
+            | Instruction::Invoke(..)
+            | Instruction::SuperInvoke(..) => 3,
+            Instruction::Closure(_, captures) => 2 + 2 * captures.len(),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Chunk {
     pub lines: Vec<u32>,
     pub code: Vec<u8>,
@@ -72,37 +329,114 @@ impl Chunk {
     pub(crate) fn add_instruction(&mut self, line: u32, instruction: Instruction) {
         match instruction {
             Instruction::Return => self.add_op(OpCode::Return, line),
-            Instruction::Constant(value) => {
-                let constant_id = self.add_constant(value);
-                self.add_op(OpCode::Constant, line);
-                self.add_raw(constant_id, line);
-            }
+            Instruction::Constant(value) => self.add_with_constant(OpCode::Constant, value, line),
             Instruction::Negate => self.add_op(OpCode::Negate, line),
             Instruction::BinaryOp(BinaryOp::Add) => self.add_op(OpCode::Add, line),
             Instruction::BinaryOp(BinaryOp::Subtract) => self.add_op(OpCode::Subtract, line),
             Instruction::BinaryOp(BinaryOp::Multiply) => self.add_op(OpCode::Multiply, line),
             Instruction::BinaryOp(BinaryOp::Divide) => self.add_op(OpCode::Divide, line),
+            Instruction::BinaryOp(BinaryOp::Equal) => self.add_op(OpCode::Equal, line),
+            Instruction::BinaryOp(BinaryOp::Greater) => self.add_op(OpCode::Greater, line),
+            Instruction::BinaryOp(BinaryOp::Less) => self.add_op(OpCode::Less, line),
+            Instruction::Nil => self.add_op(OpCode::Nil, line),
+            Instruction::True => self.add_op(OpCode::True, line),
+            Instruction::False => self.add_op(OpCode::False, line),
+            Instruction::Pop => self.add_op(OpCode::Pop, line),
+            Instruction::GetLocal(slot) => self.add_with_byte(OpCode::GetLocal, slot, line),
+            Instruction::SetLocal(slot) => self.add_with_byte(OpCode::SetLocal, slot, line),
+            Instruction::GetGlobal(name) => self.add_with_name(OpCode::GetGlobal, name, line),
+            Instruction::DefineGlobal(name) => self.add_with_name(OpCode::DefineGlobal, name, line),
+            Instruction::SetGlobal(name) => self.add_with_name(OpCode::SetGlobal, name, line),
+            Instruction::GetUpvalue(index) => self.add_with_byte(OpCode::GetUpvalue, index, line),
+            Instruction::SetUpvalue(index) => self.add_with_byte(OpCode::SetUpvalue, index, line),
+            Instruction::GetProperty(name) => self.add_with_name(OpCode::GetProperty, name, line),
+            Instruction::SetProperty(name) => self.add_with_name(OpCode::SetProperty, name, line),
+            Instruction::GetSuper(name) => self.add_with_name(OpCode::GetSuper, name, line),
+            Instruction::Not => self.add_op(OpCode::Not, line),
+            Instruction::Print => self.add_op(OpCode::Print, line),
+            Instruction::Jump(offset) => self.add_with_short(OpCode::Jump, offset, line),
+            Instruction::JumpIfFalse(offset) => {
+                self.add_with_short(OpCode::JumpIfFalse, offset, line)
+            }
+            Instruction::JumpIfTrue(offset) => {
+                self.add_with_short(OpCode::JumpIfTrue, offset, line)
+            }
+            Instruction::Loop(offset) => self.add_with_short(OpCode::Loop, offset, line),
+            Instruction::Call(arg_count) => self.add_with_byte(OpCode::Call, arg_count, line),
+            Instruction::Invoke(name, arg_count) => {
+                self.add_with_name(OpCode::Invoke, name, line);
+                self.add_raw(arg_count, line);
+            }
+            Instruction::SuperInvoke(name, arg_count) => {
+                self.add_with_name(OpCode::SuperInvoke, name, line);
+                self.add_raw(arg_count, line);
+            }
+            Instruction::Closure(function, captures) => {
+                self.add_with_constant(OpCode::Closure, Value::from(function), line);
+                for capture in captures {
+                    self.add_raw(capture.is_local.into(), line);
+                    self.add_raw(capture.index, line);
+                }
+            }
+            Instruction::CloseUpvalue => self.add_op(OpCode::CloseUpvalue, line),
+            Instruction::Class(name) => self.add_with_name(OpCode::Class, name, line),
+            Instruction::Inherit => self.add_op(OpCode::Inherit, line),
+            Instruction::Method(name) => self.add_with_name(OpCode::Method, name, line),
+            Instruction::Index => self.add_op(OpCode::Index, line),
         }
     }
 
     pub(crate) fn add_instructions(&mut self, instructions: &[(u32, Instruction)]) {
         instructions
             .iter()
-            .for_each(|(line, instruction)| self.add_instruction(*line, *instruction));
+            .for_each(|(line, instruction)| self.add_instruction(*line, instruction.clone()));
+    }
+
+    /// Whether another constant can be added without overflowing the one byte constant index.
+    pub(crate) fn has_room_for_constant(&self) -> bool {
+        self.constants.len() <= u8::MAX as usize
     }
 
-    fn add_op(&mut self, op: OpCode, line: u32) {
+    pub(crate) fn add_op(&mut self, op: OpCode, line: u32) {
         self.add_raw(op.into(), line);
     }
 
-    fn add_raw(&mut self, code: u8, line: u32) {
+    pub(crate) fn add_raw(&mut self, code: u8, line: u32) {
         self.code.push(code);
         self.lines.push(line);
     }
 
-    fn add_constant(&mut self, constant: Value) -> u8 {
-        let index = self.constants.len();
-        self.constants.push(constant);
+    fn add_with_byte(&mut self, op: OpCode, operand: u8, line: u32) {
+        self.add_op(op, line);
+        self.add_raw(operand, line);
+    }
+
+    fn add_with_short(&mut self, op: OpCode, operand: u16, line: u32) {
+        self.add_op(op, line);
+        let [high, low] = operand.to_be_bytes();
+        self.add_raw(high, line);
+        self.add_raw(low, line);
+    }
+
+    fn add_with_constant(&mut self, op: OpCode, constant: Value, line: u32) {
+        let constant_id = self.add_constant(constant);
+        self.add_with_byte(op, constant_id, line);
+    }
+
+    fn add_with_name(&mut self, op: OpCode, name: Rc<str>, line: u32) {
+        self.add_with_constant(op, Value::from(name), line);
+    }
+
+    pub(crate) fn add_constant(&mut self, constant: Value) -> u8 {
+        // names are repeated for every access of a variable or property, so strings are shared
+        let existing = match constant.as_string() {
+            Some(_) => self.constants.iter().position(|c| *c == constant),
+            None => None,
+        };
+        let index = existing.unwrap_or_else(|| {
+            self.constants.push(constant);
+            self.constants.len() - 1
+        });
         u8::try_from(index).expect("too many constants")
     }
 }
@@ -129,8 +463,8 @@ mod tests {
     fn add_constant() {
         let mut chunk = Chunk::new();
         chunk.add_instructions(&[
-            (1, Instruction::Constant(Value(3.0))),
-            (2, Instruction::Constant(Value(1.0))),
+            (1, Instruction::Constant(Value::from(3.0))),
+            (2, Instruction::Constant(Value::from(1.0))),
         ]);
 
         let expected = Chunk {
@@ -140,7 +474,7 @@ mod tests {
                 OpCode::Constant.into(),
                 1,
             ],
-            constants: vec![Value(3.0), Value(1.0)],
+            constants: vec![Value::from(3.0), Value::from(1.0)],
             lines: vec![1, 1, 2, 2],
         };
         assert_eq!(chunk, expected);
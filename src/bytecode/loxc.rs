@@ -0,0 +1,318 @@
+use std::rc::Rc;
+
+use crate::{
+    bytecode::{core::Chunk, parser::BytecodeParseError},
+    object::Function,
+    value::{Unpacked, Value},
+};
+
+/// The first bytes of every compiled lox file.
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// Bumped whenever the layout of the file or the meaning of the bytecode changes.
+pub const VERSION: u16 = 1;
+/// The extension of compiled lox files.
+pub const EXTENSION: &str = "loxc";
+/// How deeply functions can be nested in a compiled file, so that loading a crafted file can't
+/// overflow the stack.
+pub const MAX_NESTING: usize = 256;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+/// Whether bytes are a compiled file this version can load. Source starting with the magic bytes
+/// is still source, as it won't be followed by a supported version.
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+        && bytes.get(MAGIC.len()..MAGIC.len() + 2) == Some(&VERSION.to_le_bytes()[..])
+}
+
+/// Serializes a compiled script, along with all the functions nested in it.
+///
+/// The file is the magic bytes and version, followed by the script function. A function is its
+/// optional name, arity, upvalue count, code, a line for every byte of code, and then its
+/// constants. Numbers are little endian, and strings and lists are prefixed by their length.
+pub fn serialize(function: &Function) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    write_function(&mut bytes, function);
+    bytes
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    let len = u32::try_from(len).expect("too long to serialize");
+    bytes.extend(len.to_le_bytes());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) {
+    write_len(bytes, s.len());
+    bytes.extend(s.as_bytes());
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &Function) {
+    match &function.name {
+        Some(name) => {
+            bytes.push(1);
+            write_str(bytes, name);
+        }
+        None => bytes.push(0),
+    }
+    bytes.push(function.arity);
+    bytes.push(function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_len(bytes, chunk.code.len());
+    bytes.extend(&chunk.code);
+    chunk
+        .lines
+        .iter()
+        .for_each(|line| bytes.extend(line.to_le_bytes()));
+
+    write_len(bytes, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant.unpack() {
+            Unpacked::Nil => bytes.push(TAG_NIL),
+            Unpacked::Bool(false) => bytes.push(TAG_FALSE),
+            Unpacked::Bool(true) => bytes.push(TAG_TRUE),
+            Unpacked::Number(n) => {
+                bytes.push(TAG_NUMBER);
+                bytes.extend(n.to_le_bytes());
+            }
+            Unpacked::String(s) => {
+                bytes.push(TAG_STRING);
+                write_str(bytes, &s);
+            }
+            Unpacked::Function(function) => {
+                bytes.push(TAG_FUNCTION);
+                write_function(bytes, &function);
+            }
+            _ => panic!("runtime objects can't be constants: {}", constant),
+        }
+    }
+}
+
+/// Loads a compiled script, checking that all of its bytecode can be parsed.
+pub fn deserialize(bytes: &[u8]) -> Result<Function, BytecodeParseError> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        depth: 0,
+    };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(BytecodeParseError::NotCompiled);
+    }
+    let version = u16::from_le_bytes(reader.array()?);
+    if version != VERSION {
+        return Err(BytecodeParseError::UnsupportedVersion(version));
+    }
+
+    let function = reader.function()?;
+    if reader.pos != bytes.len() {
+        return Err(BytecodeParseError::TrailingData(reader.pos));
+    }
+    Ok(function)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// how many functions the one being read is nested in
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeParseError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(BytecodeParseError::UnexpectedEndOfFile)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeParseError> {
+        let bytes = self.take(N)?;
+        Ok(bytes.try_into().expect("took N bytes"))
+    }
+
+    fn byte(&mut self) -> Result<u8, BytecodeParseError> {
+        let [byte] = self.array()?;
+        Ok(byte)
+    }
+
+    fn len(&mut self) -> Result<usize, BytecodeParseError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<Rc<str>, BytecodeParseError> {
+        let len = self.len()?;
+        let pos = self.pos;
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes)
+            .map(Rc::from)
+            .map_err(|_| BytecodeParseError::InvalidString(pos))
+    }
+
+    fn function(&mut self) -> Result<Function, BytecodeParseError> {
+        let name = match self.byte()? {
+            0 => None,
+            _ => Some(self.string()?),
+        };
+        let arity = self.byte()?;
+        let upvalue_count = self.byte()?;
+
+        let code_len = self.len()?;
+        let code = self.take(code_len)?.to_vec();
+        let lines = (0..code_len)
+            .map(|_| Ok(u32::from_le_bytes(self.array()?)))
+            .collect::<Result<_, _>>()?;
+
+        let constant_count = self.len()?;
+        let constants = (0..constant_count)
+            .map(|_| self.constant())
+            .collect::<Result<_, _>>()?;
+
+        let function = Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk {
+                lines,
+                code,
+                constants,
+            },
+        };
+        // surface bad bytecode now, rather than when it's run
+        for (_, parsed) in function.chunk.iter() {
+            parsed?;
+        }
+        Ok(function)
+    }
+
+    fn constant(&mut self) -> Result<Value, BytecodeParseError> {
+        let pos = self.pos;
+        Ok(match self.byte()? {
+            TAG_NIL => Value::NIL,
+            TAG_FALSE => Value::from(false),
+            TAG_TRUE => Value::from(true),
+            TAG_NUMBER => Value::from(f64::from_le_bytes(self.array()?)),
+            TAG_STRING => Value::from(self.string()?),
+            TAG_FUNCTION => {
+                if self.depth == MAX_NESTING {
+                    return Err(BytecodeParseError::TooDeeplyNested(pos));
+                }
+                self.depth += 1;
+                let function = self.function()?;
+                self.depth -= 1;
+                Value::from(Rc::new(function))
+            }
+            _ => return Err(BytecodeParseError::UnknownConstantTag(pos)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bytecode::core::OpCode, test_support::compiled};
+
+    #[test]
+    fn round_trip() {
+        let function = compiled(
+            "
+            var a = nil;
+            fun f(b, c) { fun g() { return b; } return \"s\" + c; }
+            print f(true, 1.5) == false;
+            ",
+        );
+
+        let bytes = serialize(&function);
+        assert!(is_compiled(&bytes));
+        assert_eq!(deserialize(&bytes), Ok(function));
+    }
+
+    #[test]
+    fn not_compiled() {
+        assert_eq!(
+            deserialize(b"print 1;"),
+            Err(BytecodeParseError::NotCompiled)
+        );
+        assert!(!is_compiled(b"LOXC = 1; print LOXC;"));
+        assert!(!is_compiled(b"LOXC"));
+    }
+
+    #[test]
+    fn nesting() {
+        // a script with a function constant, nested in the function constant of another...
+        let nested = |depth: usize| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(VERSION.to_le_bytes());
+            for _ in 0..=depth {
+                bytes.extend([0, 0, 0]);
+                bytes.extend(0u32.to_le_bytes());
+                bytes.extend(1u32.to_le_bytes());
+                bytes.push(TAG_FUNCTION);
+            }
+            bytes.truncate(bytes.len() - 5);
+            bytes.extend(0u32.to_le_bytes());
+            bytes
+        };
+        assert!(deserialize(&nested(MAX_NESTING)).is_ok());
+        assert!(matches!(
+            deserialize(&nested(MAX_NESTING + 1)),
+            Err(BytecodeParseError::TooDeeplyNested(_))
+        ));
+        assert!(matches!(
+            deserialize(&nested(1_000_000)),
+            Err(BytecodeParseError::TooDeeplyNested(_))
+        ));
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut bytes = serialize(&compiled("print 1;"));
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            deserialize(&bytes),
+            Err(BytecodeParseError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = serialize(&compiled("print 1;"));
+        for len in 0..bytes.len() {
+            assert!(deserialize(&bytes[..len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut bytes = serialize(&compiled("print 1;"));
+        let len = bytes.len();
+        bytes.push(0);
+        assert_eq!(
+            deserialize(&bytes),
+            Err(BytecodeParseError::TrailingData(len))
+        );
+    }
+
+    #[test]
+    fn invalid_bytecode() {
+        let function = Function {
+            chunk: Chunk {
+                code: vec![OpCode::Constant.into(), 0],
+                lines: vec![1, 1],
+                constants: vec![],
+            },
+            ..Function::new(None)
+        };
+        assert_eq!(
+            deserialize(&serialize(&function)),
+            Err(BytecodeParseError::InvalidConstantIndex(0))
+        );
+    }
+}
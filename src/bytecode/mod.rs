@@ -1,2 +1,5 @@
 pub mod core;
+pub mod loxc;
 pub mod parser;
+pub mod peephole;
+pub mod verifier;
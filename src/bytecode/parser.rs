@@ -1,9 +1,14 @@
-use std::fmt::Display;
-use std::iter::FusedIterator;
+use std::{iter::FusedIterator, rc::Rc};
 
 use thiserror::Error;
 
-use crate::bytecode::core::{BinaryOp, Chunk, Instruction, OpCode};
+use crate::{
+    bytecode::{
+        core::{BinaryOp, Capture, Chunk, Instruction, OpCode},
+        loxc,
+    },
+    value::{Unpacked, Value},
+};
 
 pub struct InstructionMetadata {
     pub line: u32,
@@ -11,7 +16,7 @@ pub struct InstructionMetadata {
 }
 
 impl InstructionMetadata {
-    fn new(pos: usize, line: u32) -> InstructionMetadata {
+    pub(crate) fn new(pos: usize, line: u32) -> InstructionMetadata {
         InstructionMetadata { pos, line }
     }
 }
@@ -24,6 +29,29 @@ pub enum BytecodeParseError {
     InvalidConstantIndex(u8),
     #[error("unexpected end of bytecode when parsing {0:?} operands")]
     UnexpectedEndOfBytecode(OpCode),
+    #[error("{0:?} expects constant {1} to be a string")]
+    ExpectedString(OpCode, u8),
+    #[error("{0:?} expects constant {1} to be a function")]
+    ExpectedFunction(OpCode, u8),
+    #[error("jump at {0} doesn't land on an instruction")]
+    InvalidJumpTarget(usize),
+    #[error("not a compiled lox file")]
+    NotCompiled,
+    #[error(
+        "compiled for bytecode version {0}, but only version {} is supported",
+        loxc::VERSION
+    )]
+    UnsupportedVersion(u16),
+    #[error("unexpected end of compiled file")]
+    UnexpectedEndOfFile,
+    #[error("unexpected data at byte {0} after the end of the compiled file")]
+    TrailingData(usize),
+    #[error("invalid utf-8 in string at byte {0}")]
+    InvalidString(usize),
+    #[error("unknown constant type at byte {0}")]
+    UnknownConstantTag(usize),
+    #[error("function at byte {0} is nested more than {} deep", loxc::MAX_NESTING)]
+    TooDeeplyNested(usize),
 }
 
 pub struct BytecodeParser<'a> {
@@ -32,19 +60,113 @@ pub struct BytecodeParser<'a> {
 }
 
 impl<'a> BytecodeParser<'a> {
-    fn read_constant(&mut self) -> Result<Instruction, BytecodeParseError> {
+    pub(crate) fn read_byte(&mut self, op: OpCode) -> Result<u8, BytecodeParseError> {
+        let byte = self.chunk.code.get(self.pos).copied();
         self.pos += 1;
-        if let Some(&constant_id) = self.chunk.code.get(self.pos - 1) {
-            if let Some(&constant) = self.chunk.constants.get(constant_id as usize) {
-                Ok(Instruction::Constant(constant))
-            } else {
-                Err(BytecodeParseError::InvalidConstantIndex(constant_id))
+        byte.ok_or(BytecodeParseError::UnexpectedEndOfBytecode(op))
+    }
+
+    pub(crate) fn read_short(&mut self, op: OpCode) -> Result<u16, BytecodeParseError> {
+        let high = self.read_byte(op)?;
+        let low = self.read_byte(op)?;
+        Ok(u16::from_be_bytes([high, low]))
+    }
+
+    pub(crate) fn read_constant_at(
+        &mut self,
+        op: OpCode,
+    ) -> Result<(u8, &'a Value), BytecodeParseError> {
+        let constant_id = self.read_byte(op)?;
+        self.chunk
+            .constants
+            .get(constant_id as usize)
+            .map(|constant| (constant_id, constant))
+            .ok_or(BytecodeParseError::InvalidConstantIndex(constant_id))
+    }
+
+    fn read_constant(&mut self) -> Result<Instruction, BytecodeParseError> {
+        let (_, constant) = self.read_constant_at(OpCode::Constant)?;
+        Ok(Instruction::Constant(constant.clone()))
+    }
+
+    fn read_name(&mut self, op: OpCode) -> Result<Rc<str>, BytecodeParseError> {
+        self.read_string(op).cloned()
+    }
+
+    /// Reads a string constant, without cloning it.
+    pub(crate) fn read_string(&mut self, op: OpCode) -> Result<&'a Rc<str>, BytecodeParseError> {
+        let (constant_id, constant) = self.read_constant_at(op)?;
+        constant
+            .as_string()
+            .ok_or(BytecodeParseError::ExpectedString(op, constant_id))
+    }
+
+    fn read_closure(&mut self) -> Result<Instruction, BytecodeParseError> {
+        let (constant_id, constant) = self.read_constant_at(OpCode::Closure)?;
+        let function = match constant.unpack() {
+            Unpacked::Function(function) => function,
+            _ => {
+                return Err(BytecodeParseError::ExpectedFunction(
+                    OpCode::Closure,
+                    constant_id,
+                ))
             }
-        } else {
-            Err(BytecodeParseError::UnexpectedEndOfBytecode(
-                OpCode::Constant,
-            ))
-        }
+        };
+        let captures = (0..function.upvalue_count)
+            .map(|_| {
+                Ok(Capture {
+                    is_local: self.read_byte(OpCode::Closure)? != 0,
+                    index: self.read_byte(OpCode::Closure)?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Instruction::Closure(function, captures))
+    }
+
+    fn read_instruction(&mut self, op: OpCode) -> Result<Instruction, BytecodeParseError> {
+        Ok(match op {
+            OpCode::Return => Instruction::Return,
+            OpCode::Constant => self.read_constant()?,
+            OpCode::Negate => Instruction::Negate,
+            OpCode::Add => Instruction::BinaryOp(BinaryOp::Add),
+            OpCode::Subtract => Instruction::BinaryOp(BinaryOp::Subtract),
+            OpCode::Multiply => Instruction::BinaryOp(BinaryOp::Multiply),
+            OpCode::Divide => Instruction::BinaryOp(BinaryOp::Divide),
+            OpCode::Nil => Instruction::Nil,
+            OpCode::True => Instruction::True,
+            OpCode::False => Instruction::False,
+            OpCode::Pop => Instruction::Pop,
+            OpCode::GetLocal => Instruction::GetLocal(self.read_byte(op)?),
+            OpCode::SetLocal => Instruction::SetLocal(self.read_byte(op)?),
+            OpCode::GetGlobal => Instruction::GetGlobal(self.read_name(op)?),
+            OpCode::DefineGlobal => Instruction::DefineGlobal(self.read_name(op)?),
+            OpCode::SetGlobal => Instruction::SetGlobal(self.read_name(op)?),
+            OpCode::GetUpvalue => Instruction::GetUpvalue(self.read_byte(op)?),
+            OpCode::SetUpvalue => Instruction::SetUpvalue(self.read_byte(op)?),
+            OpCode::GetProperty => Instruction::GetProperty(self.read_name(op)?),
+            OpCode::SetProperty => Instruction::SetProperty(self.read_name(op)?),
+            OpCode::GetSuper => Instruction::GetSuper(self.read_name(op)?),
+            OpCode::Equal => Instruction::BinaryOp(BinaryOp::Equal),
+            OpCode::Greater => Instruction::BinaryOp(BinaryOp::Greater),
+            OpCode::Less => Instruction::BinaryOp(BinaryOp::Less),
+            OpCode::Not => Instruction::Not,
+            OpCode::Print => Instruction::Print,
+            OpCode::Jump => Instruction::Jump(self.read_short(op)?),
+            OpCode::JumpIfFalse => Instruction::JumpIfFalse(self.read_short(op)?),
+            OpCode::Loop => Instruction::Loop(self.read_short(op)?),
+            OpCode::Call => Instruction::Call(self.read_byte(op)?),
+            OpCode::Invoke => Instruction::Invoke(self.read_name(op)?, self.read_byte(op)?),
+            OpCode::SuperInvoke => {
+                Instruction::SuperInvoke(self.read_name(op)?, self.read_byte(op)?)
+            }
+            OpCode::Closure => self.read_closure()?,
+            OpCode::CloseUpvalue => Instruction::CloseUpvalue,
+            OpCode::Class => Instruction::Class(self.read_name(op)?),
+            OpCode::Inherit => Instruction::Inherit,
+            OpCode::Method => Instruction::Method(self.read_name(op)?),
+            OpCode::JumpIfTrue => Instruction::JumpIfTrue(self.read_short(op)?),
+            OpCode::Index => Instruction::Index,
+        })
     }
 }
 
@@ -60,15 +182,7 @@ impl<'a> Iterator for BytecodeParser<'a> {
         if let Some((&code, &line)) = code.zip(line) {
             let metadata = InstructionMetadata::new(pos, line);
             let instruction = match OpCode::try_from(code) {
-                Ok(op) => match op {
-                    OpCode::Return => Ok(Instruction::Return),
-                    OpCode::Constant => self.read_constant(),
-                    OpCode::Negate => Ok(Instruction::Negate),
-                    OpCode::Add => Ok(Instruction::BinaryOp(BinaryOp::Add)),
-                    OpCode::Subtract => Ok(Instruction::BinaryOp(BinaryOp::Subtract)),
-                    OpCode::Multiply => Ok(Instruction::BinaryOp(BinaryOp::Multiply)),
-                    OpCode::Divide => Ok(Instruction::BinaryOp(BinaryOp::Divide)),
-                },
+                Ok(op) => self.read_instruction(op),
                 Err(_) => Err(BytecodeParseError::UnknownInstruction(code)),
             };
             // read opcode
@@ -96,7 +210,7 @@ impl<'a> IntoIterator for &'a Chunk {
 
     fn into_iter(self) -> Self::IntoIter {
         BytecodeParser {
-            chunk: &self,
+            chunk: self,
             pos: 0,
         }
     }
@@ -112,9 +226,9 @@ mod tests {
         let mut chunk = Chunk::new();
 
         let instructions = vec![
-            (1, Instruction::Constant(Value(3.0))),
+            (1, Instruction::Constant(Value::from(3.0))),
             (2, Instruction::Return),
-            (3, Instruction::Constant(Value(2.0))),
+            (3, Instruction::Constant(Value::from(2.0))),
             (3, Instruction::Return),
         ];
         chunk.add_instructions(&instructions);
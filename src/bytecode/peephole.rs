@@ -0,0 +1,454 @@
+use std::{collections::HashSet, rc::Rc};
+
+use crate::{
+    bytecode::{
+        core::{Chunk, Instruction},
+        parser::BytecodeParseError,
+    },
+    object::Function,
+};
+
+/// When a jump is taken.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    Always,
+    IfFalse,
+    IfTrue,
+}
+
+impl Condition {
+    fn negated(self) -> Condition {
+        match self {
+            Condition::Always => Condition::Always,
+            Condition::IfFalse => Condition::IfTrue,
+            Condition::IfTrue => Condition::IfFalse,
+        }
+    }
+}
+
+/// An instruction, where jumps refer to the index of the instruction they land on rather than a
+/// byte offset. This way instructions can be added and removed without breaking the jumps over
+/// them.
+#[derive(Debug, Clone, PartialEq)]
+enum Op {
+    Plain(Instruction),
+    Jump(Condition, usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    line: u32,
+    op: Op,
+}
+
+/// Rewrites common instruction sequences in a function and all the functions nested in it.
+pub fn optimize_function(function: &Function) -> Result<Function, BytecodeParseError> {
+    Ok(Function {
+        name: function.name.clone(),
+        arity: function.arity,
+        upvalue_count: function.upvalue_count,
+        chunk: optimize(&function.chunk)?,
+    })
+}
+
+/// Rewrites common instruction sequences in a chunk:
+/// - a negated number constant is replaced with the negative number
+/// - `Not` followed by a conditional jump is replaced with the opposite jump, when both branches
+///   pop the condition
+/// - jumps to unconditional jumps go straight to the final target, and jumps to the next
+///   instruction are removed
+/// - unreachable code after a `Return` or unconditional jump is removed
+pub fn optimize(chunk: &Chunk) -> Result<Chunk, BytecodeParseError> {
+    let mut steps = decode(chunk)?;
+    while rewrite(&mut steps) {}
+    // threading can make a jump too long for its offset, in which case it's safest to do nothing
+    Ok(encode(&steps).unwrap_or_else(|| chunk.clone()))
+}
+
+fn decode(chunk: &Chunk) -> Result<Vec<Step>, BytecodeParseError> {
+    let mut decoded = Vec::new();
+    for (metadata, parsed) in chunk {
+        decoded.push((metadata.pos, metadata.line, parsed?));
+    }
+    let positions: Vec<_> = decoded.iter().map(|(pos, _, _)| *pos).collect();
+    let index_of = |target: usize| {
+        if target == chunk.code.len() {
+            Some(positions.len())
+        } else {
+            positions.binary_search(&target).ok()
+        }
+    };
+
+    decoded
+        .into_iter()
+        .map(|(pos, line, instruction)| {
+            let after = pos + 3;
+            let (condition, target) = match instruction {
+                Instruction::Jump(offset) => (Condition::Always, Some(after + offset as usize)),
+                Instruction::JumpIfFalse(offset) => {
+                    (Condition::IfFalse, Some(after + offset as usize))
+                }
+                Instruction::JumpIfTrue(offset) => {
+                    (Condition::IfTrue, Some(after + offset as usize))
+                }
+                Instruction::Loop(offset) => {
+                    (Condition::Always, after.checked_sub(offset as usize))
+                }
+                Instruction::Closure(function, captures) => {
+                    let function = Rc::new(optimize_function(&function)?);
+                    let op = Op::Plain(Instruction::Closure(function, captures));
+                    return Ok(Step { line, op });
+                }
+                instruction => {
+                    let op = Op::Plain(instruction);
+                    return Ok(Step { line, op });
+                }
+            };
+            let target = target
+                .and_then(index_of)
+                .ok_or(BytecodeParseError::InvalidJumpTarget(pos))?;
+            Ok(Step {
+                line,
+                op: Op::Jump(condition, target),
+            })
+        })
+        .collect()
+}
+
+fn encode(steps: &[Step]) -> Option<Chunk> {
+    let mut positions = Vec::with_capacity(steps.len() + 1);
+    let mut pos = 0;
+    for step in steps {
+        positions.push(pos);
+        pos += match &step.op {
+            Op::Plain(instruction) => instruction.size(),
+            Op::Jump(..) => 3,
+        };
+    }
+    positions.push(pos);
+
+    let mut instructions = Vec::with_capacity(steps.len());
+    for (i, step) in steps.iter().enumerate() {
+        let instruction = match &step.op {
+            Op::Plain(instruction) => instruction.clone(),
+            Op::Jump(condition, target) => {
+                let after = positions[i] + 3;
+                let to = positions[*target];
+                if to >= after {
+                    let offset = u16::try_from(to - after).ok()?;
+                    match condition {
+                        Condition::Always => Instruction::Jump(offset),
+                        Condition::IfFalse => Instruction::JumpIfFalse(offset),
+                        Condition::IfTrue => Instruction::JumpIfTrue(offset),
+                    }
+                } else if *condition == Condition::Always {
+                    Instruction::Loop(u16::try_from(after - to).ok()?)
+                } else {
+                    // only unconditional jumps can go backwards
+                    return None;
+                }
+            }
+        };
+        instructions.push((step.line, instruction));
+    }
+
+    let mut chunk = Chunk::new();
+    chunk.add_instructions(&instructions);
+    Some(chunk)
+}
+
+fn is_pop(steps: &[Step], index: usize) -> bool {
+    matches!(
+        steps.get(index),
+        Some(Step {
+            op: Op::Plain(Instruction::Pop),
+            ..
+        })
+    )
+}
+
+/// Follows a jump through any jumps it lands on that would always be taken next.
+fn thread(steps: &[Step], from: usize, condition: Condition, mut target: usize) -> usize {
+    for _ in 0..steps.len() {
+        match steps.get(target) {
+            Some(Step {
+                op: Op::Jump(next_condition, next_target),
+                ..
+            }) if (*next_condition == Condition::Always || *next_condition == condition)
+                && (condition == Condition::Always || *next_target > from) =>
+            {
+                target = *next_target
+            }
+            _ => break,
+        }
+    }
+    target
+}
+
+/// Applies one round of rewrites, returning whether anything changed.
+fn rewrite(steps: &mut Vec<Step>) -> bool {
+    let targets: HashSet<usize> = steps
+        .iter()
+        .filter_map(|step| match step.op {
+            Op::Jump(_, target) => Some(target),
+            Op::Plain(_) => None,
+        })
+        .collect();
+    let mut removed = vec![false; steps.len()];
+    let mut changed = false;
+
+    for i in 0..steps.len() {
+        if let Op::Jump(condition, target) = steps[i].op {
+            let threaded = thread(steps, i, condition, target);
+            if threaded != target {
+                steps[i].op = Op::Jump(condition, threaded);
+                changed = true;
+            }
+            if condition == Condition::Always && threaded == i + 1 {
+                removed[i] = true;
+                changed = true;
+            }
+        }
+
+        // the rewrites below merge an instruction into the next one, which can't be done when
+        // something jumps between them
+        let next = i + 1;
+        if removed[i] || next >= steps.len() || targets.contains(&next) {
+            continue;
+        }
+        match (&steps[i].op, &steps[next].op) {
+            (Op::Plain(Instruction::Constant(constant)), Op::Plain(Instruction::Negate))
+                if constant.as_number().is_some() =>
+            {
+                let negated = constant.clone().negate().expect("negating a number");
+                steps[i].op = Op::Plain(Instruction::Constant(negated));
+                removed[next] = true;
+                changed = true;
+            }
+            (Op::Plain(Instruction::Not), &Op::Jump(condition, target))
+                if condition != Condition::Always
+                    && is_pop(steps, next + 1)
+                    && is_pop(steps, target) =>
+            {
+                steps[next].op = Op::Jump(condition.negated(), target);
+                removed[i] = true;
+                changed = true;
+            }
+            _ => (),
+        }
+    }
+
+    // nothing after a return or unconditional jump runs until something jumps there
+    let mut reachable = true;
+    for (i, step) in steps.iter().enumerate() {
+        if targets.contains(&i) {
+            reachable = true;
+        }
+        if removed[i] {
+            continue;
+        }
+        if !reachable {
+            removed[i] = true;
+            changed = true;
+        } else if matches!(
+            step.op,
+            Op::Plain(Instruction::Return) | Op::Jump(Condition::Always, _)
+        ) {
+            reachable = false;
+        }
+    }
+
+    if changed {
+        remove(steps, &removed);
+    }
+    changed
+}
+
+/// Removes the marked steps. Jumps to a removed step land on the step after it instead.
+fn remove(steps: &mut Vec<Step>, removed: &[bool]) {
+    let mut remapped = Vec::with_capacity(steps.len() + 1);
+    let mut kept = 0;
+    for &removed in removed {
+        remapped.push(kept);
+        if !removed {
+            kept += 1;
+        }
+    }
+    remapped.push(kept);
+
+    let mut removed = removed.iter();
+    steps.retain(|_| !removed.next().expect("a flag for every step"));
+    for step in steps.iter_mut() {
+        if let Op::Jump(_, target) = &mut step.op {
+            *target = remapped[*target];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    fn chunk(instructions: &[(u32, Instruction)]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.add_instructions(instructions);
+        chunk
+    }
+
+    #[test]
+    fn negate_constant() {
+        let original = chunk(&[
+            (1, Instruction::Constant(Value::from(2.0))),
+            (1, Instruction::Negate),
+            (2, Instruction::Return),
+        ]);
+
+        let expected = chunk(&[
+            (1, Instruction::Constant(Value::from(-2.0))),
+            (2, Instruction::Return),
+        ]);
+        assert_eq!(optimize(&original), Ok(expected));
+    }
+
+    #[test]
+    fn negate_jump_target() {
+        let original = chunk(&[
+            (1, Instruction::Constant(Value::from(2.0))),
+            (1, Instruction::JumpIfFalse(0)),
+            (1, Instruction::Negate),
+            (2, Instruction::Return),
+        ]);
+
+        assert_eq!(optimize(&original), Ok(original));
+    }
+
+    #[test]
+    fn not_jump_if_false() {
+        // if (!x) print 1;
+        let original = chunk(&[
+            (1, Instruction::GetGlobal(Rc::from("x"))),
+            (1, Instruction::Not),
+            (1, Instruction::JumpIfFalse(7)),
+            (1, Instruction::Pop),
+            (1, Instruction::Constant(Value::from(1.0))),
+            (1, Instruction::Print),
+            (1, Instruction::Jump(1)),
+            (1, Instruction::Pop),
+            (2, Instruction::Nil),
+            (2, Instruction::Return),
+        ]);
+
+        let expected = chunk(&[
+            (1, Instruction::GetGlobal(Rc::from("x"))),
+            (1, Instruction::JumpIfTrue(7)),
+            (1, Instruction::Pop),
+            (1, Instruction::Constant(Value::from(1.0))),
+            (1, Instruction::Print),
+            (1, Instruction::Jump(1)),
+            (1, Instruction::Pop),
+            (2, Instruction::Nil),
+            (2, Instruction::Return),
+        ]);
+        assert_eq!(optimize(&original), Ok(expected));
+    }
+
+    #[test]
+    fn not_jump_keeps_condition() {
+        // print !x and y; leaves the negated condition on the stack when it short circuits
+        let original = chunk(&[
+            (1, Instruction::GetGlobal(Rc::from("x"))),
+            (1, Instruction::Not),
+            (1, Instruction::JumpIfFalse(3)),
+            (1, Instruction::Pop),
+            (1, Instruction::GetGlobal(Rc::from("y"))),
+            (1, Instruction::Print),
+            (2, Instruction::Nil),
+            (2, Instruction::Return),
+        ]);
+
+        assert_eq!(optimize(&original), Ok(original));
+    }
+
+    #[test]
+    fn dead_code_after_return() {
+        let original = chunk(&[
+            (1, Instruction::Constant(Value::from(1.0))),
+            (1, Instruction::Return),
+            (2, Instruction::Nil),
+            (2, Instruction::Return),
+        ]);
+
+        let expected = chunk(&[
+            (1, Instruction::Constant(Value::from(1.0))),
+            (1, Instruction::Return),
+        ]);
+        assert_eq!(optimize(&original), Ok(expected));
+    }
+
+    #[test]
+    fn thread_jumps() {
+        let original = chunk(&[
+            (1, Instruction::True),
+            (1, Instruction::JumpIfFalse(5)), // to the second jump
+            (2, Instruction::Pop),
+            (2, Instruction::Nil),
+            (2, Instruction::Jump(3)), // to the pop
+            (3, Instruction::Jump(1)), // to the nil
+            (3, Instruction::Pop),
+            (4, Instruction::Nil),
+            (4, Instruction::Return),
+        ]);
+
+        // the second jump is no longer reachable, so the first jump lands right after itself
+        let expected = chunk(&[
+            (1, Instruction::True),
+            (1, Instruction::JumpIfFalse(3)), // to the nil
+            (2, Instruction::Pop),
+            (2, Instruction::Nil),
+            (3, Instruction::Pop),
+            (4, Instruction::Nil),
+            (4, Instruction::Return),
+        ]);
+        assert_eq!(optimize(&original), Ok(expected));
+    }
+
+    #[test]
+    fn thread_jump_to_loop() {
+        let original = chunk(&[
+            (1, Instruction::Nil),
+            (1, Instruction::JumpIfFalse(4)), // to the second pop
+            (2, Instruction::Pop),
+            (2, Instruction::Jump(3)), // to the loop
+            (3, Instruction::Pop),
+            (3, Instruction::Nil),
+            (3, Instruction::Return),
+            (4, Instruction::Loop(14)), // to the start
+        ]);
+
+        let expected = chunk(&[
+            (1, Instruction::Nil),
+            (1, Instruction::JumpIfFalse(4)),
+            (2, Instruction::Pop),
+            (2, Instruction::Loop(8)),
+            (3, Instruction::Pop),
+            (3, Instruction::Nil),
+            (3, Instruction::Return),
+        ]);
+        assert_eq!(optimize(&original), Ok(expected));
+    }
+
+    #[test]
+    fn invalid_jump() {
+        let original = chunk(&[
+            (1, Instruction::Constant(Value::from(1.0))),
+            (1, Instruction::Jump(2)),
+            (1, Instruction::Return),
+        ]);
+
+        assert_eq!(
+            optimize(&original),
+            Err(BytecodeParseError::InvalidJumpTarget(2))
+        );
+    }
+}
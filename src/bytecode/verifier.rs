@@ -0,0 +1,386 @@
+use std::collections::{HashMap, HashSet};
+
+use thiserror::Error;
+
+use crate::{
+    bytecode::{core::Instruction, parser::BytecodeParseError},
+    object::Function,
+};
+
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{kind} at {pos} in {function}")]
+pub struct VerifyError {
+    pub function: String,
+    pub pos: usize,
+    pub kind: VerifyErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum VerifyErrorKind {
+    #[error("{0}")]
+    ParseError(BytecodeParseError),
+    #[error("jump lands outside of the chunk")]
+    JumpOutOfBounds,
+    #[error("jump lands in the middle of an instruction")]
+    JumpIntoInstruction,
+    #[error("needs {needed} values on the stack, but there are only {available}")]
+    StackUnderflow { needed: usize, available: usize },
+    #[error("stack depth is {expected} on one path but {found} on another")]
+    StackMismatch { expected: usize, found: usize },
+    #[error("invalid local slot ({0})")]
+    InvalidLocal(u8),
+    #[error("invalid upvalue index ({0})")]
+    InvalidUpvalue(u8),
+    #[error("runs off the end of the chunk without returning")]
+    MissingReturn,
+}
+
+/// Statically checks that a function, and all the functions nested in it, can run without
+/// corrupting the VM: every instruction can be parsed, every jump lands on an instruction, the
+/// stack never underflows and has the same depth on every path to an instruction, locals and
+/// upvalues exist, and every path ends by returning.
+pub fn verify(function: &Function) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    verify_function(function, &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The stack depth on entry to each reachable instruction in a function's chunk, counting the
+/// called value in slot zero.
+pub(crate) fn stack_depths(function: &Function) -> HashMap<usize, usize> {
+    check_paths(function, &mut Vec::new())
+}
+
+fn verify_function(function: &Function, errors: &mut Vec<VerifyError>) {
+    check_paths(function, errors);
+    for constant in &function.chunk.constants {
+        if let Some(function) = constant.as_function() {
+            verify_function(function, errors);
+        }
+    }
+}
+
+fn check_paths(function: &Function, errors: &mut Vec<VerifyError>) -> HashMap<usize, usize> {
+    let name = function.to_string();
+    let mut error = |pos, kind| {
+        errors.push(VerifyError {
+            function: name.clone(),
+            pos,
+            kind,
+        })
+    };
+    let chunk = &function.chunk;
+
+    let mut starts = HashSet::new();
+    let mut instructions = HashMap::new();
+    for (metadata, parsed) in chunk.iter() {
+        starts.insert(metadata.pos);
+        match parsed {
+            Ok(instruction) => {
+                instructions.insert(metadata.pos, instruction);
+            }
+            Err(e) => error(metadata.pos, VerifyErrorKind::ParseError(e)),
+        }
+    }
+
+    let mut depths: HashMap<usize, usize> = HashMap::new();
+    let mut pending = vec![(0, function.arity as usize + 1)];
+    if chunk.code.is_empty() {
+        error(0, VerifyErrorKind::MissingReturn);
+        pending.clear();
+    }
+
+    while let Some((pos, depth)) = pending.pop() {
+        if let Some(&expected) = depths.get(&pos) {
+            if expected != depth {
+                let kind = VerifyErrorKind::StackMismatch {
+                    expected,
+                    found: depth,
+                };
+                error(pos, kind);
+            }
+            continue;
+        }
+        depths.insert(pos, depth);
+        let instruction = match instructions.get(&pos) {
+            Some(instruction) => instruction,
+            // already reported as a parse error
+            None => continue,
+        };
+
+        let (needed, pushed) = stack_effect(instruction);
+        if depth < needed + 1 {
+            let kind = VerifyErrorKind::StackUnderflow {
+                needed,
+                available: depth - 1,
+            };
+            error(pos, kind);
+            continue;
+        }
+        let after = depth - needed + pushed;
+
+        let upvalue_count = function.upvalue_count;
+        match instruction {
+            Instruction::GetLocal(slot) | Instruction::SetLocal(slot)
+                if *slot as usize >= depth =>
+            {
+                error(pos, VerifyErrorKind::InvalidLocal(*slot))
+            }
+            Instruction::GetUpvalue(index) | Instruction::SetUpvalue(index)
+                if *index >= upvalue_count =>
+            {
+                error(pos, VerifyErrorKind::InvalidUpvalue(*index))
+            }
+            Instruction::Closure(_, captures) => {
+                for capture in captures {
+                    if capture.is_local && capture.index as usize >= depth {
+                        error(pos, VerifyErrorKind::InvalidLocal(capture.index));
+                    } else if !capture.is_local && capture.index >= upvalue_count {
+                        error(pos, VerifyErrorKind::InvalidUpvalue(capture.index));
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        let next = pos + instruction.size();
+        let targets = match instruction {
+            Instruction::Return => vec![],
+            Instruction::Jump(offset) => vec![Some(next + *offset as usize)],
+            Instruction::JumpIfFalse(offset) | Instruction::JumpIfTrue(offset) => {
+                vec![Some(next), Some(next + *offset as usize)]
+            }
+            Instruction::Loop(offset) => vec![next.checked_sub(*offset as usize)],
+            _ => vec![Some(next)],
+        };
+        for target in targets {
+            match target {
+                Some(target) if target == chunk.code.len() => {
+                    error(pos, VerifyErrorKind::MissingReturn)
+                }
+                Some(target) if target > chunk.code.len() => {
+                    error(pos, VerifyErrorKind::JumpOutOfBounds)
+                }
+                None => error(pos, VerifyErrorKind::JumpOutOfBounds),
+                Some(target) if !starts.contains(&target) => {
+                    error(pos, VerifyErrorKind::JumpIntoInstruction)
+                }
+                Some(target) => pending.push((target, after)),
+            }
+        }
+    }
+    depths
+}
+
+/// The number of values an instruction needs on the stack, and the number it leaves in their
+/// place.
+pub(crate) fn stack_effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Constant(_)
+        | Instruction::Nil
+        | Instruction::True
+        | Instruction::False
+        | Instruction::GetLocal(_)
+        | Instruction::GetGlobal(_)
+        | Instruction::GetUpvalue(_)
+        | Instruction::Closure(..)
+        | Instruction::Class(_) => (0, 1),
+        Instruction::Jump(_) | Instruction::Loop(_) => (0, 0),
+        Instruction::Return
+        | Instruction::Pop
+        | Instruction::DefineGlobal(_)
+        | Instruction::Print
+        | Instruction::CloseUpvalue => (1, 0),
+        Instruction::Negate
+        | Instruction::Not
+        | Instruction::SetLocal(_)
+        | Instruction::SetGlobal(_)
+        | Instruction::SetUpvalue(_)
+        | Instruction::GetProperty(_)
+        | Instruction::JumpIfFalse(_)
+        | Instruction::JumpIfTrue(_) => (1, 1),
+        Instruction::BinaryOp(_)
+        | Instruction::SetProperty(_)
+        | Instruction::GetSuper(_)
+        | Instruction::Index => (2, 1),
+        Instruction::Inherit | Instruction::Method(_) => (2, 1),
+        Instruction::Call(arg_count) | Instruction::Invoke(_, arg_count) => {
+            (*arg_count as usize + 1, 1)
+        }
+        Instruction::SuperInvoke(_, arg_count) => (*arg_count as usize + 2, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        bytecode::core::{BinaryOp, Capture, Chunk, OpCode},
+        compiler::{codegen::compile, parser::Parser},
+    };
+
+    fn script(instructions: &[(u32, Instruction)]) -> Function {
+        let mut function = Function::new(None);
+        function.chunk.add_instructions(instructions);
+        function
+    }
+
+    fn kinds(function: &Function) -> Vec<(usize, VerifyErrorKind)> {
+        match verify(function) {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|e| (e.pos, e.kind)).collect(),
+        }
+    }
+
+    #[test]
+    fn compiled() {
+        let source = "
+            class A < B { init(a) { this.a = a; } get() { return super.get() + this.a; } }
+            fun f(n) { var x = n; fun g() { return x; } for (;;) if (!x or n) return g(); }
+            while (true and false) { var y = 1; print y; }
+        ";
+        let declarations = Parser::new(source).parse().unwrap();
+        let function = compile(&declarations).unwrap();
+        assert_eq!(verify(&function), Ok(()));
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let function = script(&[
+            (1, Instruction::Nil),
+            (1, Instruction::BinaryOp(BinaryOp::Add)),
+            (1, Instruction::Return),
+        ]);
+        assert_eq!(
+            kinds(&function),
+            vec![(
+                1,
+                VerifyErrorKind::StackUnderflow {
+                    needed: 2,
+                    available: 1
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn stack_mismatch() {
+        let function = script(&[
+            (1, Instruction::True),
+            (1, Instruction::JumpIfFalse(1)),
+            (1, Instruction::Nil),
+            (1, Instruction::Return),
+        ]);
+        assert_eq!(
+            kinds(&function),
+            vec![(
+                5,
+                VerifyErrorKind::StackMismatch {
+                    expected: 2,
+                    found: 3
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        let function = script(&[
+            (1, Instruction::True),
+            (1, Instruction::JumpIfFalse(1)), // into the loop
+            (1, Instruction::Loop(10)),       // before the start
+            (1, Instruction::Return),
+        ]);
+        assert_eq!(
+            kinds(&function),
+            vec![
+                (1, VerifyErrorKind::JumpIntoInstruction),
+                (4, VerifyErrorKind::JumpOutOfBounds),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_return() {
+        let function = script(&[(1, Instruction::Nil), (1, Instruction::Pop)]);
+        assert_eq!(kinds(&function), vec![(1, VerifyErrorKind::MissingReturn)]);
+        assert_eq!(
+            kinds(&Function::new(None)),
+            vec![(0, VerifyErrorKind::MissingReturn)]
+        );
+    }
+
+    #[test]
+    fn locals_and_upvalues() {
+        let function = script(&[
+            (1, Instruction::GetLocal(1)),
+            (1, Instruction::GetUpvalue(0)),
+            (1, Instruction::Return),
+        ]);
+        assert_eq!(
+            kinds(&function),
+            vec![
+                (0, VerifyErrorKind::InvalidLocal(1)),
+                (2, VerifyErrorKind::InvalidUpvalue(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_function() {
+        let mut inner = Function::new(Some(Rc::from("f")));
+        inner.upvalue_count = 1;
+        inner.chunk.add_instructions(&[(2, Instruction::Pop)]);
+        let function = script(&[
+            (
+                1,
+                Instruction::Closure(
+                    Rc::new(inner),
+                    vec![Capture {
+                        is_local: true,
+                        index: 3,
+                    }],
+                ),
+            ),
+            (1, Instruction::Return),
+        ]);
+
+        let errors: Vec<_> = verify(&function)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "invalid local slot (3) at 0 in <script>",
+                "needs 1 values on the stack, but there are only 0 at 0 in <fn f>",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let function = Function {
+            chunk: Chunk {
+                code: vec![OpCode::Constant.into(), 0, OpCode::Return.into()],
+                lines: vec![1, 1, 1],
+                constants: vec![],
+            },
+            ..Function::new(None)
+        };
+        assert_eq!(
+            kinds(&function),
+            vec![(
+                0,
+                VerifyErrorKind::ParseError(BytecodeParseError::InvalidConstantIndex(0))
+            )]
+        );
+    }
+}
@@ -0,0 +1,731 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    string::FromUtf8Error,
+    time::Duration,
+};
+
+use anyhow::{Context, Error};
+use rustyline::{error::ReadlineError, DefaultEditor};
+use structopt::StructOpt;
+
+use crate::{
+    assembler,
+    bench::{self, Measurement},
+    bytecode::{loxc, parser::BytecodeParseError, peephole, verifier, verifier::VerifyError},
+    compiler::{
+        codegen,
+        codegen::CompileError,
+        constant_folding,
+        parser::{ParseError, Parser},
+        scanner::{LosslessScanner, ScannerError},
+    },
+    dap,
+    debugger::Debugger,
+    dissembler::{self, DissemblerPrinter},
+    exit_code::{self, ToExitCode},
+    formatter, lsp,
+    object::Function,
+    process::{self, Permissions},
+    profiler::Profiler,
+    repl::{self, Repl, ReplError},
+    vm::{InterpreterError, Limits, VM},
+};
+
+/// The path that stands for stdin.
+const STDIN: &str = "-";
+
+/// Where the repl keeps its history, in the home directory.
+const HISTORY_FILE: &str = ".rlox_history";
+
+#[derive(Debug, StructOpt)]
+/// A rust implemenation of a lox interpreter/compiler/vm.
+/// If no file path or code is given, drops into a REPL.
+/// Lox files can be given as - to read them from stdin instead.
+struct Rlox {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    /// a file to run, or - to read the program from stdin
+    #[structopt(parse(from_os_str))]
+    path: Option<PathBuf>,
+    /// arguments for the script, which it reads with args()
+    args: Vec<String>,
+    /// code to run instead of a file
+    #[structopt(short, long = "eval", value_name = "CODE", conflicts_with = "path")]
+    eval: Option<String>,
+    /// fold constant expressions before compiling, and simplify the compiled bytecode
+    #[structopt(short = "O", long, global = true)]
+    optimize: bool,
+    /// let scripts read environment variables with getenv(name)
+    #[structopt(long, global = true)]
+    allow_env: bool,
+    /// let scripts end the process with exit(code)
+    #[structopt(long, global = true)]
+    allow_exit: bool,
+    /// stop scripts after they run this many instructions
+    #[structopt(long, global = true, value_name = "COUNT")]
+    max_instructions: Option<u64>,
+    /// stop scripts after they allocate this many bytes for strings and objects
+    #[structopt(long, global = true, value_name = "BYTES")]
+    max_heap: Option<usize>,
+    /// stop scripts that put more than this many values on the stack
+    #[structopt(long, global = true, value_name = "VALUES")]
+    max_stack: Option<usize>,
+    /// stop scripts that nest calls more than this deep
+    #[structopt(long, global = true, value_name = "DEPTH")]
+    max_frames: Option<usize>,
+    /// stop scripts that run for longer than this many seconds
+    #[structopt(long, global = true, value_name = "SECONDS", parse(try_from_str = parse_timeout))]
+    timeout: Option<Duration>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Runs a lox file, which can be either source or compiled bytecode.
+    Run {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// arguments for the script, which it reads with args()
+        args: Vec<String>,
+        /// decode each instruction before running it, which is slower, and only kept to benchmark
+        /// the vm against
+        #[structopt(long, hidden = true)]
+        decode: bool,
+        /// count the instructions run by each opcode, line and function, and time the functions,
+        /// printing a report to stderr at exit
+        #[structopt(long)]
+        profile: bool,
+        /// profile the script, writing the instructions run by each call stack to this file in
+        /// the collapsed format used by flame graph tools
+        #[structopt(long, parse(from_os_str))]
+        collapsed: Option<PathBuf>,
+    },
+    /// Reads lox a line at a time, running each entry and printing the values of expressions.
+    Repl,
+    /// Prints the tokens a lox file is scanned into.
+    Tokens {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Prints the syntax tree of a lox file.
+    Ast {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Parses and compiles lox files without running them, reporting any errors.
+    Check {
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Runs a lox file in an interactive debugger, stopping before the first line.
+    Debug {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// arguments for the script, which it reads with args()
+        args: Vec<String>,
+    },
+    /// Serves the Debug Adapter Protocol over stdin and stdout, for debugging from an editor.
+    Dap,
+    /// Serves the Language Server Protocol over stdin and stdout, for editing lox in an editor.
+    Lsp,
+    /// Compiles a lox file to bytecode, which can be run later without recompiling.
+    Compile {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// where to write the bytecode, defaults to the source path with a .loxc extension, or .s
+        /// for assembly
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// write the bytecode as assembly, which can be edited and assembled with `asm`
+        #[structopt(short = "S", long)]
+        asm: bool,
+    },
+    /// Assembles a textual bytecode file into compiled bytecode.
+    Asm {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// where to write the bytecode, defaults to the assembly path with a .loxc extension
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Prints the bytecode of a lox file, which can be either source or compiled bytecode.
+    Disasm {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        /// print the instructions as json
+        #[structopt(long)]
+        json: bool,
+        /// print the bytecode as assembly, in the format read by `asm`
+        #[structopt(long, conflicts_with = "json")]
+        asm: bool,
+    },
+    /// Times lox programs, by default the built-in benchmark suite.
+    Bench {
+        /// the files to benchmark instead of the suite
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+        /// untimed runs of each program before it is measured
+        #[structopt(long, default_value = "3")]
+        warmups: usize,
+        /// timed runs of each program
+        #[structopt(long, default_value = "10")]
+        runs: usize,
+        /// print the results as json
+        #[structopt(long)]
+        json: bool,
+        /// json results from an earlier run to compare against
+        #[structopt(long, parse(from_os_str))]
+        baseline: Option<PathBuf>,
+    },
+    /// Formats lox files in place, or writes a formatted stdin to stdout.
+    Fmt {
+        /// don't write the files, instead fail if any of them aren't formatted
+        #[structopt(long)]
+        check: bool,
+        /// the files to format
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// Runs the rlox command line with the process's arguments, returning the code it should exit
+/// with.
+pub fn main() -> i32 {
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Warn)
+        .env()
+        .init()
+        .expect("cannot initialize logger");
+
+    let args = Rlox::from_args();
+    let permissions = Permissions {
+        env: args.allow_env,
+        exit: args.allow_exit,
+    };
+    let limits = Limits {
+        instructions: args.max_instructions,
+        heap_bytes: args.max_heap,
+        stack: args.max_stack,
+        frames: args.max_frames,
+        timeout: args.timeout,
+    };
+    let result = match args.command {
+        Some(Command::Run {
+            path,
+            args: script_args,
+            decode,
+            profile,
+            collapsed,
+        }) => {
+            let vm = script_vm(&script_args, permissions, limits);
+            match (profile, collapsed) {
+                (false, None) => run_file(&path, vm, args.optimize, decode),
+                (_, collapsed) => profile_file(&path, vm, collapsed, args.optimize),
+            }
+        }
+        Some(Command::Repl) => repl(script_vm(&[], permissions, limits)),
+        Some(Command::Tokens { path }) => print_tokens(&path),
+        Some(Command::Ast { path }) => print_ast(&path),
+        Some(Command::Check { paths }) => check_files(&paths, args.optimize),
+        Some(Command::Debug {
+            path,
+            args: script_args,
+        }) => debug_file(
+            &path,
+            script_vm(&script_args, permissions, limits),
+            args.optimize,
+        ),
+        Some(Command::Dap) => serve_dap(),
+        Some(Command::Lsp) => serve_lsp(),
+        Some(Command::Compile { path, output, asm }) => {
+            compile_file(&path, output, asm, args.optimize)
+        }
+        Some(Command::Asm { path, output }) => assemble_file(&path, output),
+        Some(Command::Disasm { path, json, asm }) => {
+            disassemble_file(&path, json, asm, args.optimize)
+        }
+        Some(Command::Bench {
+            paths,
+            warmups,
+            runs,
+            json,
+            baseline,
+        }) => bench_files(&paths, warmups, runs, json, baseline, args.optimize),
+        Some(Command::Fmt { check, paths }) => format_files(&paths, check),
+        None => {
+            let vm = script_vm(&args.args, permissions, limits);
+            match (args.eval, args.path) {
+                (Some(code), _) => run_source(&code, vm, args.optimize),
+                (None, Some(path)) => run_file(&path, vm, args.optimize, false),
+                (None, None) => repl(vm),
+            }
+        }
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(error) => {
+            // a script that calls exit() hasn't failed, it just wants to set the exit code
+            if !matches!(error.downcast_ref(), Some(InterpreterError::Exit(_))) {
+                log::error!("{:?}", error);
+            }
+            exit_code(&error)
+        }
+    }
+}
+
+/// Creates a VM for running a script, with the natives that give it access to the process and
+/// the limits it runs under.
+fn script_vm(args: &[String], permissions: Permissions, limits: Limits) -> VM {
+    let mut vm = VM::new();
+    vm.limits = limits;
+    process::define_natives(&mut vm, args, permissions);
+    vm
+}
+
+fn parse_timeout(seconds: &str) -> Result<Duration, Error> {
+    Ok(Duration::try_from_secs_f64(seconds.parse()?)?)
+}
+
+/// The code to exit with for an error, taken from the first cause in its chain that has one.
+fn exit_code(error: &Error) -> i32 {
+    fn code<E: ToExitCode + std::error::Error + Send + Sync + 'static>(
+        cause: &(dyn std::error::Error + 'static),
+    ) -> Option<i32> {
+        cause.downcast_ref::<E>().map(E::exit_code)
+    }
+
+    error
+        .chain()
+        .find_map(|cause| {
+            code::<InterpreterError>(cause)
+                .or_else(|| code::<ParseError>(cause))
+                .or_else(|| code::<CompileError>(cause))
+                .or_else(|| code::<ScannerError>(cause))
+                .or_else(|| code::<BytecodeParseError>(cause))
+                .or_else(|| code::<VerifyError>(cause))
+                .or_else(|| code::<ReplError>(cause))
+                .or_else(|| code::<FromUtf8Error>(cause))
+                .or_else(|| code::<std::io::Error>(cause))
+        })
+        .unwrap_or(exit_code::FAILURE)
+}
+
+fn repl(vm: VM) -> Result<(), Error> {
+    log::debug!("launching repl");
+
+    let mut editor = DefaultEditor::new().with_context(|| "unable to start the line editor")?;
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+    if let Some(history) = &history {
+        // there's no history the first time the repl runs
+        editor.load_history(history).ok();
+    }
+
+    let mut repl = Repl::with_vm(vm);
+    let mut entry = String::new();
+    let mut exit = None;
+    loop {
+        let prompt = if entry.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                entry.push_str(&line);
+                entry.push('\n');
+                if !repl::is_complete(&entry) {
+                    continue;
+                }
+                if !entry.trim().is_empty() {
+                    editor.add_history_entry(entry.trim_end()).ok();
+                    match repl.run(&entry, &mut std::io::stdout()) {
+                        Ok(()) => {}
+                        Err(ReplError::Runtime(InterpreterError::Exit(code))) => {
+                            exit = Some(code);
+                            break;
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                entry.clear();
+            }
+            // ctrl-c abandons the entry being typed
+            Err(ReadlineError::Interrupted) => entry.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e).with_context(|| "unable to read input"),
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            log::warn!("unable to save history to {:?}: {}", history, e);
+        }
+    }
+    log::debug!("terminating repl");
+    match exit {
+        Some(code) => Err(InterpreterError::Exit(code).into()),
+        None => Ok(()),
+    }
+}
+
+fn run_file<P>(path: &P, mut vm: VM, optimize: bool, decode: bool) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let (function, _) = load_file(path, optimize)?;
+    let function = Rc::new(function);
+    if decode {
+        vm.interpret_decoded(function)?;
+    } else {
+        vm.interpret(function)?;
+    }
+
+    log::debug!("finished running file");
+    Ok(())
+}
+
+fn run_source(code: &str, mut vm: VM, optimize: bool) -> Result<(), Error> {
+    let function = compile_source(&"--eval", code, optimize)?;
+    vm.interpret(Rc::new(function))?;
+    Ok(())
+}
+
+fn print_tokens<P>(path: &P) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let source = read_source(path)?;
+    repl::write_tokens(&mut std::io::stdout().lock(), &source)
+        .with_context(|| "unable to write the tokens")
+}
+
+fn print_ast<P>(path: &P) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let source = read_source(path)?;
+    repl::write_ast(&mut std::io::stdout().lock(), &source)
+        .with_context(|| format!("unable to parse lox file at {:?}", path))
+}
+
+fn check_files<P>(paths: &[P], optimize: bool) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut failed = 0;
+    let mut last_error = None;
+    for path in paths {
+        let checked = read_source(path).and_then(|source| compile_source(path, &source, optimize));
+        if let Err(error) = checked {
+            log::error!("{:?}", error);
+            failed += 1;
+            last_error = Some(error);
+        }
+    }
+
+    match last_error {
+        // keep an error as the cause so the exit code says what went wrong
+        Some(error) => Err(error.context(format!("{} file(s) have errors", failed))),
+        None => Ok(()),
+    }
+}
+
+fn profile_file<P>(
+    path: &P,
+    mut vm: VM,
+    collapsed: Option<PathBuf>,
+    optimize: bool,
+) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let (function, source) = load_file(path, optimize)?;
+    let mut profiler = Profiler::new();
+    let result = vm.interpret_with_hook(Rc::new(function), &mut profiler);
+
+    // a profile of a script that failed is still useful
+    match collapsed {
+        Some(output) => {
+            let mut file = std::fs::File::create(&output)
+                .with_context(|| format!("unable to create profile at {:?}", output))?;
+            profiler
+                .write_collapsed(&mut file)
+                .with_context(|| format!("unable to write profile at {:?}", output))?;
+            log::info!("wrote profile to {:?}", output);
+        }
+        None => profiler
+            .report(&mut std::io::stderr().lock(), source.as_deref())
+            .with_context(|| "unable to write the profile")?,
+    }
+    result?;
+    Ok(())
+}
+
+fn debug_file<P>(path: &P, mut vm: VM, optimize: bool) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let (function, source) = load_file(path, optimize)?;
+    let stdin = std::io::stdin();
+    let mut debugger = Debugger::new(stdin.lock(), std::io::stdout(), source.as_deref());
+    match vm.interpret_with_hook(Rc::new(function), &mut debugger) {
+        Ok(()) | Err(InterpreterError::Aborted) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn serve_dap() -> Result<(), Error> {
+    let stdin = std::io::stdin();
+    dap::serve(stdin.lock(), std::io::stdout()).with_context(|| "debug adapter connection failed")
+}
+
+fn serve_lsp() -> Result<(), Error> {
+    let stdin = std::io::stdin();
+    lsp::serve(stdin.lock(), std::io::stdout()).with_context(|| "language server connection failed")
+}
+
+fn disassemble_file<P>(path: &P, json: bool, asm: bool, optimize: bool) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let (function, source) = load_file(path, optimize)?;
+    let mut stdout = std::io::stdout().lock();
+    if json {
+        dissembler::dissemble_json(&mut stdout, &function)
+    } else if asm {
+        stdout.write_all(dissembler::to_assembly(&function.chunk).as_bytes())
+    } else {
+        DissemblerPrinter::dissemble(&mut stdout, &function, source.as_deref())
+    }
+    .with_context(|| "unable to write the disassembly")
+}
+
+/// Reads a lox file, or stdin when the path is `-`.
+fn read_file<P>(path: &P) -> Result<Vec<u8>, Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    if path.as_ref() == Path::new(STDIN) {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .with_context(|| "unable to read lox from stdin")?;
+        return Ok(bytes);
+    }
+    std::fs::read(path).with_context(|| format!("unable to read lox file at {:?}", path))
+}
+
+fn read_source<P>(path: &P) -> Result<String, Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    String::from_utf8(read_file(path)?)
+        .with_context(|| format!("lox file at {:?} isn't valid utf-8", path))
+}
+
+/// Loads a lox file, compiling it if it's source code, in which case the source is also returned.
+fn load_file<P>(path: &P, optimize: bool) -> Result<(Function, Option<String>), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let bytes = read_file(path)?;
+    // compiled files are told apart by their extension, or failing that their header
+    let extension = path.as_ref().extension();
+    if extension.is_some_and(|extension| extension == loxc::EXTENSION) || loxc::is_compiled(&bytes)
+    {
+        log::info!("read compiled file at {:?}", path);
+        let function = loxc::deserialize(&bytes)
+            .with_context(|| format!("unable to load compiled lox file at {:?}", path))?;
+        verifier::verify(&function)
+            .map_err(|errors| {
+                errors.iter().for_each(|error| log::error!("{}", error));
+                Error::new(errors[0].clone())
+            })
+            .with_context(|| format!("compiled lox file at {:?} failed verification", path))?;
+        Ok((function, None))
+    } else {
+        let source = String::from_utf8(bytes)
+            .with_context(|| format!("lox file at {:?} isn't valid utf-8", path))?;
+        let function = compile_source(path, &source, optimize)?;
+        Ok((function, Some(source)))
+    }
+}
+
+fn compile_file<P>(
+    path: &P,
+    output: Option<PathBuf>,
+    asm: bool,
+    optimize: bool,
+) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let source = read_source(path)?;
+    let function = compile_source(path, &source, optimize)?;
+
+    let (extension, compiled) = if asm {
+        let assembly = dissembler::to_assembly(&function.chunk);
+        (assembler::EXTENSION, assembly.into_bytes())
+    } else {
+        (loxc::EXTENSION, loxc::serialize(&function))
+    };
+    let output = match output {
+        Some(output) => output,
+        None if path.as_ref() == Path::new(STDIN) => {
+            anyhow::bail!("compiling stdin needs an --output path")
+        }
+        None => path.as_ref().with_extension(extension),
+    };
+    std::fs::write(&output, compiled)
+        .with_context(|| format!("unable to write compiled lox file at {:?}", output))?;
+    log::info!("compiled {:?} to {:?}", path, output);
+    Ok(())
+}
+
+fn assemble_file<P>(path: &P, output: Option<PathBuf>) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read assembly file at {:?}", path))?;
+    let chunk = assembler::assemble(&source).map_err(|errors| {
+        errors.iter().for_each(|error| log::error!("{}", error));
+        anyhow::anyhow!("unable to assemble file at {:?}", path)
+    })?;
+    let function = Function {
+        chunk,
+        ..Function::new(None)
+    };
+    // unlike compiled code, hand written bytecode can do anything, so it's checked up front
+    verifier::verify(&function).map_err(|errors| {
+        errors.iter().for_each(|error| log::error!("{}", error));
+        anyhow::anyhow!("assembly file at {:?} failed verification", path)
+    })?;
+
+    let output = output.unwrap_or_else(|| path.as_ref().with_extension(loxc::EXTENSION));
+    std::fs::write(&output, loxc::serialize(&function))
+        .with_context(|| format!("unable to write compiled lox file at {:?}", output))?;
+    log::info!("assembled {:?} to {:?}", path, output);
+    Ok(())
+}
+
+fn compile_source<P>(path: &P, source: &str, optimize: bool) -> Result<Function, Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    if log::log_enabled!(log::Level::Trace) {
+        log::trace!("read file at {:?}:\n{}", path, source);
+        for token in LosslessScanner::new(source) {
+            log::trace!(
+                "{}: {:?} {:?}",
+                token.location,
+                token.token,
+                token.full_text()
+            );
+        }
+    } else {
+        log::info!("read file at {:?}", path)
+    }
+
+    let mut declarations = Parser::new(source)
+        .parse()
+        .with_context(|| format!("unable to parse lox file at {:?}", path))?;
+    if optimize {
+        declarations = constant_folding::fold_constants(declarations);
+    }
+    let mut function = codegen::compile(&declarations).map_err(|errors| {
+        errors.iter().for_each(|error| log::error!("{}", error));
+        Error::new(errors[0].clone()).context(format!("unable to compile lox file at {:?}", path))
+    })?;
+    if optimize {
+        function = peephole::optimize_function(&function)?;
+    }
+    Ok(function)
+}
+
+fn bench_files(
+    paths: &[PathBuf],
+    warmups: usize,
+    runs: usize,
+    json: bool,
+    baseline: Option<PathBuf>,
+    optimize: bool,
+) -> Result<(), Error> {
+    if runs == 0 {
+        anyhow::bail!("benchmarks need at least one timed run");
+    }
+    let baseline = match baseline {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("unable to read benchmark results at {:?}", path))?;
+            let results = serde_json::from_str(&text)
+                .with_context(|| format!("benchmark results at {:?} aren't json", path))?;
+            bench::from_json(&results)
+                .with_context(|| format!("{:?} doesn't contain benchmark results", path))?
+        }
+        None => Vec::new(),
+    };
+
+    let programs = if paths.is_empty() {
+        bench::PROGRAMS
+            .iter()
+            .map(|(name, source)| Ok((name.to_string(), compile_source(name, source, optimize)?)))
+            .collect::<Result<Vec<_>, Error>>()?
+    } else {
+        paths
+            .iter()
+            .map(|path| {
+                let name = path.file_stem().unwrap_or(path.as_os_str());
+                Ok((
+                    name.to_string_lossy().into_owned(),
+                    load_file(path, optimize)?.0,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?
+    };
+
+    let mut measurements: Vec<Measurement> = Vec::new();
+    let mut stdout = std::io::stdout().lock();
+    for (name, function) in programs {
+        let measurement = bench::measure(&name, &Rc::new(function), warmups, runs)
+            .with_context(|| format!("benchmark {} failed", name))?;
+        if !json {
+            // report as each benchmark finishes, as the whole suite takes a while
+            bench::report(&mut stdout, std::slice::from_ref(&measurement), &baseline)?;
+        }
+        measurements.push(measurement);
+    }
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &bench::to_json(&measurements))?;
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
+
+fn format_files<P>(paths: &[P], check: bool) -> Result<(), Error>
+where
+    P: AsRef<Path> + std::fmt::Debug,
+{
+    let mut unformatted = 0;
+    for path in paths {
+        let source = read_source(path)?;
+        let formatted = formatter::format_source(&source)
+            .with_context(|| format!("unable to parse lox file at {:?}", path))?;
+
+        if path.as_ref() == Path::new(STDIN) && !check {
+            print!("{}", formatted);
+        } else if formatted == source {
+            log::debug!("{:?} is already formatted", path);
+        } else if check {
+            println!("{} is not formatted", path.as_ref().display());
+            unformatted += 1;
+        } else {
+            std::fs::write(path, formatted)
+                .with_context(|| format!("unable to write lox file at {:?}", path))?;
+            log::info!("formatted {:?}", path);
+        }
+    }
+
+    if unformatted > 0 {
+        anyhow::bail!("{} file(s) are not formatted", unformatted);
+    }
+    Ok(())
+}
@@ -0,0 +1,869 @@
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use crate::{
+    bytecode::core::{BinaryOp, Capture, Chunk, Instruction},
+    compiler::{
+        scanner::Location,
+        syntax_tree::{
+            self, BinaryOperator, Decl, Expression, Literal, LogicalOperator, Name, Span, Stmt,
+            UnaryOperator,
+        },
+        visitor::Visitor,
+    },
+    object::Function,
+    value::Value,
+};
+
+/// Local slots and upvalue indices are a single byte.
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CompileError {
+    #[error("can't read local variable '{1}' in its own initializer at {0}")]
+    ReadInOwnInitializer(Location, String),
+    #[error("already a variable named '{1}' in this scope at {0}")]
+    AlreadyDeclared(Location, String),
+    #[error("too many local variables in function at {0}")]
+    TooManyLocals(Location),
+    #[error("too many closure variables in function at {0}")]
+    TooManyUpvalues(Location),
+    #[error("too many constants in one chunk at {0}")]
+    TooManyConstants(Location),
+    #[error("too much code to jump over at {0}")]
+    JumpTooLarge(Location),
+    #[error("can't return from top-level code at {0}")]
+    ReturnFromTopLevel(Location),
+    #[error("can't return a value from an initializer at {0}")]
+    ReturnFromInitializer(Location),
+    #[error("can't use 'this' outside of a class at {0}")]
+    ThisOutsideClass(Location),
+    #[error("can't use 'super' outside of a class at {0}")]
+    SuperOutsideClass(Location),
+    #[error("can't use 'super' in a class with no superclass at {0}")]
+    SuperWithoutSuperclass(Location),
+    #[error("class '{1}' can't inherit from itself at {0}")]
+    InheritFromSelf(Location, String),
+}
+
+impl CompileError {
+    pub fn location(&self) -> Location {
+        match self {
+            CompileError::ReadInOwnInitializer(location, _)
+            | CompileError::AlreadyDeclared(location, _)
+            | CompileError::TooManyLocals(location)
+            | CompileError::TooManyUpvalues(location)
+            | CompileError::TooManyConstants(location)
+            | CompileError::JumpTooLarge(location)
+            | CompileError::ReturnFromTopLevel(location)
+            | CompileError::ReturnFromInitializer(location)
+            | CompileError::ThisOutsideClass(location)
+            | CompileError::SuperOutsideClass(location)
+            | CompileError::SuperWithoutSuperclass(location)
+            | CompileError::InheritFromSelf(location, _) => *location,
+        }
+    }
+}
+
+/// Compiles a program into the function for its top level script.
+pub fn compile(declarations: &[Decl<'_>]) -> Result<Function, Vec<CompileError>> {
+    compile_script(declarations, false)
+}
+
+/// Compiles a program like [`compile`], but when its last statement is an expression the script
+/// returns that expression's value rather than nil.
+pub fn compile_returning(declarations: &[Decl<'_>]) -> Result<Function, Vec<CompileError>> {
+    compile_script(declarations, true)
+}
+
+fn compile_script(
+    declarations: &[Decl<'_>],
+    return_last: bool,
+) -> Result<Function, Vec<CompileError>> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::new(FunctionKind::Script, None)],
+        classes: Vec::new(),
+        errors: Vec::new(),
+        line: 0,
+    };
+    match declarations.split_last() {
+        Some((Decl::Stmt(Stmt::Expression { expression, span }), rest)) if return_last => {
+            rest.iter().for_each(|decl| compiler.visit_decl(decl));
+            compiler.line = span.line;
+            compiler.visit_expression(expression);
+            compiler.emit(Instruction::Return);
+        }
+        _ => {
+            declarations
+                .iter()
+                .for_each(|decl| compiler.visit_decl(decl));
+            compiler.emit_return();
+        }
+    }
+
+    if compiler.errors.is_empty() {
+        Ok(compiler.functions.pop().expect("script function").function)
+    } else {
+        Err(compiler.errors)
+    }
+}
+
+/// The VM only has `==`, `>` and `<`, so the other comparisons are compiled as the negation of
+/// one of those. Returns the op, and whether its result needs to be negated.
+pub(crate) fn binary_op(operator: BinaryOperator) -> (BinaryOp, bool) {
+    match operator {
+        BinaryOperator::EqualEqual => (BinaryOp::Equal, false),
+        BinaryOperator::BangEqual => (BinaryOp::Equal, true),
+        BinaryOperator::Less => (BinaryOp::Less, false),
+        BinaryOperator::LessEqual => (BinaryOp::Greater, true),
+        BinaryOperator::Greater => (BinaryOp::Greater, false),
+        BinaryOperator::GreaterEqual => (BinaryOp::Less, true),
+        BinaryOperator::Plus => (BinaryOp::Add, false),
+        BinaryOperator::Minus => (BinaryOp::Subtract, false),
+        BinaryOperator::Star => (BinaryOp::Multiply, false),
+        BinaryOperator::Slash => (BinaryOp::Divide, false),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local<'a> {
+    name: &'a str,
+    /// the scope depth, or `None` while its initializer is being compiled
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+/// The function currently being compiled, along with the variables in scope.
+struct FunctionState<'a> {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local<'a>>,
+    upvalues: Vec<Capture>,
+    scope_depth: usize,
+}
+
+impl<'a> FunctionState<'a> {
+    fn new(kind: FunctionKind, name: Option<Rc<str>>) -> FunctionState<'a> {
+        // slot zero holds the called value, which methods can refer to as `this`
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        FunctionState {
+            function: Function::new(name),
+            kind,
+            locals: vec![Local {
+                name: receiver,
+                depth: Some(0),
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+struct Compiler<'a> {
+    /// the functions being compiled, innermost last
+    functions: Vec<FunctionState<'a>>,
+    /// the classes being compiled, innermost last
+    classes: Vec<ClassState>,
+    errors: Vec<CompileError>,
+    line: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn state(&mut self) -> &mut FunctionState<'a> {
+        self.functions
+            .last_mut()
+            .expect("no function being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    fn location(&self) -> Location {
+        Location { line: self.line }
+    }
+
+    fn error(&mut self, error: fn(Location) -> CompileError) {
+        let error = error(self.location());
+        self.errors.push(error);
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let needs_constant = matches!(
+            instruction,
+            Instruction::Constant(_)
+                | Instruction::GetGlobal(_)
+                | Instruction::DefineGlobal(_)
+                | Instruction::SetGlobal(_)
+                | Instruction::GetProperty(_)
+                | Instruction::SetProperty(_)
+                | Instruction::GetSuper(_)
+                | Instruction::Invoke(..)
+                | Instruction::SuperInvoke(..)
+                | Instruction::Closure(..)
+                | Instruction::Class(_)
+                | Instruction::Method(_)
+        );
+        if needs_constant && !self.chunk().has_room_for_constant() {
+            return self.error(CompileError::TooManyConstants);
+        }
+        let line = self.line as u32;
+        self.chunk().add_instruction(line, instruction);
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(Instruction::GetLocal(0));
+        } else {
+            self.emit(Instruction::Nil);
+        }
+        self.emit(Instruction::Return);
+    }
+
+    /// Emits a jump with a placeholder offset, and returns the position of the offset to patch.
+    fn emit_jump(&mut self, jump: fn(u16) -> Instruction) -> usize {
+        self.emit(jump(u16::MAX));
+        self.chunk().code.len() - 2
+    }
+
+    /// Points the jump at `operand` to the next instruction.
+    fn patch_jump(&mut self, operand: usize) {
+        let distance = self.chunk().code.len() - operand - 2;
+        let offset = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error(CompileError::JumpTooLarge);
+            0
+        });
+        self.chunk().code[operand..operand + 2].copy_from_slice(&offset.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        // the offset also has to skip over the loop instruction itself
+        let distance = self.chunk().code.len() - loop_start + 3;
+        let offset = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error(CompileError::JumpTooLarge);
+            0
+        });
+        self.emit(Instruction::Loop(offset));
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+
+        while let Some(local) = self.state().locals.last() {
+            if local.depth.is_some_and(|local_depth| local_depth <= depth) {
+                break;
+            }
+            let instruction = if local.is_captured {
+                Instruction::CloseUpvalue
+            } else {
+                Instruction::Pop
+            };
+            self.state().locals.pop();
+            self.emit(instruction);
+        }
+    }
+
+    fn add_local(&mut self, name: &'a str) {
+        if self.state().locals.len() == MAX_LOCALS {
+            return self.error(CompileError::TooManyLocals);
+        }
+        self.state().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    /// Adds a local variable to the current scope. Globals are late bound, so aren't declared.
+    fn declare_variable(&mut self, name: Name<'a>) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let depth = state.scope_depth;
+        let already_declared = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|local_depth| local_depth >= depth))
+            .any(|local| local.name == name.text);
+        if already_declared {
+            let location = Location {
+                line: name.span.line,
+            };
+            self.errors.push(CompileError::AlreadyDeclared(
+                location,
+                name.text.to_string(),
+            ));
+        }
+        self.add_local(name.text);
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    /// Makes a declared variable available, taking its value from the top of the stack.
+    fn define_variable(&mut self, name: &str) {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+        } else {
+            self.emit(Instruction::DefineGlobal(Rc::from(name)));
+        }
+    }
+
+    fn resolve_local(&mut self, function: usize, name: &str) -> Option<u8> {
+        let (slot, local) = self.functions[function]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name)?;
+        if local.depth.is_none() {
+            let error = CompileError::ReadInOwnInitializer(self.location(), name.to_string());
+            self.errors.push(error);
+        }
+        Some(slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        let enclosing = function.checked_sub(1)?;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true));
+        }
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, index, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8 {
+        let capture = Capture { is_local, index };
+        let state = &mut self.functions[function];
+        if let Some(existing) = state.upvalues.iter().position(|c| *c == capture) {
+            return existing as u8;
+        }
+        if state.upvalues.len() == MAX_UPVALUES {
+            self.error(CompileError::TooManyUpvalues);
+            return 0;
+        }
+        state.upvalues.push(capture);
+        state.function.upvalue_count = state.upvalues.len() as u8;
+        state.upvalues.len() as u8 - 1
+    }
+
+    /// Reads a variable, or assigns the value at the top of the stack to it.
+    fn named_variable(&mut self, name: &str, assign: bool) {
+        let function = self.functions.len() - 1;
+        let instruction = if let Some(slot) = self.resolve_local(function, name) {
+            if assign {
+                Instruction::SetLocal(slot)
+            } else {
+                Instruction::GetLocal(slot)
+            }
+        } else if let Some(index) = self.resolve_upvalue(function, name) {
+            if assign {
+                Instruction::SetUpvalue(index)
+            } else {
+                Instruction::GetUpvalue(index)
+            }
+        } else if assign {
+            Instruction::SetGlobal(Rc::from(name))
+        } else {
+            Instruction::GetGlobal(Rc::from(name))
+        };
+        self.emit(instruction);
+    }
+
+    fn function(&mut self, function: &syntax_tree::Function<'a>, kind: FunctionKind) {
+        let mut state = FunctionState::new(kind, Some(Rc::from(function.name.text)));
+        state.function.arity = function.params.len() as u8;
+        self.functions.push(state);
+        self.begin_scope();
+
+        for param in &function.params {
+            self.declare_variable(*param);
+            self.define_variable(param.text);
+        }
+        function.body.iter().for_each(|decl| self.visit_decl(decl));
+        self.emit_return();
+
+        let state = self.functions.pop().expect("function being compiled");
+        self.line = function.span.line;
+        self.emit(Instruction::Closure(
+            Rc::new(state.function),
+            state.upvalues,
+        ));
+    }
+
+    fn class(
+        &mut self,
+        name: Name<'a>,
+        superclass: &Option<Name<'a>>,
+        methods: &[syntax_tree::Function<'a>],
+    ) {
+        self.declare_variable(name);
+        self.emit(Instruction::Class(Rc::from(name.text)));
+        self.define_variable(name.text);
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if let Some(superclass) = superclass {
+            if superclass.text == name.text {
+                let location = Location {
+                    line: superclass.span.line,
+                };
+                self.errors.push(CompileError::InheritFromSelf(
+                    location,
+                    name.text.to_string(),
+                ));
+            }
+            self.named_variable(superclass.text, false);
+
+            // methods capture the superclass as the local `super`
+            self.begin_scope();
+            self.add_local("super");
+            self.define_variable("super");
+
+            self.named_variable(name.text, false);
+            self.emit(Instruction::Inherit);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.named_variable(name.text, false);
+        for method in methods {
+            let kind = if method.name.text == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind);
+            self.emit(Instruction::Method(Rc::from(method.name.text)));
+        }
+        self.emit(Instruction::Pop);
+
+        let class = self.classes.pop().expect("class being compiled");
+        if class.has_superclass {
+            self.end_scope();
+        }
+    }
+
+    /// Checks that `super` is used in a subclass.
+    fn check_super(&mut self) {
+        match self.classes.last() {
+            None => self.error(CompileError::SuperOutsideClass),
+            Some(class) if !class.has_superclass => {
+                self.error(CompileError::SuperWithoutSuperclass)
+            }
+            Some(_) => (),
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expression<'a>]) -> u8 {
+        arguments
+            .iter()
+            .for_each(|argument| self.visit_expression(argument));
+        arguments.len() as u8
+    }
+
+    fn for_loop(
+        &mut self,
+        initializer: &Option<Box<Decl<'a>>>,
+        condition: &Option<Expression<'a>>,
+        increment: &Option<Expression<'a>>,
+        body: &Stmt<'a>,
+        span: Span,
+    ) {
+        self.begin_scope();
+        if let Some(initializer) = initializer {
+            self.visit_decl(initializer);
+        }
+
+        let mut loop_start = self.chunk().code.len();
+        let exit_jump = condition.as_ref().map(|condition| {
+            self.visit_expression(condition);
+            let exit_jump = self.emit_jump(Instruction::JumpIfFalse);
+            self.emit(Instruction::Pop);
+            exit_jump
+        });
+
+        if let Some(increment) = increment {
+            // the increment runs after the body, so jump over it on the way in
+            let body_jump = self.emit_jump(Instruction::Jump);
+            let increment_start = self.chunk().code.len();
+            self.visit_expression(increment);
+            self.emit(Instruction::Pop);
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.visit_stmt(body);
+        self.line = span.line;
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit(Instruction::Pop);
+        }
+        self.end_scope();
+    }
+}
+
+impl<'a> Visitor<'a> for Compiler<'a> {
+    fn visit_decl(&mut self, decl: &Decl<'a>) {
+        self.line = decl.span().line;
+        match decl {
+            Decl::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => self.class(*name, superclass, methods),
+            Decl::Fun { function, .. } => {
+                // functions are initialized immediately, so they can refer to themselves
+                self.declare_variable(function.name);
+                self.mark_initialized();
+                self.visit_function(function);
+                self.define_variable(function.name.text);
+            }
+            Decl::Var {
+                name, initializer, ..
+            } => {
+                self.declare_variable(*name);
+                match initializer {
+                    Some(initializer) => self.visit_expression(initializer),
+                    None => self.emit(Instruction::Nil),
+                }
+                self.define_variable(name.text);
+            }
+            Decl::Stmt(stmt) => self.visit_stmt(stmt),
+        }
+    }
+
+    fn visit_function(&mut self, function: &syntax_tree::Function<'a>) {
+        self.function(function, FunctionKind::Function);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'a>) {
+        self.line = stmt.span().line;
+        match stmt {
+            Stmt::Block { declarations, .. } => {
+                self.begin_scope();
+                declarations.iter().for_each(|decl| self.visit_decl(decl));
+                self.end_scope();
+            }
+            Stmt::Expression { expression, .. } => {
+                self.visit_expression(expression);
+                self.emit(Instruction::Pop);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                span,
+            } => self.for_loop(initializer, condition, increment, body, *span),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.visit_expression(condition);
+                let then_jump = self.emit_jump(Instruction::JumpIfFalse);
+                self.emit(Instruction::Pop);
+                self.visit_stmt(then_branch);
+
+                let else_jump = self.emit_jump(Instruction::Jump);
+                self.patch_jump(then_jump);
+                self.emit(Instruction::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::Print { expression, .. } => {
+                self.visit_expression(expression);
+                self.emit(Instruction::Print);
+            }
+            Stmt::Return { value, .. } => {
+                let kind = self.state().kind;
+                if kind == FunctionKind::Script {
+                    self.error(CompileError::ReturnFromTopLevel);
+                }
+                match value {
+                    Some(value) => {
+                        if kind == FunctionKind::Initializer {
+                            self.error(CompileError::ReturnFromInitializer);
+                        }
+                        self.visit_expression(value);
+                        self.emit(Instruction::Return);
+                    }
+                    None => self.emit_return(),
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                span,
+            } => {
+                let loop_start = self.chunk().code.len();
+                self.visit_expression(condition);
+                let exit_jump = self.emit_jump(Instruction::JumpIfFalse);
+                self.emit(Instruction::Pop);
+                self.visit_stmt(body);
+                self.line = span.line;
+                self.emit_loop(loop_start);
+
+                self.patch_jump(exit_jump);
+                self.emit(Instruction::Pop);
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        let line = expression.span().line;
+        self.line = line;
+        match expression {
+            Expression::Assign { name, value, .. } => {
+                self.visit_expression(value);
+                self.line = line;
+                self.named_variable(name.text, true);
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                self.visit_expression(left);
+                self.visit_expression(right);
+                self.line = line;
+                let (op, negate) = binary_op(*operator);
+                self.emit(Instruction::BinaryOp(op));
+                if negate {
+                    self.emit(Instruction::Not);
+                }
+            }
+            Expression::Call {
+                callee, arguments, ..
+            } => match &**callee {
+                // method calls skip creating a bound method
+                Expression::Get { object, name, .. } => {
+                    self.visit_expression(object);
+                    let arg_count = self.arguments(arguments);
+                    self.line = line;
+                    self.emit(Instruction::Invoke(Rc::from(name.text), arg_count));
+                }
+                Expression::Super { method, .. } => {
+                    self.check_super();
+                    self.named_variable("this", false);
+                    let arg_count = self.arguments(arguments);
+                    self.line = line;
+                    self.named_variable("super", false);
+                    self.emit(Instruction::SuperInvoke(Rc::from(method.text), arg_count));
+                }
+                callee => {
+                    self.visit_expression(callee);
+                    let arg_count = self.arguments(arguments);
+                    self.line = line;
+                    self.emit(Instruction::Call(arg_count));
+                }
+            },
+            Expression::Get { object, name, .. } => {
+                self.visit_expression(object);
+                self.line = line;
+                self.emit(Instruction::GetProperty(Rc::from(name.text)));
+            }
+            Expression::Grouping { expression, .. } => self.visit_expression(expression),
+            Expression::Index { object, index, .. } => {
+                self.visit_expression(object);
+                self.visit_expression(index);
+                self.line = line;
+                self.emit(Instruction::Index);
+            }
+            Expression::Literal { value, .. } => match value {
+                Literal::Identifier(name) => self.named_variable(name, false),
+                Literal::String(s) => self.emit(Instruction::Constant(Value::from(*s))),
+                Literal::Number(n) => self.emit(Instruction::Constant(Value::from(*n))),
+                Literal::True => self.emit(Instruction::True),
+                Literal::False => self.emit(Instruction::False),
+                Literal::Nil => self.emit(Instruction::Nil),
+            },
+            Expression::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                self.visit_expression(left);
+                self.line = line;
+                let end_jump = match operator {
+                    LogicalOperator::And => self.emit_jump(Instruction::JumpIfFalse),
+                    LogicalOperator::Or => {
+                        let else_jump = self.emit_jump(Instruction::JumpIfFalse);
+                        let end_jump = self.emit_jump(Instruction::Jump);
+                        self.patch_jump(else_jump);
+                        end_jump
+                    }
+                };
+                self.emit(Instruction::Pop);
+                self.visit_expression(right);
+                self.patch_jump(end_jump);
+            }
+            Expression::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                self.visit_expression(object);
+                self.visit_expression(value);
+                self.line = line;
+                self.emit(Instruction::SetProperty(Rc::from(name.text)));
+            }
+            Expression::Super { method, .. } => {
+                self.check_super();
+                self.named_variable("this", false);
+                self.named_variable("super", false);
+                self.emit(Instruction::GetSuper(Rc::from(method.text)));
+            }
+            Expression::This { .. } => {
+                if self.classes.is_empty() {
+                    self.error(CompileError::ThisOutsideClass);
+                } else {
+                    self.named_variable("this", false);
+                }
+            }
+            Expression::Unary {
+                operator, right, ..
+            } => {
+                self.visit_expression(right);
+                self.line = line;
+                match operator {
+                    UnaryOperator::Minus => self.emit(Instruction::Negate),
+                    UnaryOperator::Bang => self.emit(Instruction::Not),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::Parser;
+    use test_case::test_case;
+
+    fn instructions(source: &str) -> Vec<Instruction> {
+        let declarations = Parser::new(source).parse().unwrap();
+        let function = compile(&declarations).unwrap();
+        function
+            .chunk
+            .iter()
+            .map(|(_, parsed)| parsed.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn expression() {
+        assert_eq!(
+            instructions("-(2 * 3) + 4;"),
+            vec![
+                Instruction::Constant(Value::from(2.0)),
+                Instruction::Constant(Value::from(3.0)),
+                Instruction::BinaryOp(BinaryOp::Multiply),
+                Instruction::Negate,
+                Instruction::Constant(Value::from(4.0)),
+                Instruction::BinaryOp(BinaryOp::Add),
+                Instruction::Pop,
+                Instruction::Nil,
+                Instruction::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            instructions("var a = 1; { var b = a; b = 2; }"),
+            vec![
+                Instruction::Constant(Value::from(1.0)),
+                Instruction::DefineGlobal(Rc::from("a")),
+                Instruction::GetGlobal(Rc::from("a")),
+                Instruction::Constant(Value::from(2.0)),
+                Instruction::SetLocal(1),
+                Instruction::Pop,
+                Instruction::Pop,
+                Instruction::Nil,
+                Instruction::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        assert_eq!(
+            instructions("if (true) print 1; else print 2;"),
+            vec![
+                Instruction::True,
+                Instruction::JumpIfFalse(7),
+                Instruction::Pop,
+                Instruction::Constant(Value::from(1.0)),
+                Instruction::Print,
+                Instruction::Jump(4),
+                Instruction::Pop,
+                Instruction::Constant(Value::from(2.0)),
+                Instruction::Print,
+                Instruction::Nil,
+                Instruction::Return,
+            ]
+        );
+    }
+
+    #[test_case("return 1;", CompileError::ReturnFromTopLevel(Location { line: 1 }); "top level return")]
+    #[test_case("print this;", CompileError::ThisOutsideClass(Location { line: 1 }); "this outside class")]
+    #[test_case("class A { f() { super.f(); } }", CompileError::SuperWithoutSuperclass(Location { line: 1 }); "super without superclass")]
+    #[test_case("class A < A {}", CompileError::InheritFromSelf(Location { line: 1 }, "A".to_string()); "inherit from self")]
+    #[test_case("class A { init() { return 1; } }", CompileError::ReturnFromInitializer(Location { line: 1 }); "return from initializer")]
+    #[test_case("{ var a = a; }", CompileError::ReadInOwnInitializer(Location { line: 1 }, "a".to_string()); "own initializer")]
+    #[test_case("{ var a; \n var a; }", CompileError::AlreadyDeclared(Location { line: 2 }, "a".to_string()); "already declared")]
+    fn error(source: &str, expected: CompileError) {
+        let declarations = Parser::new(source).parse().unwrap();
+        assert_eq!(compile(&declarations).unwrap_err(), vec![expected]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    compiler::{
+        codegen::binary_op,
+        folder::{walk_expression, Folder},
+        syntax_tree::{Decl, Expression, Literal, LogicalOperator, Span, UnaryOperator},
+    },
+    value::{Unpacked, Value},
+};
+
+/// Evaluates operators whose operands are all literals at compile time, using the same semantics
+/// as the VM. Anything that would fail at runtime, such as `1 + nil`, is left alone so the error
+/// still happens when (and if) the code runs. Code is never removed, even when it can't run, so
+/// that the compiler still reports its errors.
+pub struct ConstantFolder;
+
+pub fn fold_constants(declarations: Vec<Decl<'_>>) -> Vec<Decl<'_>> {
+    declarations
+        .into_iter()
+        .map(|decl| ConstantFolder.fold_decl(decl))
+        .collect()
+}
+
+impl<'a> Folder<'a> for ConstantFolder {
+    fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+        let expression = walk_expression(self, expression);
+        fold(&expression).unwrap_or(expression)
+    }
+}
+
+/// Folds an expression whose children have already been folded.
+fn fold<'a>(expression: &Expression<'a>) -> Option<Expression<'a>> {
+    match expression {
+        Expression::Grouping { expression, .. } => {
+            constant(expression)?;
+            Some((**expression).clone())
+        }
+        Expression::Unary {
+            operator,
+            right,
+            span,
+        } => {
+            let right = constant(right)?;
+            let result = match operator {
+                UnaryOperator::Minus => right.negate().ok()?,
+                UnaryOperator::Bang => !right,
+            };
+            literal(result, *span)
+        }
+        Expression::Binary {
+            left,
+            operator,
+            right,
+            span,
+        } => {
+            let (op, negate) = binary_op(*operator);
+            let result = Value::apply_binary_op(constant(left)?, constant(right)?, op).ok()?;
+            literal(if negate { !result } else { result }, *span)
+        }
+        // logical operators evaluate to one of their operands, and the other is only dropped when
+        // it's a literal, as it could hold code the compiler would reject
+        Expression::Logical {
+            left,
+            operator,
+            right,
+            ..
+        } => {
+            let left_value = constant(left)?;
+            let short_circuits = match operator {
+                LogicalOperator::And => left_value.is_falsey(),
+                LogicalOperator::Or => !left_value.is_falsey(),
+            };
+            if short_circuits {
+                constant(right)?;
+                Some((**left).clone())
+            } else {
+                Some((**right).clone())
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The value of a literal expression. Identifiers are variables, so aren't constant.
+fn constant(expression: &Expression<'_>) -> Option<Value> {
+    match expression {
+        Expression::Literal { value, .. } => match value {
+            Literal::Identifier(_) => None,
+            Literal::String(s) => Some(Value::from(*s)),
+            Literal::Number(n) => Some(Value::from(*n)),
+            Literal::True => Some(Value::from(true)),
+            Literal::False => Some(Value::from(false)),
+            Literal::Nil => Some(Value::NIL),
+        },
+        _ => None,
+    }
+}
+
+/// Converts a folded value back into a literal. Concatenated strings don't appear in the source,
+/// so they can't be borrowed as a literal and are left to be built at runtime.
+fn literal<'a>(value: Value, span: Span) -> Option<Expression<'a>> {
+    let value = match value.unpack() {
+        Unpacked::Number(n) => Literal::Number(n),
+        Unpacked::Bool(true) => Literal::True,
+        Unpacked::Bool(false) => Literal::False,
+        Unpacked::Nil => Literal::Nil,
+        _ => return None,
+    };
+    Some(Expression::Literal { value, span })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{codegen::compile, parser::Parser};
+    use test_case::test_case;
+
+    fn folded(source: &str) -> Vec<String> {
+        let declarations = Parser::new(source).parse().unwrap();
+        fold_constants(declarations)
+            .iter()
+            .map(|decl| decl.to_string())
+            .collect()
+    }
+
+    #[test_case("-(2 * 3) + 4;", "(; -2)"; "arithmetic")]
+    #[test_case("1 < 2 == !nil;", "(; true)"; "comparison")]
+    #[test_case("2 >= 3;", "(; false)"; "negated comparison")]
+    #[test_case("\"a\" == \"a\";", "(; true)"; "string equality")]
+    #[test_case("x + 2 * 3;", "(; (+ x 6))"; "partial")]
+    #[test_case("1 + nil;", "(; (+ 1 nil))"; "type mismatch")]
+    #[test_case("-\"a\";", "(; (- a))"; "negate string")]
+    #[test_case("(1 + nil) * 2;", "(; (* (group (+ 1 nil)) 2))"; "nested type mismatch")]
+    #[test_case("\"a\" + \"b\";", "(; (+ a b))"; "concatenation")]
+    #[test_case("true and x;", "(; x)"; "and")]
+    #[test_case("nil and 2;", "(; nil)"; "and short circuits")]
+    #[test_case("0 or \"a\";", "(; 0)"; "or short circuits")]
+    #[test_case("nil and x;", "(; (and nil x))"; "short circuit keeps code")]
+    #[test_case("x and false;", "(; (and x false))"; "variable operand")]
+    fn expression(source: &str, expected: &str) {
+        assert_eq!(folded(source), vec![expected]);
+    }
+
+    #[test_case("if (1 > 2) print 1; else print 2;", "(if false (print 1) (print 2))"; "if false")]
+    #[test_case("while (false) print 1 + 1;", "(while false (print 2))"; "while false")]
+    #[test_case("while (x) print 1 + 1;", "(while x (print 2))"; "while variable")]
+    fn statement(source: &str, expected: &str) {
+        assert_eq!(folded(source), vec![expected]);
+    }
+
+    #[test_case("if (false) return 1;"; "return from top level")]
+    #[test_case("while (false) { var a = a; }"; "read in own initializer")]
+    #[test_case("if (false) print this;"; "this outside class")]
+    #[test_case("if (nil) { super.x(); }"; "super outside class")]
+    #[test_case("false and this;"; "short circuit")]
+    fn keeps_compile_errors(source: &str) {
+        let declarations = fold_constants(Parser::new(source).parse().unwrap());
+        assert!(compile(&declarations).is_err());
+    }
+}
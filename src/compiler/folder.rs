@@ -0,0 +1,271 @@
+use crate::compiler::syntax_tree::{Decl, Expression, Function, Stmt};
+
+/// Rewrites the syntax tree by taking nodes by value and returning their replacements.
+/// Implementors override the `fold_*` methods for the nodes they rewrite, and call the matching
+/// `walk_*` function to rebuild the node from its folded children.
+pub trait Folder<'a> {
+    fn fold_decl(&mut self, decl: Decl<'a>) -> Decl<'a> {
+        walk_decl(self, decl)
+    }
+
+    fn fold_function(&mut self, function: Function<'a>) -> Function<'a> {
+        walk_function(self, function)
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt<'a>) -> Stmt<'a> {
+        walk_stmt(self, stmt)
+    }
+
+    fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_decl<'a, F: Folder<'a> + ?Sized>(folder: &mut F, decl: Decl<'a>) -> Decl<'a> {
+    match decl {
+        Decl::Class {
+            name,
+            superclass,
+            methods,
+            span,
+        } => Decl::Class {
+            name,
+            superclass,
+            methods: methods
+                .into_iter()
+                .map(|method| folder.fold_function(method))
+                .collect(),
+            span,
+        },
+        Decl::Fun { function, span } => Decl::Fun {
+            function: folder.fold_function(function),
+            span,
+        },
+        Decl::Var {
+            name,
+            initializer,
+            span,
+        } => Decl::Var {
+            name,
+            initializer: initializer.map(|initializer| folder.fold_expression(initializer)),
+            span,
+        },
+        Decl::Stmt(stmt) => Decl::Stmt(folder.fold_stmt(stmt)),
+    }
+}
+
+pub fn walk_function<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    function: Function<'a>,
+) -> Function<'a> {
+    Function {
+        body: fold_declarations(folder, function.body),
+        ..function
+    }
+}
+
+fn fold_declarations<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    declarations: Vec<Decl<'a>>,
+) -> Vec<Decl<'a>> {
+    declarations
+        .into_iter()
+        .map(|decl| folder.fold_decl(decl))
+        .collect()
+}
+
+fn fold_boxed<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    expression: Expression<'a>,
+) -> Box<Expression<'a>> {
+    Box::new(folder.fold_expression(expression))
+}
+
+pub fn walk_stmt<'a, F: Folder<'a> + ?Sized>(folder: &mut F, stmt: Stmt<'a>) -> Stmt<'a> {
+    match stmt {
+        Stmt::Block { declarations, span } => Stmt::Block {
+            declarations: fold_declarations(folder, declarations),
+            span,
+        },
+        Stmt::Expression { expression, span } => Stmt::Expression {
+            expression: folder.fold_expression(expression),
+            span,
+        },
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            span,
+        } => Stmt::For {
+            initializer: initializer.map(|initializer| Box::new(folder.fold_decl(*initializer))),
+            condition: condition.map(|condition| folder.fold_expression(condition)),
+            increment: increment.map(|increment| folder.fold_expression(increment)),
+            body: Box::new(folder.fold_stmt(*body)),
+            span,
+        },
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span,
+        } => Stmt::If {
+            condition: folder.fold_expression(condition),
+            then_branch: Box::new(folder.fold_stmt(*then_branch)),
+            else_branch: else_branch.map(|else_branch| Box::new(folder.fold_stmt(*else_branch))),
+            span,
+        },
+        Stmt::Print { expression, span } => Stmt::Print {
+            expression: folder.fold_expression(expression),
+            span,
+        },
+        Stmt::Return { value, span } => Stmt::Return {
+            value: value.map(|value| folder.fold_expression(value)),
+            span,
+        },
+        Stmt::While {
+            condition,
+            body,
+            span,
+        } => Stmt::While {
+            condition: folder.fold_expression(condition),
+            body: Box::new(folder.fold_stmt(*body)),
+            span,
+        },
+    }
+}
+
+pub fn walk_expression<'a, F: Folder<'a> + ?Sized>(
+    folder: &mut F,
+    expression: Expression<'a>,
+) -> Expression<'a> {
+    match expression {
+        Expression::Assign { name, value, span } => Expression::Assign {
+            name,
+            value: fold_boxed(folder, *value),
+            span,
+        },
+        Expression::Binary {
+            left,
+            operator,
+            right,
+            span,
+        } => Expression::Binary {
+            left: fold_boxed(folder, *left),
+            operator,
+            right: fold_boxed(folder, *right),
+            span,
+        },
+        Expression::Call {
+            callee,
+            arguments,
+            span,
+        } => Expression::Call {
+            callee: fold_boxed(folder, *callee),
+            arguments: arguments
+                .into_iter()
+                .map(|argument| folder.fold_expression(argument))
+                .collect(),
+            span,
+        },
+        Expression::Get { object, name, span } => Expression::Get {
+            object: fold_boxed(folder, *object),
+            name,
+            span,
+        },
+        Expression::Grouping { expression, span } => Expression::Grouping {
+            expression: fold_boxed(folder, *expression),
+            span,
+        },
+        Expression::Index {
+            object,
+            index,
+            span,
+        } => Expression::Index {
+            object: fold_boxed(folder, *object),
+            index: fold_boxed(folder, *index),
+            span,
+        },
+        Expression::Logical {
+            left,
+            operator,
+            right,
+            span,
+        } => Expression::Logical {
+            left: fold_boxed(folder, *left),
+            operator,
+            right: fold_boxed(folder, *right),
+            span,
+        },
+        Expression::Set {
+            object,
+            name,
+            value,
+            span,
+        } => Expression::Set {
+            object: fold_boxed(folder, *object),
+            name,
+            value: fold_boxed(folder, *value),
+            span,
+        },
+        Expression::Unary {
+            operator,
+            right,
+            span,
+        } => Expression::Unary {
+            operator,
+            right: fold_boxed(folder, *right),
+            span,
+        },
+        expression @ (Expression::Literal { .. }
+        | Expression::Super { .. }
+        | Expression::This { .. }) => expression,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{parser::Parser, syntax_tree::Literal};
+
+    /// Replaces every read of `x` with `nil`.
+    struct ReplaceX;
+
+    impl<'a> Folder<'a> for ReplaceX {
+        fn fold_expression(&mut self, expression: Expression<'a>) -> Expression<'a> {
+            match expression {
+                Expression::Literal {
+                    value: Literal::Identifier("x"),
+                    span,
+                } => Expression::Literal {
+                    value: Literal::Nil,
+                    span,
+                },
+                expression => walk_expression(self, expression),
+            }
+        }
+    }
+
+    #[test]
+    fn rebuilds_tree() {
+        let source = "
+            var a = x;
+            fun f(b) { return -x + b(x.c); }
+            for (x = x; x; x = x) if (x) print x; else while (x) x.d = (x);
+        ";
+        let declarations = Parser::new(source).parse().unwrap();
+
+        let folded: Vec<_> = declarations
+            .into_iter()
+            .map(|decl| ReplaceX.fold_decl(decl).to_string())
+            .collect();
+        assert_eq!(
+            folded,
+            vec![
+                "(var a nil)",
+                "(fun f (params b) (return (+ (- nil) (call b (. nil c)))))",
+                "(for (; (= x nil)) nil (= x nil) (if nil (print nil) (while nil (; (= (. nil d) (group nil))))))",
+            ]
+        );
+    }
+}
@@ -1,2 +1,8 @@
+pub mod codegen;
+pub mod constant_folding;
+pub mod folder;
+pub mod parser;
+pub mod resolver;
 pub mod scanner;
 pub mod syntax_tree;
+pub mod visitor;
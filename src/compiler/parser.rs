@@ -0,0 +1,766 @@
+use thiserror::Error;
+
+use crate::compiler::scanner::{Location, LosslessScanner, LosslessToken, ScannerError, Token};
+use crate::compiler::syntax_tree::{
+    BinaryOperator, Decl, Expression, Function, Literal, LogicalOperator, Name, Span, Stmt,
+    UnaryOperator,
+};
+
+/// Functions can't take more arguments than fit in a byte.
+pub const MAX_ARGUMENTS: usize = 255;
+/// How deeply statements and expressions can be nested. Each level takes stack space to parse,
+/// compile and drop, so without a limit deeply nested source would overflow the stack.
+pub const MAX_NESTING: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ParseError {
+    #[error("{1} at {0}")]
+    ScannerError(Location, ScannerError),
+    #[error("expected {expected} at {location}, found '{found}'")]
+    UnexpectedToken {
+        location: Location,
+        expected: &'static str,
+        found: String,
+    },
+    #[error("invalid assignment target at {0}")]
+    InvalidAssignmentTarget(Location),
+    #[error("can't have more than {MAX_ARGUMENTS} arguments at {0}")]
+    TooManyArguments(Location),
+    #[error("can't nest more than {MAX_NESTING} levels deep at {0}")]
+    TooDeeplyNested(Location),
+}
+
+impl ParseError {
+    pub fn location(&self) -> Location {
+        match self {
+            ParseError::ScannerError(location, _)
+            | ParseError::UnexpectedToken { location, .. }
+            | ParseError::InvalidAssignmentTarget(location)
+            | ParseError::TooManyArguments(location)
+            | ParseError::TooDeeplyNested(location) => *location,
+        }
+    }
+}
+
+/// A recursive descent parser over the lossless token stream, so the byte offsets of every token
+/// (and the comments between them) are available to tooling.
+pub struct Parser<'a> {
+    tokens: Vec<LosslessToken<'a>>,
+    current: usize,
+    /// how deeply nested the syntax being parsed is
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str) -> Parser<'a> {
+        Parser {
+            tokens: LosslessScanner::new(source).collect(),
+            current: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn tokens(&self) -> &[LosslessToken<'a>] {
+        &self.tokens
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.peek().token.is_none()
+    }
+
+    /// Parses the rest of the source as a program.
+    pub fn parse(&mut self) -> Result<Vec<Decl<'a>>, ParseError> {
+        let mut declarations = Vec::new();
+        while !self.is_at_end() {
+            declarations.push(self.declaration()?);
+        }
+        Ok(declarations)
+    }
+
+    pub fn declaration(&mut self) -> Result<Decl<'a>, ParseError> {
+        let start = self.start();
+        match self.peek_token()? {
+            Some(Token::Class) => {
+                self.advance();
+                let name = self.name("class name")?;
+                let superclass = if self.matches(Token::Less)? {
+                    Some(self.name("superclass name")?)
+                } else {
+                    None
+                };
+                self.consume(Token::LeftBrace, "'{' before class body")?;
+                let mut methods = Vec::new();
+                while !self.check(Token::RightBrace)? && !self.is_at_end() {
+                    methods.push(self.function("method name")?);
+                }
+                self.consume(Token::RightBrace, "'}' after class body")?;
+                Ok(Decl::Class {
+                    name,
+                    superclass,
+                    methods,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::Fun) => {
+                self.advance();
+                let function = self.function("function name")?;
+                Ok(Decl::Fun {
+                    function,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::Var) => self.var_declaration(),
+            _ => Ok(Decl::Stmt(self.statement()?)),
+        }
+    }
+
+    fn var_declaration(&mut self) -> Result<Decl<'a>, ParseError> {
+        let start = self.start();
+        self.consume(Token::Var, "'var'")?;
+        let name = self.name("variable name")?;
+        let initializer = if self.matches(Token::Equal)? {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(Token::Semicolon, "';' after variable declaration")?;
+        Ok(Decl::Var {
+            name,
+            initializer,
+            span: self.finish(start),
+        })
+    }
+
+    fn function(&mut self, kind: &'static str) -> Result<Function<'a>, ParseError> {
+        let start = self.start();
+        let name = self.name(kind)?;
+        self.consume(Token::LeftParen, "'(' after function name")?;
+        let mut params = Vec::new();
+        if !self.check(Token::RightParen)? {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(ParseError::TooManyArguments(self.peek().location));
+                }
+                params.push(self.name("parameter name")?);
+                if !self.matches(Token::Comma)? {
+                    break;
+                }
+            }
+        }
+        self.consume(Token::RightParen, "')' after parameters")?;
+        self.consume(Token::LeftBrace, "'{' before function body")?;
+        let body = self.block_contents()?;
+        Ok(Function {
+            name,
+            params,
+            body,
+            span: self.finish(start),
+        })
+    }
+
+    pub fn statement(&mut self) -> Result<Stmt<'a>, ParseError> {
+        let start = self.start();
+        match self.peek_token()? {
+            Some(Token::LeftBrace) => {
+                self.advance();
+                let declarations = self.block_contents()?;
+                Ok(Stmt::Block {
+                    declarations,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::For) => {
+                self.advance();
+                self.consume(Token::LeftParen, "'(' after 'for'")?;
+                let initializer = match self.peek_token()? {
+                    Some(Token::Semicolon) => {
+                        self.advance();
+                        None
+                    }
+                    Some(Token::Var) => Some(Box::new(self.var_declaration()?)),
+                    _ => Some(Box::new(Decl::Stmt(self.expression_statement()?))),
+                };
+                let condition = self.optional_expression(Token::Semicolon)?;
+                self.consume(Token::Semicolon, "';' after loop condition")?;
+                let increment = self.optional_expression(Token::RightParen)?;
+                self.consume(Token::RightParen, "')' after for clauses")?;
+                let body = Box::new(self.nested(Self::statement)?);
+                Ok(Stmt::For {
+                    initializer,
+                    condition,
+                    increment,
+                    body,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::If) => {
+                self.advance();
+                self.consume(Token::LeftParen, "'(' after 'if'")?;
+                let condition = self.expression()?;
+                self.consume(Token::RightParen, "')' after if condition")?;
+                let then_branch = Box::new(self.nested(Self::statement)?);
+                let else_branch = if self.matches(Token::Else)? {
+                    Some(Box::new(self.nested(Self::statement)?))
+                } else {
+                    None
+                };
+                Ok(Stmt::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::Print) => {
+                self.advance();
+                let expression = self.expression()?;
+                self.consume(Token::Semicolon, "';' after value")?;
+                Ok(Stmt::Print {
+                    expression,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::Return) => {
+                self.advance();
+                let value = self.optional_expression(Token::Semicolon)?;
+                self.consume(Token::Semicolon, "';' after return value")?;
+                Ok(Stmt::Return {
+                    value,
+                    span: self.finish(start),
+                })
+            }
+            Some(Token::While) => {
+                self.advance();
+                self.consume(Token::LeftParen, "'(' after 'while'")?;
+                let condition = self.expression()?;
+                self.consume(Token::RightParen, "')' after condition")?;
+                let body = Box::new(self.nested(Self::statement)?);
+                Ok(Stmt::While {
+                    condition,
+                    body,
+                    span: self.finish(start),
+                })
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt<'a>, ParseError> {
+        let start = self.start();
+        let expression = self.expression()?;
+        self.consume(Token::Semicolon, "';' after expression")?;
+        Ok(Stmt::Expression {
+            expression,
+            span: self.finish(start),
+        })
+    }
+
+    /// Parses the declarations of a block, after the opening brace.
+    fn block_contents(&mut self) -> Result<Vec<Decl<'a>>, ParseError> {
+        let mut declarations = Vec::new();
+        while !self.check(Token::RightBrace)? && !self.is_at_end() {
+            declarations.push(self.nested(Self::declaration)?);
+        }
+        self.consume(Token::RightBrace, "'}' after block")?;
+        Ok(declarations)
+    }
+
+    /// Parses an expression, unless the next token is `terminator`.
+    fn optional_expression(
+        &mut self,
+        terminator: Token<'a>,
+    ) -> Result<Option<Expression<'a>>, ParseError> {
+        if self.check(terminator)? {
+            Ok(None)
+        } else {
+            self.expression().map(Some)
+        }
+    }
+
+    pub fn expression(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expression<'a>, ParseError> {
+        let start = self.start();
+        let target = self.or()?;
+        if !self.check(Token::Equal)? {
+            return Ok(target);
+        }
+
+        let location = self.peek().location;
+        self.advance();
+        let value = Box::new(self.nested(Self::assignment)?);
+        let span = self.finish(start);
+        match target {
+            Expression::Literal {
+                value: Literal::Identifier(text),
+                span: name_span,
+            } => Ok(Expression::Assign {
+                name: Name {
+                    text,
+                    span: name_span,
+                },
+                value,
+                span,
+            }),
+            Expression::Get { object, name, .. } => Ok(Expression::Set {
+                object,
+                name,
+                value,
+                span,
+            }),
+            _ => Err(ParseError::InvalidAssignmentTarget(location)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.logical(Self::and, Token::Or, LogicalOperator::Or)
+    }
+
+    fn and(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.logical(Self::equality, Token::And, LogicalOperator::And)
+    }
+
+    fn logical(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expression<'a>, ParseError>,
+        token: Token<'a>,
+        operator: LogicalOperator,
+    ) -> Result<Expression<'a>, ParseError> {
+        let start = self.start();
+        let depth = self.depth;
+        let mut left = operand(self)?;
+        while self.matches(token)? {
+            self.deepen()?;
+            let right = operand(self)?;
+            left = Expression::Logical {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+                span: self.finish(start),
+            };
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn equality(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.binary(Self::comparison, |token| match token {
+            Token::EqualEqual => Some(BinaryOperator::EqualEqual),
+            Token::BangEqual => Some(BinaryOperator::BangEqual),
+            _ => None,
+        })
+    }
+
+    fn comparison(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.binary(Self::term, |token| match token {
+            Token::Less => Some(BinaryOperator::Less),
+            Token::LessEqual => Some(BinaryOperator::LessEqual),
+            Token::Greater => Some(BinaryOperator::Greater),
+            Token::GreaterEqual => Some(BinaryOperator::GreaterEqual),
+            _ => None,
+        })
+    }
+
+    fn term(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.binary(Self::factor, |token| match token {
+            Token::Plus => Some(BinaryOperator::Plus),
+            Token::Minus => Some(BinaryOperator::Minus),
+            _ => None,
+        })
+    }
+
+    fn factor(&mut self) -> Result<Expression<'a>, ParseError> {
+        self.binary(Self::unary, |token| match token {
+            Token::Star => Some(BinaryOperator::Star),
+            Token::Slash => Some(BinaryOperator::Slash),
+            _ => None,
+        })
+    }
+
+    /// Parses a left associative chain of binary operators, with operands parsed by `operand`.
+    fn binary(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expression<'a>, ParseError>,
+        operator: fn(Token<'a>) -> Option<BinaryOperator>,
+    ) -> Result<Expression<'a>, ParseError> {
+        let start = self.start();
+        let depth = self.depth;
+        let mut left = operand(self)?;
+        while let Some(op) = self.peek_token()?.and_then(operator) {
+            self.advance();
+            self.deepen()?;
+            let right = operand(self)?;
+            left = Expression::Binary {
+                left: Box::new(left),
+                operator: op,
+                right: Box::new(right),
+                span: self.finish(start),
+            };
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression<'a>, ParseError> {
+        let start = self.start();
+        let operator = match self.peek_token()? {
+            Some(Token::Minus) => UnaryOperator::Minus,
+            Some(Token::Bang) => UnaryOperator::Bang,
+            _ => return self.call(),
+        };
+        self.advance();
+        let right = self.nested(Self::unary)?;
+        Ok(Expression::Unary {
+            operator,
+            right: Box::new(right),
+            span: self.finish(start),
+        })
+    }
+
+    fn call(&mut self) -> Result<Expression<'a>, ParseError> {
+        let start = self.start();
+        let depth = self.depth;
+        let mut expression = self.primary()?;
+        loop {
+            if self.matches(Token::LeftParen)? {
+                let mut arguments = Vec::new();
+                if !self.check(Token::RightParen)? {
+                    loop {
+                        if arguments.len() >= MAX_ARGUMENTS {
+                            return Err(ParseError::TooManyArguments(self.peek().location));
+                        }
+                        arguments.push(self.nested(Self::expression)?);
+                        if !self.matches(Token::Comma)? {
+                            break;
+                        }
+                    }
+                }
+                self.consume(Token::RightParen, "')' after arguments")?;
+                expression = Expression::Call {
+                    callee: Box::new(expression),
+                    arguments,
+                    span: self.finish(start),
+                };
+            } else if self.matches(Token::Dot)? {
+                let name = self.name("property name after '.'")?;
+                expression = Expression::Get {
+                    object: Box::new(expression),
+                    name,
+                    span: self.finish(start),
+                };
+            } else if self.matches(Token::LeftBracket)? {
+                let index = self.nested(Self::expression)?;
+                self.consume(Token::RightBracket, "']' after index")?;
+                expression = Expression::Index {
+                    object: Box::new(expression),
+                    index: Box::new(index),
+                    span: self.finish(start),
+                };
+            } else {
+                self.depth = depth;
+                return Ok(expression);
+            }
+            self.deepen()?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression<'a>, ParseError> {
+        let start = self.start();
+        let value = match self.peek_token()? {
+            Some(Token::Number(n)) => Literal::Number(n),
+            Some(Token::String(s)) => Literal::String(s),
+            Some(Token::Identifier(s)) => Literal::Identifier(s),
+            Some(Token::True) => Literal::True,
+            Some(Token::False) => Literal::False,
+            Some(Token::Nil) => Literal::Nil,
+            Some(Token::This) => {
+                self.advance();
+                return Ok(Expression::This {
+                    span: self.finish(start),
+                });
+            }
+            Some(Token::Super) => {
+                self.advance();
+                self.consume(Token::Dot, "'.' after 'super'")?;
+                let method = self.name("superclass method name")?;
+                return Ok(Expression::Super {
+                    method,
+                    span: self.finish(start),
+                });
+            }
+            Some(Token::LeftParen) => {
+                self.advance();
+                let expression = self.nested(Self::expression)?;
+                self.consume(Token::RightParen, "')' after expression")?;
+                return Ok(Expression::Grouping {
+                    expression: Box::new(expression),
+                    span: self.finish(start),
+                });
+            }
+            _ => return Err(self.unexpected("expression")),
+        };
+        self.advance();
+        Ok(Expression::Literal {
+            value,
+            span: self.finish(start),
+        })
+    }
+
+    fn name(&mut self, expected: &'static str) -> Result<Name<'a>, ParseError> {
+        let start = self.start();
+        match self.peek_token()? {
+            Some(Token::Identifier(text)) => {
+                self.advance();
+                Ok(Name {
+                    text,
+                    span: self.finish(start),
+                })
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    /// Parses something nested in the syntax being parsed, one level deeper.
+    fn nested<T>(
+        &mut self,
+        parse: fn(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        let depth = self.depth;
+        let result = self.deepen().and_then(|_| parse(self));
+        self.depth = depth;
+        result
+    }
+
+    /// Goes a level deeper into nested syntax, failing past [`MAX_NESTING`].
+    fn deepen(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(ParseError::TooDeeplyNested(self.peek().location));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> &LosslessToken<'a> {
+        // the token stream always ends with the end of input, which is never advanced past
+        &self.tokens[self.current]
+    }
+
+    /// The next token, or `None` at the end of input.
+    fn peek_token(&self) -> Result<Option<Token<'a>>, ParseError> {
+        let next = self.peek();
+        match next.token {
+            None => Ok(None),
+            Some(Ok(token)) => Ok(Some(token)),
+            Some(Err(e)) => Err(ParseError::ScannerError(next.location, e)),
+        }
+    }
+
+    fn check(&self, token: Token<'a>) -> Result<bool, ParseError> {
+        Ok(self.peek_token()? == Some(token))
+    }
+
+    /// Advances past the next token if it is `token`.
+    fn matches(&mut self, token: Token<'a>) -> Result<bool, ParseError> {
+        let matches = self.check(token)?;
+        if matches {
+            self.advance();
+        }
+        Ok(matches)
+    }
+
+    fn advance(&mut self) {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+    }
+
+    /// An empty span at the start of the next token.
+    fn start(&self) -> Span {
+        let next = self.peek();
+        Span {
+            start: next.offset,
+            end: next.offset,
+            line: next.location.line,
+        }
+    }
+
+    /// Extends a span from [`Parser::start`] to the end of the last consumed token.
+    fn finish(&self, start: Span) -> Span {
+        let previous = &self.tokens[self.current - 1];
+        Span {
+            end: previous.offset + previous.text.len(),
+            ..start
+        }
+    }
+
+    fn consume(&mut self, token: Token<'a>, expected: &'static str) -> Result<(), ParseError> {
+        if self.matches(token)? {
+            Ok(())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn unexpected(&self, expected: &'static str) -> ParseError {
+        let next = self.peek();
+        let found = match next.token {
+            None => "end of file",
+            _ => next.text,
+        };
+        ParseError::UnexpectedToken {
+            location: next.location,
+            expected,
+            found: found.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    fn parse_expression(input: &str) -> String {
+        let mut parser = Parser::new(input);
+        let expression = parser.expression().unwrap();
+        assert!(parser.is_at_end());
+        format!("{expression}")
+    }
+
+    fn parse(input: &str) -> Vec<String> {
+        let declarations = Parser::new(input).parse().unwrap();
+        declarations.iter().map(|decl| format!("{decl}")).collect()
+    }
+
+    #[test_case("1 + 2 * 3", "(+ 1 (* 2 3))")]
+    #[test_case("1 - 2 - 3", "(- (- 1 2) 3)"; "left associative")]
+    #[test_case("-(2 * 3) + 4", "(+ (- (group (* 2 3))) 4)")]
+    #[test_case("!!a == nil", "(== (! (! a)) nil)")]
+    #[test_case("a < b != c >= d", "(!= (< a b) (>= c d))")]
+    #[test_case("\"str\" // comment\n / .5", "(/ str 0.5)"; "comments are skipped")]
+    #[test_case("a = b = true", "(= a (= b true))"; "assignment")]
+    #[test_case("a or b and !c or false", "(or (or a (and b (! c))) false)"; "logical")]
+    #[test_case("f(1)(a, b)()", "(call (call (call f 1) a b))"; "call")]
+    #[test_case("a.b.c = this.d", "(= (. (. a b) c) (. this d))"; "set")]
+    #[test_case("args()[i + 1].x", "(. ([] (call args) (+ i 1)) x)"; "index")]
+    #[test_case("super.method(1)", "(call (super method) 1)"; "super call")]
+    fn expression(input: &str, expected: &str) {
+        assert_eq!(parse_expression(input), expected)
+    }
+
+    #[test_case("print 1;", "(print 1)")]
+    #[test_case("a;", "(; a)")]
+    #[test_case("var a;", "(var a)")]
+    #[test_case("var a = 1;", "(var a 1)")]
+    #[test_case("{ var a; print a; }", "(block (var a) (print a))")]
+    #[test_case("if (a) print 1; else { }", "(if a (print 1) (block))")]
+    #[test_case("while (true) a = a + 1;", "(while true (; (= a (+ a 1))))")]
+    #[test_case("for (;;) {}", "(for _ _ _ (block))"; "empty for")]
+    #[test_case(
+        "for (var i = 0; i < 10; i = i + 1) print i;",
+        "(for (var i 0) (< i 10) (= i (+ i 1)) (print i))"
+    )]
+    #[test_case("fun f() { return; }", "(fun f (params) (return))")]
+    #[test_case(
+        "fun add(a, b) { return a + b; }",
+        "(fun add (params a b) (return (+ a b)))"
+    )]
+    #[test_case(
+        "class A < B { init(a) { this.a = a; } get() {} }",
+        "(class A < B (fun init (params a) (; (= (. this a) a))) (fun get (params)))"
+    )]
+    fn declaration(input: &str, expected: &str) {
+        assert_eq!(parse(input), vec![expected])
+    }
+
+    #[test]
+    fn spans() {
+        let input = "print 1;\n  var a = -b;";
+        let declarations = Parser::new(input).parse().unwrap();
+        assert_eq!(
+            declarations[0].span(),
+            Span {
+                start: 0,
+                end: 8,
+                line: 1
+            }
+        );
+        match &declarations[1] {
+            Decl::Var {
+                name,
+                initializer: Some(initializer),
+                span,
+            } => {
+                assert_eq!(
+                    *span,
+                    Span {
+                        start: 11,
+                        end: 22,
+                        line: 2
+                    }
+                );
+                assert_eq!((name.span.start, name.span.end), (15, 16));
+                assert_eq!((initializer.span().start, initializer.span().end), (19, 21));
+            }
+            decl => panic!("expected a variable declaration, found {decl}"),
+        }
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        let result = Parser::new("1 + 2").parse();
+        assert_eq!(
+            result,
+            Err(ParseError::UnexpectedToken {
+                location: Location { line: 1 },
+                expected: "';' after expression",
+                found: "end of file".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_assignment_target() {
+        for source in ["a + b = c;", "a[0] = c;"] {
+            let result = Parser::new(source).parse();
+            assert_eq!(
+                result,
+                Err(ParseError::InvalidAssignmentTarget(Location { line: 1 }))
+            );
+        }
+    }
+
+    #[test]
+    fn scanner_error() {
+        let result = Parser::new("1 + #").expression();
+        assert_eq!(
+            result,
+            Err(ParseError::ScannerError(
+                Location { line: 1 },
+                ScannerError::UnexpectedCharacter('#')
+            ))
+        );
+    }
+
+    #[test_case("{".repeat(1000) + &"}".repeat(1000); "blocks")]
+    #[test_case("(".repeat(1000) + &")".repeat(1000) + ";"; "parens")]
+    #[test_case("-".repeat(1000) + "1;"; "unary")]
+    #[test_case("if (true) ".repeat(1000) + ";"; "statements")]
+    #[test_case(vec!["a"; 10000].join(" + ") + ";"; "chains")]
+    fn too_deeply_nested(source: String) {
+        // run with as much stack as the main thread has, where this much nesting used to overflow
+        let result = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(move || Parser::new(&source).parse().map(|_| ()))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            result,
+            Err(ParseError::TooDeeplyNested(Location { line: 1 }))
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use std::{collections::HashMap, fmt, ops::Range};
+
+use crate::compiler::{
+    syntax_tree::{Decl, Expression, Function, Literal, Name, Span, Stmt},
+    visitor::{walk_decl, walk_expression, walk_stmt, Visitor},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Method,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Function => "function",
+            SymbolKind::Class => "class",
+            SymbolKind::Method => "method",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// Something declared by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol<'a> {
+    pub name: Name<'a>,
+    pub kind: SymbolKind,
+    pub global: bool,
+    /// the whole declaration
+    pub span: Span,
+    /// the byte range of the source it can be referred to by name from, which is empty for methods
+    pub scope: Range<usize>,
+    /// the function, method or class it's declared in
+    pub parent: Option<usize>,
+}
+
+/// The symbols declared by a program, and what each name in it refers to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resolution<'a> {
+    pub symbols: Vec<Symbol<'a>>,
+    /// every name that refers to a variable in the order they appear, along with the symbol it
+    /// refers to, or `None` when there's no declaration for it
+    pub references: Vec<(Name<'a>, Option<usize>)>,
+}
+
+impl<'a> Resolution<'a> {
+    /// The name at `offset`, which may be just past its end, and the symbol it declares or refers
+    /// to.
+    pub fn name_at(&self, offset: usize) -> Option<(Name<'a>, usize)> {
+        let contains = |name: &Name| name.span.start <= offset && offset <= name.span.end;
+        let declaration = self
+            .symbols
+            .iter()
+            .position(|symbol| contains(&symbol.name))
+            .map(|symbol| (self.symbols[symbol].name, symbol));
+        declaration.or_else(|| {
+            self.references
+                .iter()
+                .find(|(name, _)| contains(name))
+                .and_then(|(name, symbol)| Some((*name, (*symbol)?)))
+        })
+    }
+
+    /// The names referring to a symbol, in the order they appear.
+    pub fn references_to(&self, symbol: usize) -> impl Iterator<Item = &Name<'a>> + '_ {
+        self.references
+            .iter()
+            .filter(move |(_, resolved)| *resolved == Some(symbol))
+            .map(|(name, _)| name)
+    }
+
+    /// The symbols that can be referred to by name at `offset`. When a name is shadowed, only the
+    /// innermost declaration is included.
+    pub fn visible_at(&self, offset: usize) -> Vec<&Symbol<'a>> {
+        let mut visible: HashMap<&str, &Symbol<'a>> = HashMap::new();
+        for symbol in &self.symbols {
+            if !symbol.scope.contains(&offset) {
+                continue;
+            }
+            let innermost = visible
+                .get(symbol.name.text)
+                .is_none_or(|other| other.scope.start <= symbol.scope.start);
+            if innermost {
+                visible.insert(symbol.name.text, symbol);
+            }
+        }
+        let mut visible: Vec<_> = visible.into_values().collect();
+        visible.sort_by_key(|symbol| symbol.name.text);
+        visible
+    }
+}
+
+/// Works out what every variable in a program refers to. Globals are late bound, so they can be
+/// referred to from anywhere, and redeclaring one refers back to its first declaration.
+pub fn resolve<'a>(declarations: &[Decl<'a>]) -> Resolution<'a> {
+    let mut resolver = Resolver::default();
+    for decl in declarations {
+        resolver.declare_global(decl);
+    }
+    declarations
+        .iter()
+        .for_each(|decl| resolver.visit_decl(decl));
+    // assignments are resolved after their values
+    let references = &mut resolver.resolution.references;
+    references.sort_by_key(|(name, _)| name.span.start);
+    resolver.resolution
+}
+
+#[derive(Default)]
+struct Resolver<'a> {
+    resolution: Resolution<'a>,
+    globals: HashMap<&'a str, usize>,
+    /// the local scopes, innermost last, with the symbols declared in each
+    scopes: Vec<Vec<usize>>,
+    /// where each scope ends
+    scope_ends: Vec<usize>,
+    /// the functions, methods and classes being resolved, innermost last
+    parents: Vec<usize>,
+}
+
+impl<'a> Resolver<'a> {
+    fn add(&mut self, name: Name<'a>, kind: SymbolKind, span: Span, scope: Range<usize>) -> usize {
+        self.resolution.symbols.push(Symbol {
+            name,
+            kind,
+            global: self.scopes.is_empty() && kind != SymbolKind::Method,
+            span,
+            scope,
+            parent: self.parents.last().copied(),
+        });
+        self.resolution.symbols.len() - 1
+    }
+
+    /// Globals are declared up front, since functions can refer to globals declared after them.
+    fn declare_global(&mut self, decl: &Decl<'a>) {
+        let (name, kind, span) = match decl {
+            Decl::Class { name, span, .. } => (*name, SymbolKind::Class, *span),
+            Decl::Fun { function, span } => (function.name, SymbolKind::Function, *span),
+            Decl::Var { name, span, .. } => (*name, SymbolKind::Variable, *span),
+            Decl::Stmt(_) => return,
+        };
+        if !self.globals.contains_key(name.text) {
+            let symbol = self.add(name, kind, span, 0..usize::MAX);
+            self.globals.insert(name.text, symbol);
+        }
+    }
+
+    /// Declares a variable in the innermost scope, visible from `start`. At the top level, this
+    /// finds the global declared up front.
+    fn declare(&mut self, name: Name<'a>, kind: SymbolKind, span: Span, start: usize) -> usize {
+        match self.scope_ends.last() {
+            Some(&end) => {
+                let symbol = self.add(name, kind, span, start..end);
+                self.scopes.last_mut().expect("no scope").push(symbol);
+                symbol
+            }
+            None => {
+                let symbol = self.globals[name.text];
+                if self.resolution.symbols[symbol].name != name {
+                    self.resolution.references.push((name, Some(symbol)));
+                }
+                symbol
+            }
+        }
+    }
+
+    fn begin_scope(&mut self, end: usize) {
+        self.scopes.push(Vec::new());
+        self.scope_ends.push(end);
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+        self.scope_ends.pop();
+    }
+
+    fn reference(&mut self, name: Name<'a>) {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .copied()
+            .find(|&symbol| self.resolution.symbols[symbol].name.text == name.text);
+        let symbol = local.or_else(|| self.globals.get(name.text).copied());
+        self.resolution.references.push((name, symbol));
+    }
+
+    fn function(&mut self, function: &Function<'a>, symbol: usize) {
+        self.parents.push(symbol);
+        self.begin_scope(function.span.end);
+        for param in &function.params {
+            let span = param.span;
+            self.declare(*param, SymbolKind::Parameter, span, span.start);
+        }
+        function.body.iter().for_each(|decl| self.visit_decl(decl));
+        self.end_scope();
+        self.parents.pop();
+    }
+}
+
+impl<'a> Visitor<'a> for Resolver<'a> {
+    fn visit_decl(&mut self, decl: &Decl<'a>) {
+        match decl {
+            Decl::Class {
+                name,
+                superclass,
+                methods,
+                span,
+            } => {
+                let class = self.declare(*name, SymbolKind::Class, *span, name.span.start);
+                if let Some(superclass) = superclass {
+                    self.reference(*superclass);
+                }
+                self.parents.push(class);
+                for method in methods {
+                    let symbol = self.add(method.name, SymbolKind::Method, method.span, 0..0);
+                    self.function(method, symbol);
+                }
+                self.parents.pop();
+            }
+            Decl::Fun { function, span } => {
+                // the name is declared first so the function can call itself
+                let symbol = self.declare(
+                    function.name,
+                    SymbolKind::Function,
+                    *span,
+                    function.name.span.start,
+                );
+                self.function(function, symbol);
+            }
+            Decl::Var { name, span, .. } => {
+                walk_decl(self, decl);
+                self.declare(*name, SymbolKind::Variable, *span, span.end);
+            }
+            Decl::Stmt(_) => walk_decl(self, decl),
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'a>) {
+        match stmt {
+            Stmt::Block { span, .. } | Stmt::For { span, .. } => {
+                self.begin_scope(span.end);
+                walk_stmt(self, stmt);
+                self.end_scope();
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        match expression {
+            Expression::Literal {
+                value: Literal::Identifier(text),
+                span,
+            } => self.reference(Name { text, span: *span }),
+            Expression::Assign { name, .. } => {
+                walk_expression(self, expression);
+                self.reference(*name);
+            }
+            _ => walk_expression(self, expression),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::Parser;
+
+    const SOURCE: &str = "\
+var a = 1;
+fun f(b) {
+    var c = a + b;
+    {
+        var a = c;
+        print a;
+    }
+    return f(a) + d;
+}
+class E < F {
+    g() { return this; }
+}
+var a = 2;";
+
+    /// Each reference as `name@line -> declaration line`, or `?` when it's unresolved.
+    fn references(source: &str) -> Vec<String> {
+        let declarations = Parser::new(source).parse().unwrap();
+        let resolution = resolve(&declarations);
+        resolution
+            .references
+            .iter()
+            .map(|(name, symbol)| {
+                let declaration = match symbol {
+                    Some(symbol) => resolution.symbols[*symbol].name.span.line.to_string(),
+                    None => "?".to_string(),
+                };
+                format!("{}@{} -> {}", name.text, name.span.line, declaration)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolves_references() {
+        assert_eq!(
+            references(SOURCE),
+            vec![
+                "a@3 -> 1",
+                "b@3 -> 2",
+                "c@5 -> 3",
+                "a@6 -> 5",
+                "f@8 -> 2",
+                "a@8 -> 1",
+                "d@8 -> ?",
+                "F@10 -> ?",
+                "a@13 -> 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn own_initializer_refers_to_outer_variable() {
+        assert_eq!(references("var a = 1;\n{ var a = a; }"), vec!["a@2 -> 1"]);
+        // globals are late bound, so a function can refer to one declared after it
+        assert_eq!(references("fun f() { return g; } var g;"), vec!["g@1 -> 1"]);
+    }
+
+    #[test]
+    fn symbols() {
+        let declarations = Parser::new(SOURCE).parse().unwrap();
+        let resolution = resolve(&declarations);
+        let symbols: Vec<_> = resolution
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let parent = symbol
+                    .parent
+                    .map(|parent| resolution.symbols[parent].name.text);
+                (symbol.name.text, symbol.kind, symbol.global, parent)
+            })
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("a", SymbolKind::Variable, true, None),
+                ("f", SymbolKind::Function, true, None),
+                ("E", SymbolKind::Class, true, None),
+                ("b", SymbolKind::Parameter, false, Some("f")),
+                ("c", SymbolKind::Variable, false, Some("f")),
+                ("a", SymbolKind::Variable, false, Some("f")),
+                ("g", SymbolKind::Method, false, Some("E")),
+            ]
+        );
+
+        let (name, f) = resolution.name_at(SOURCE.find("f(a)").unwrap()).unwrap();
+        assert_eq!(name.span.line, 8);
+        assert_eq!(resolution.symbols[f].name.span.line, 2);
+        let lines: Vec<_> = resolution
+            .references_to(0)
+            .map(|name| name.span.line)
+            .collect();
+        assert_eq!(lines, vec![3, 8, 13]);
+    }
+
+    #[test]
+    fn visible_names() {
+        let declarations = Parser::new(SOURCE).parse().unwrap();
+        let resolution = resolve(&declarations);
+        let visible = |at: &str| -> Vec<_> {
+            resolution
+                .visible_at(SOURCE.find(at).unwrap())
+                .iter()
+                .map(|symbol| (symbol.name.text, symbol.name.span.line))
+                .collect()
+        };
+        assert_eq!(
+            visible("print a"),
+            vec![("E", 10), ("a", 5), ("b", 2), ("c", 3), ("f", 2)]
+        );
+        assert_eq!(
+            visible("var c"),
+            vec![("E", 10), ("a", 1), ("b", 2), ("f", 2)]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use phf::phf_map;
+use std::iter::FusedIterator;
 use std::str::FromStr;
 use std::{fmt::Display, str::Chars};
 use thiserror::Error;
@@ -28,7 +29,8 @@ fn complete_quote<'a>(iter: &mut Chars<'a>, line: &mut usize) -> Result<Token<'a
             let token = Token::String(raw.get(0..length).unwrap());
             return Ok(token);
         } else if let Some(c) = c {
-            length += 1;
+            // lengths are in bytes, as they're used to slice the source
+            length += c.len_utf8();
             if c == '\n' {
                 *line += 1;
             }
@@ -61,6 +63,8 @@ impl<'a: 'b, 'b> Iterator for &'b mut Scanner<'a> {
                     ')' => Some(Ok(RightParen)),
                     '{' => Some(Ok(LeftBrace)),
                     '}' => Some(Ok(RightBrace)),
+                    '[' => Some(Ok(LeftBracket)),
+                    ']' => Some(Ok(RightBracket)),
                     ',' => Some(Ok(Comma)),
                     '-' => Some(Ok(Minus)),
                     '+' => Some(Ok(Plus)),
@@ -77,7 +81,7 @@ impl<'a: 'b, 'b> Iterator for &'b mut Scanner<'a> {
                                 last = iter.clone();
                             } else if !period
                                 && c == '.'
-                                && iter.next().map_or(false, |c| '0' <= c && c <= '9')
+                                && iter.next().is_some_and(|c| c.is_ascii_digit())
                             {
                                 period = true;
                                 length += 2;
@@ -95,10 +99,10 @@ impl<'a: 'b, 'b> Iterator for &'b mut Scanner<'a> {
                     }
                     c if c.is_alphabetic() || c == '_' => {
                         let mut last = iter.clone();
-                        let mut length = 1;
+                        let mut length = c.len_utf8();
                         while let Some(c) = iter.next() {
                             if c.is_alphanumeric() || c == '_' {
-                                length += 1;
+                                length += c.len_utf8();
                                 last = iter.clone();
                             } else {
                                 break;
@@ -149,12 +153,12 @@ impl<'a: 'b, 'b> Iterator for &'b mut Scanner<'a> {
                         // comment goes till end of line
                         let raw = iter.as_str();
                         let mut length = 0;
-                        while let Some(c) = iter.next() {
+                        for c in iter.by_ref() {
                             if c == '\n' {
                                 self.line += 1;
                                 break;
                             } else {
-                                length += 1;
+                                length += c.len_utf8();
                             }
                         }
                         let comment = raw.get(0..length).unwrap();
@@ -176,6 +180,7 @@ impl<'a: 'b, 'b> Iterator for &'b mut Scanner<'a> {
                     self.iter = iter_1;
                     return Some((loc, Ok(token)));
                 } else {
+                    self.iter = iter_1;
                     return Some((loc, Err(ScannerError::UnexpectedCharacter(c1))));
                 };
             }
@@ -185,6 +190,172 @@ impl<'a: 'b, 'b> Iterator for &'b mut Scanner<'a> {
     }
 }
 
+/// Source text between tokens that the fast [`Scanner`] throws away (or, for comments, yields as a
+/// token of its own).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trivia<'a> {
+    /// A run of whitespace, not including newlines.
+    Whitespace(&'a str),
+    Newline,
+    /// A line comment, including the leading `//` but not the terminating newline.
+    Comment(&'a str),
+}
+
+impl<'a> Trivia<'a> {
+    pub fn text(&self) -> &'a str {
+        match self {
+            Trivia::Whitespace(s) | Trivia::Comment(s) => s,
+            Trivia::Newline => "\n",
+        }
+    }
+}
+
+/// A token along with the exact source text it was scanned from and the trivia surrounding it.
+///
+/// Trailing trivia runs up to (but not including) the next newline, everything else is leading
+/// trivia of the following token. Concatenating the [`LosslessToken::full_text`] of every token
+/// reproduces the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessToken<'a> {
+    pub leading: Vec<Trivia<'a>>,
+    /// The scanned token, or `None` for the end of input.
+    pub token: Option<Result<Token<'a>, ScannerError>>,
+    /// The source text of the token itself, without trivia.
+    pub text: &'a str,
+    /// Byte offset of `text` into the source.
+    pub offset: usize,
+    pub location: Location,
+    pub trailing: Vec<Trivia<'a>>,
+}
+
+impl<'a> LosslessToken<'a> {
+    pub fn full_text(&self) -> String {
+        let mut text = String::new();
+        self.leading.iter().for_each(|t| text.push_str(t.text()));
+        text.push_str(self.text);
+        self.trailing.iter().for_each(|t| text.push_str(t.text()));
+        text
+    }
+
+    /// The comments in the leading and then the trailing trivia.
+    pub fn comments(&self) -> impl Iterator<Item = Comment<'a>> + '_ {
+        let leading_length: usize = self.leading.iter().map(|t| t.text().len()).sum();
+        let leading = (self.offset - leading_length, &self.leading, false);
+        let trailing = (self.offset + self.text.len(), &self.trailing, true);
+        [leading, trailing]
+            .into_iter()
+            .flat_map(|(start, trivia, trailing)| {
+                trivia.iter().scan(start, move |offset, trivia| {
+                    let start = *offset;
+                    *offset += trivia.text().len();
+                    Some((start, trivia, trailing))
+                })
+            })
+            .filter_map(|(offset, trivia, trailing)| match trivia {
+                Trivia::Comment(text) => Some(Comment {
+                    text,
+                    offset,
+                    trailing,
+                }),
+                _ => None,
+            })
+    }
+}
+
+/// A comment in the trivia of a [`LosslessToken`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comment<'a> {
+    /// The comment, including the leading `//`.
+    pub text: &'a str,
+    /// Byte offset of the comment into the source.
+    pub offset: usize,
+    /// Whether the comment follows the token on the same line.
+    pub trailing: bool,
+}
+
+/// A slower scanner for tooling, which keeps whitespace and comments as trivia attached to the
+/// tokens. The last token is always an end of input token without a [`LosslessToken::token`], which
+/// holds any trivia at the end of the source.
+pub struct LosslessScanner<'a> {
+    source: &'a str,
+    scanner: Scanner<'a>,
+    done: bool,
+}
+
+impl<'a> LosslessScanner<'a> {
+    pub fn new(source: &'a str) -> LosslessScanner<'a> {
+        LosslessScanner {
+            source,
+            scanner: Scanner::new(source),
+            done: false,
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.source.len() - self.scanner.iter.as_str().len()
+    }
+
+    fn scan_trivia(&mut self, stop_at_newline: bool) -> Vec<Trivia<'a>> {
+        let mut trivia = Vec::new();
+        loop {
+            let rest = self.scanner.iter.as_str();
+            let (item, length) = if rest.starts_with("//") {
+                let length = rest.find('\n').unwrap_or(rest.len());
+                (Trivia::Comment(&rest[..length]), length)
+            } else if rest.starts_with('\n') {
+                if stop_at_newline {
+                    break;
+                }
+                self.scanner.line += 1;
+                (Trivia::Newline, 1)
+            } else {
+                let length = rest
+                    .find(|c: char| c == '\n' || !c.is_whitespace())
+                    .unwrap_or(rest.len());
+                if length == 0 {
+                    break;
+                }
+                (Trivia::Whitespace(&rest[..length]), length)
+            };
+            trivia.push(item);
+            self.scanner.iter = rest[length..].chars();
+        }
+        trivia
+    }
+}
+
+impl<'a> Iterator for LosslessScanner<'a> {
+    type Item = LosslessToken<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let leading = self.scan_trivia(false);
+        let offset = self.offset();
+        let location = Location {
+            line: self.scanner.line,
+        };
+        // all whitespace and comments have been consumed, so the scanner starts right at the token
+        let token = (&mut self.scanner).next().map(|(_, token)| token);
+        self.done = token.is_none();
+        let text = &self.source[offset..self.offset()];
+        let trailing = self.scan_trivia(true);
+
+        Some(LosslessToken {
+            leading,
+            token,
+            text,
+            offset,
+            location,
+            trailing,
+        })
+    }
+}
+
+impl FusedIterator for LosslessScanner<'_> {}
+
 #[derive(Debug, Clone, Copy, PartialEq, Error)]
 pub enum ScannerError {
     #[error("unterminated string")]
@@ -195,7 +366,7 @@ pub enum ScannerError {
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Location {
-    line: usize,
+    pub line: usize,
 }
 
 impl Display for Location {
@@ -211,6 +382,8 @@ pub enum Token<'a> {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -251,7 +424,7 @@ pub enum Token<'a> {
     While,
 }
 
-static KEYWORDS: phf::Map<&'static str, Token<'static>> = phf_map! {
+pub(crate) static KEYWORDS: phf::Map<&'static str, Token<'static>> = phf_map! {
     "and" => Token::And,
     "class" => Token::Class,
     "else" => Token::Else,
@@ -276,14 +449,16 @@ mod tests {
 
     use test_case::test_case;
 
-    fn scan(input: &str) -> Vec<Token> {
-        Scanner::new(&input)
+    fn scan(input: &str) -> Vec<Token<'_>> {
+        Scanner::new(input)
             .map(|(_, parsed)| parsed.unwrap())
             .collect()
     }
 
     #[test_case("(", Token::LeftParen)]
     #[test_case(")", Token::RightParen)]
+    #[test_case("[", Token::LeftBracket)]
+    #[test_case("]", Token::RightBracket)]
     #[test_case(",", Token::Comma)]
     #[test_case(".", Token::Dot)]
     #[test_case("-", Token::Minus)]
@@ -303,6 +478,9 @@ mod tests {
     #[test_case("first", Token::Identifier("first"), None; "first_EOF")]
     #[test_case("first ", Token::Identifier("first"), None; "first_whitespace")]
     #[test_case("_first ", Token::Identifier("_first"), None; "underscore")]
+    #[test_case("café+", Token::Identifier("café"), Some(Token::Plus); "non-ascii")]
+    #[test_case("élan", Token::Identifier("élan"), None; "non-ascii first")]
+    #[test_case("\"café\"+", Token::String("café"), Some(Token::Plus); "non-ascii string")]
     fn identifier(input: &str, t1: Token, t2: Option<Token>) {
         let tokens = scan(input);
         let expected = if let Some(t2) = t2 {
@@ -349,6 +527,7 @@ mod tests {
     #[test_case("// comment", Token::Comment(" comment"))]
     #[test_case("// comment\n", Token::Comment(" comment"); "newline")]
     #[test_case("//", Token::Comment(""); "empty")]
+    #[test_case("// naïve\n", Token::Comment(" naïve"); "non-ascii comment")]
     #[test_case("//\n", Token::Comment(""); "empty newline")]
     #[test_case("// ", Token::Comment(" "); "single whitespace")]
     #[test_case("// \n", Token::Comment(" "); "single whitespace newline")]
@@ -400,7 +579,7 @@ mod tests {
     #[test]
     fn whitespace() {
         let input = "\r(\n\t)\n\n{ }";
-        let tokens: Vec<_> = Scanner::new(&input)
+        let tokens: Vec<_> = Scanner::new(input)
             .map(|(loc, parsed)| (loc, parsed.unwrap()))
             .collect();
 
@@ -414,4 +593,103 @@ mod tests {
             ]
         );
     }
+
+    #[test_case(""; "empty")]
+    #[test_case("  \n\t"; "only whitespace")]
+    #[test_case("// only a comment"; "only comment")]
+    #[test_case("var a = 1;\n"; "trailing newline")]
+    #[test_case("\r\n(\n\t)  // closing\n\n{ }  "; "mixed whitespace")]
+    #[test_case("print \"multi\nline\" // comment\n.5 / 2"; "strings and slash")]
+    #[test_case("a # b"; "unexpected character")]
+    #[test_case("\"unterminated\n"; "unterminated string")]
+    #[test_case("print \"café\"; // naïve\n"; "non-ascii string and comment")]
+    #[test_case("var é = 1; print éa_ü;"; "non-ascii identifiers")]
+    #[test_case("a € b"; "non-ascii unexpected character")]
+    #[test_case("\"ünterminated"; "non-ascii unterminated string")]
+    fn lossless_round_trip(input: &str) {
+        let output: String = LosslessScanner::new(input)
+            .map(|token| token.full_text())
+            .collect();
+        assert_eq!(output, input)
+    }
+
+    #[test]
+    fn lossless_trivia() {
+        let input = "// header\na  // trailing\n  b";
+        let tokens: Vec<_> = LosslessScanner::new(input).collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                LosslessToken {
+                    leading: vec![Trivia::Comment("// header"), Trivia::Newline],
+                    token: Some(Ok(Token::Identifier("a"))),
+                    text: "a",
+                    offset: 10,
+                    location: Location { line: 2 },
+                    trailing: vec![Trivia::Whitespace("  "), Trivia::Comment("// trailing")],
+                },
+                LosslessToken {
+                    leading: vec![Trivia::Newline, Trivia::Whitespace("  ")],
+                    token: Some(Ok(Token::Identifier("b"))),
+                    text: "b",
+                    offset: 27,
+                    location: Location { line: 3 },
+                    trailing: vec![],
+                },
+                LosslessToken {
+                    leading: vec![],
+                    token: None,
+                    text: "",
+                    offset: 28,
+                    location: Location { line: 3 },
+                    trailing: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn lossless_comments() {
+        let input = "// header\na; // trailing\n  // leading\nb";
+        let comments: Vec<_> = LosslessScanner::new(input)
+            .flat_map(|token| token.comments().collect::<Vec<_>>())
+            .collect();
+
+        assert_eq!(
+            comments,
+            vec![
+                Comment {
+                    text: "// header",
+                    offset: 0,
+                    trailing: false,
+                },
+                Comment {
+                    text: "// trailing",
+                    offset: 13,
+                    trailing: true,
+                },
+                Comment {
+                    text: "// leading",
+                    offset: 27,
+                    trailing: false,
+                },
+            ]
+        );
+        for comment in comments {
+            assert_eq!(&input[comment.offset..][..comment.text.len()], comment.text);
+        }
+    }
+
+    #[test]
+    fn lossless_matches_fast_scanner() {
+        let input = "fun f(a) {\n  // comment\n  return a >= .5 and !nil;\n}";
+        let fast: Vec<_> = Scanner::new(input)
+            .filter(|(_, parsed)| !matches!(parsed, Ok(Token::Comment(_))))
+            .collect();
+        let lossless: Vec<_> = LosslessScanner::new(input)
+            .filter_map(|token| Some((token.location, token.token?)))
+            .collect();
+        assert_eq!(lossless, fast);
+    }
 }
@@ -1,9 +1,32 @@
-use crate::compiler::scanner::Token;
 use std::fmt;
-use std::fmt::{Error, Formatter, Write};
+use std::fmt::Formatter;
+
+use crate::compiler::visitor::Visitor;
+
+/// The location of a node in the source, as a byte range along with the line it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+}
+
+/// An identifier that declares or refers to something by name, such as a variable, parameter,
+/// property or method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Name<'a> {
+    pub text: &'a str,
+    pub span: Span,
+}
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum UnaryOperator {
+pub enum UnaryOperator {
     Minus,
     Bang,
 }
@@ -19,7 +42,7 @@ impl fmt::Display for UnaryOperator {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum BinaryOperator {
+pub enum BinaryOperator {
     EqualEqual,
     BangEqual,
     Less,
@@ -50,11 +73,30 @@ impl fmt::Display for BinaryOperator {
     }
 }
 
+/// Short-circuiting binary operators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogicalOperator {
+    And,
+    Or,
+}
+
+impl fmt::Display for LogicalOperator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let op_str = match self {
+            LogicalOperator::And => "and",
+            LogicalOperator::Or => "or",
+        };
+        write!(f, "{}", op_str)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Literal<'a> {
     Identifier(&'a str),
     String(&'a str),
     Number(f64),
+    True,
+    False,
     Nil,
 }
 
@@ -64,67 +106,533 @@ impl<'a> fmt::Display for Literal<'a> {
             Literal::Identifier(s) => write!(f, "{}", s),
             Literal::String(s) => write!(f, "{}", s),
             Literal::Number(n) => write!(f, "{}", n),
+            Literal::True => write!(f, "true"),
+            Literal::False => write!(f, "false"),
             Literal::Nil => write!(f, "nil"),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum Expression<'a> {
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression<'a> {
+    Assign {
+        name: Name<'a>,
+        value: Box<Expression<'a>>,
+        span: Span,
+    },
     Binary {
         left: Box<Expression<'a>>,
         operator: BinaryOperator,
         right: Box<Expression<'a>>,
+        span: Span,
+    },
+    Call {
+        callee: Box<Expression<'a>>,
+        arguments: Vec<Expression<'a>>,
+        span: Span,
+    },
+    Get {
+        object: Box<Expression<'a>>,
+        name: Name<'a>,
+        span: Span,
     },
     Grouping {
         expression: Box<Expression<'a>>,
+        span: Span,
+    },
+    /// Reading an element of a list, as in `list[index]`.
+    Index {
+        object: Box<Expression<'a>>,
+        index: Box<Expression<'a>>,
+        span: Span,
     },
     Literal {
         value: Literal<'a>,
+        span: Span,
+    },
+    Logical {
+        left: Box<Expression<'a>>,
+        operator: LogicalOperator,
+        right: Box<Expression<'a>>,
+        span: Span,
+    },
+    Set {
+        object: Box<Expression<'a>>,
+        name: Name<'a>,
+        value: Box<Expression<'a>>,
+        span: Span,
+    },
+    Super {
+        method: Name<'a>,
+        span: Span,
+    },
+    This {
+        span: Span,
     },
     Unary {
         operator: UnaryOperator,
         right: Box<Expression<'a>>,
+        span: Span,
     },
 }
 
-impl fmt::Display for Expression<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl Expression<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Assign { span, .. }
+            | Expression::Binary { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::Get { span, .. }
+            | Expression::Grouping { span, .. }
+            | Expression::Index { span, .. }
+            | Expression::Literal { span, .. }
+            | Expression::Logical { span, .. }
+            | Expression::Set { span, .. }
+            | Expression::Super { span, .. }
+            | Expression::This { span }
+            | Expression::Unary { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt<'a> {
+    Block {
+        declarations: Vec<Decl<'a>>,
+        span: Span,
+    },
+    Expression {
+        expression: Expression<'a>,
+        span: Span,
+    },
+    For {
+        /// Either a variable declaration or an expression statement.
+        initializer: Option<Box<Decl<'a>>>,
+        condition: Option<Expression<'a>>,
+        increment: Option<Expression<'a>>,
+        body: Box<Stmt<'a>>,
+        span: Span,
+    },
+    If {
+        condition: Expression<'a>,
+        then_branch: Box<Stmt<'a>>,
+        else_branch: Option<Box<Stmt<'a>>>,
+        span: Span,
+    },
+    Print {
+        expression: Expression<'a>,
+        span: Span,
+    },
+    Return {
+        value: Option<Expression<'a>>,
+        span: Span,
+    },
+    While {
+        condition: Expression<'a>,
+        body: Box<Stmt<'a>>,
+        span: Span,
+    },
+}
+
+impl Stmt<'_> {
+    pub fn span(&self) -> Span {
         match self {
+            Stmt::Block { span, .. }
+            | Stmt::Expression { span, .. }
+            | Stmt::For { span, .. }
+            | Stmt::If { span, .. }
+            | Stmt::Print { span, .. }
+            | Stmt::Return { span, .. }
+            | Stmt::While { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function<'a> {
+    pub name: Name<'a>,
+    pub params: Vec<Name<'a>>,
+    pub body: Vec<Decl<'a>>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl<'a> {
+    Class {
+        name: Name<'a>,
+        superclass: Option<Name<'a>>,
+        methods: Vec<Function<'a>>,
+        span: Span,
+    },
+    Fun {
+        function: Function<'a>,
+        span: Span,
+    },
+    Var {
+        name: Name<'a>,
+        initializer: Option<Expression<'a>>,
+        span: Span,
+    },
+    Stmt(Stmt<'a>),
+}
+
+impl Decl<'_> {
+    pub fn span(&self) -> Span {
+        match self {
+            Decl::Class { span, .. } | Decl::Fun { span, .. } | Decl::Var { span, .. } => *span,
+            Decl::Stmt(stmt) => stmt.span(),
+        }
+    }
+}
+
+/// Prints nodes as s-expressions, for debugging and tests.
+struct Printer<'f, 'w> {
+    f: &'f mut Formatter<'w>,
+    result: fmt::Result,
+}
+
+impl<'f, 'w> Printer<'f, 'w> {
+    fn print<F: FnOnce(&mut Self)>(f: &'f mut Formatter<'w>, print: F) -> fmt::Result {
+        let mut printer = Printer { f, result: Ok(()) };
+        print(&mut printer);
+        printer.result
+    }
+
+    fn write<T: fmt::Display + ?Sized>(&mut self, text: &T) {
+        if self.result.is_ok() {
+            self.result = write!(self.f, "{}", text);
+        }
+    }
+
+    /// Writes `(<name> <children>...)`.
+    fn list<T, F: FnMut(&mut Self, &T)>(&mut self, name: &str, children: &[T], mut print: F) {
+        self.write("(");
+        self.write(name);
+        for child in children {
+            self.write(" ");
+            print(self, child);
+        }
+        self.write(")");
+    }
+
+    /// Writes an optional node, with `_` standing in for a missing one.
+    fn optional<T, F: FnOnce(&mut Self, &T)>(&mut self, child: &Option<T>, print: F) {
+        match child {
+            Some(child) => print(self, child),
+            None => self.write("_"),
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for Printer<'_, '_> {
+    fn visit_decl(&mut self, decl: &Decl<'a>) {
+        match decl {
+            Decl::Class {
+                name,
+                superclass,
+                methods,
+                ..
+            } => {
+                self.write("(class ");
+                self.write(name);
+                if let Some(superclass) = superclass {
+                    self.write(" < ");
+                    self.write(superclass);
+                }
+                for method in methods {
+                    self.write(" ");
+                    self.visit_function(method);
+                }
+                self.write(")");
+            }
+            Decl::Fun { function, .. } => self.visit_function(function),
+            Decl::Var {
+                name, initializer, ..
+            } => {
+                self.write("(var ");
+                self.write(name);
+                if let Some(initializer) = initializer {
+                    self.write(" ");
+                    self.visit_expression(initializer);
+                }
+                self.write(")");
+            }
+            Decl::Stmt(stmt) => self.visit_stmt(stmt),
+        }
+    }
+
+    fn visit_function(&mut self, function: &Function<'a>) {
+        self.write("(fun ");
+        self.write(&function.name);
+        self.write(" ");
+        self.list("params", &function.params, |printer, param| {
+            printer.write(param)
+        });
+        for decl in &function.body {
+            self.write(" ");
+            self.visit_decl(decl);
+        }
+        self.write(")");
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'a>) {
+        match stmt {
+            Stmt::Block { declarations, .. } => {
+                self.list("block", declarations, |printer, decl| {
+                    printer.visit_decl(decl)
+                })
+            }
+            Stmt::Expression { expression, .. } => {
+                self.write("(; ");
+                self.visit_expression(expression);
+                self.write(")");
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.write("(for ");
+                self.optional(initializer, |printer, decl| printer.visit_decl(decl));
+                self.write(" ");
+                self.optional(condition, |printer, e| printer.visit_expression(e));
+                self.write(" ");
+                self.optional(increment, |printer, e| printer.visit_expression(e));
+                self.write(" ");
+                self.visit_stmt(body);
+                self.write(")");
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.write("(if ");
+                self.visit_expression(condition);
+                self.write(" ");
+                self.visit_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.write(" ");
+                    self.visit_stmt(else_branch);
+                }
+                self.write(")");
+            }
+            Stmt::Print { expression, .. } => {
+                self.write("(print ");
+                self.visit_expression(expression);
+                self.write(")");
+            }
+            Stmt::Return { value, .. } => {
+                self.write("(return");
+                if let Some(value) = value {
+                    self.write(" ");
+                    self.visit_expression(value);
+                }
+                self.write(")");
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.write("(while ");
+                self.visit_expression(condition);
+                self.write(" ");
+                self.visit_stmt(body);
+                self.write(")");
+            }
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        match expression {
+            Expression::Assign { name, value, .. } => {
+                self.write("(= ");
+                self.write(name);
+                self.write(" ");
+                self.visit_expression(value);
+                self.write(")");
+            }
             Expression::Binary {
                 left,
                 operator,
                 right,
-            } => write!(f, "({operator} {left} {right})"),
-            Expression::Grouping { expression } => write!(f, "(group {expression})"),
-            Expression::Literal { value } => write!(f, "{value}"),
-            Expression::Unary { operator, right } => write!(f, "({operator} {right})"),
+                ..
+            } => self.list(&operator.to_string(), &[left, right], |printer, e| {
+                printer.visit_expression(e)
+            }),
+            Expression::Call {
+                callee, arguments, ..
+            } => {
+                self.write("(call ");
+                self.visit_expression(callee);
+                for argument in arguments {
+                    self.write(" ");
+                    self.visit_expression(argument);
+                }
+                self.write(")");
+            }
+            Expression::Get { object, name, .. } => {
+                self.write("(. ");
+                self.visit_expression(object);
+                self.write(" ");
+                self.write(name);
+                self.write(")");
+            }
+            Expression::Grouping { expression, .. } => {
+                self.list("group", &[expression], |printer, e| {
+                    printer.visit_expression(e)
+                })
+            }
+            Expression::Index { object, index, .. } => {
+                self.list("[]", &[object, index], |printer, e| {
+                    printer.visit_expression(e)
+                })
+            }
+            Expression::Literal { value, .. } => self.write(value),
+            Expression::Logical {
+                left,
+                operator,
+                right,
+                ..
+            } => self.list(&operator.to_string(), &[left, right], |printer, e| {
+                printer.visit_expression(e)
+            }),
+            Expression::Set {
+                object,
+                name,
+                value,
+                ..
+            } => {
+                self.write("(= (. ");
+                self.visit_expression(object);
+                self.write(" ");
+                self.write(name);
+                self.write(") ");
+                self.visit_expression(value);
+                self.write(")");
+            }
+            Expression::Super { method, .. } => {
+                self.write("(super ");
+                self.write(method);
+                self.write(")");
+            }
+            Expression::This { .. } => self.write("this"),
+            Expression::Unary {
+                operator, right, ..
+            } => self.list(&operator.to_string(), &[right], |printer, e| {
+                printer.visit_expression(e)
+            }),
         }
     }
 }
 
+impl fmt::Display for Expression<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer::print(f, |printer| printer.visit_expression(self))
+    }
+}
+
+impl fmt::Display for Stmt<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer::print(f, |printer| printer.visit_stmt(self))
+    }
+}
+
+impl fmt::Display for Function<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer::print(f, |printer| printer.visit_function(self))
+    }
+}
+
+impl fmt::Display for Decl<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Printer::print(f, |printer| printer.visit_decl(self))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn literal(value: Literal) -> Box<Expression> {
+        Box::new(Expression::Literal {
+            value,
+            span: Span::default(),
+        })
+    }
+
+    fn name(text: &str) -> Name<'_> {
+        Name {
+            text,
+            span: Span::default(),
+        }
+    }
+
     #[test]
     fn test_expression_display() {
         let expr = Expression::Binary {
             left: Box::new(Expression::Unary {
                 operator: UnaryOperator::Minus,
-                right: Box::new(Expression::Literal {
-                    value: Literal::Number(123f64),
-                }),
+                right: literal(Literal::Number(123f64)),
+                span: Span::default(),
             }),
             operator: BinaryOperator::Star,
             right: Box::new(Expression::Grouping {
-                expression: Box::new(Expression::Literal {
-                    value: Literal::Number(45.67),
-                }),
+                expression: literal(Literal::Number(45.67)),
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
 
         let s = format!("{expr}");
         assert_eq!(s, "(* (- 123) (group 45.67))")
     }
+
+    #[test]
+    fn test_declaration_display() {
+        let condition = Expression::Logical {
+            left: literal(Literal::True),
+            operator: LogicalOperator::Or,
+            right: Box::new(Expression::Get {
+                object: Box::new(Expression::This {
+                    span: Span::default(),
+                }),
+                name: name("field"),
+                span: Span::default(),
+            }),
+            span: Span::default(),
+        };
+        let decl = Decl::Fun {
+            function: Function {
+                name: name("f"),
+                params: vec![name("a"), name("b")],
+                body: vec![
+                    Decl::Var {
+                        name: name("c"),
+                        initializer: None,
+                        span: Span::default(),
+                    },
+                    Decl::Stmt(Stmt::If {
+                        condition,
+                        then_branch: Box::new(Stmt::Return {
+                            value: Some(*literal(Literal::Nil)),
+                            span: Span::default(),
+                        }),
+                        else_branch: None,
+                        span: Span::default(),
+                    }),
+                ],
+                span: Span::default(),
+            },
+            span: Span::default(),
+        };
+
+        let s = format!("{decl}");
+        assert_eq!(
+            s,
+            "(fun f (params a b) (var c) (if (or true (. this field)) (return nil)))"
+        )
+    }
 }
@@ -0,0 +1,168 @@
+use crate::compiler::syntax_tree::{Decl, Expression, Function, Stmt};
+
+/// Read-only traversal of the syntax tree. Implementors override the `visit_*` methods for the
+/// nodes they care about, and call the matching `walk_*` function to continue into its children.
+pub trait Visitor<'a> {
+    fn visit_decl(&mut self, decl: &Decl<'a>) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_function(&mut self, function: &Function<'a>) {
+        walk_function(self, function)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'a>) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_expression(&mut self, expression: &Expression<'a>) {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_decl<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, decl: &Decl<'a>) {
+    match decl {
+        Decl::Class { methods, .. } => methods
+            .iter()
+            .for_each(|method| visitor.visit_function(method)),
+        Decl::Fun { function, .. } => visitor.visit_function(function),
+        Decl::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_expression(initializer);
+            }
+        }
+        Decl::Stmt(stmt) => visitor.visit_stmt(stmt),
+    }
+}
+
+pub fn walk_function<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, function: &Function<'a>) {
+    function
+        .body
+        .iter()
+        .for_each(|decl| visitor.visit_decl(decl));
+}
+
+pub fn walk_stmt<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, stmt: &Stmt<'a>) {
+    match stmt {
+        Stmt::Block { declarations, .. } => declarations
+            .iter()
+            .for_each(|decl| visitor.visit_decl(decl)),
+        Stmt::Expression { expression, .. } | Stmt::Print { expression, .. } => {
+            visitor.visit_expression(expression)
+        }
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+            ..
+        } => {
+            if let Some(initializer) = initializer {
+                visitor.visit_decl(initializer);
+            }
+            if let Some(condition) = condition {
+                visitor.visit_expression(condition);
+            }
+            if let Some(increment) = increment {
+                visitor.visit_expression(increment);
+            }
+            visitor.visit_stmt(body);
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        Stmt::While {
+            condition, body, ..
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_stmt(body);
+        }
+    }
+}
+
+pub fn walk_expression<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expression: &Expression<'a>) {
+    match expression {
+        Expression::Assign { value, .. } => visitor.visit_expression(value),
+        Expression::Binary { left, right, .. }
+        | Expression::Logical { left, right, .. }
+        | Expression::Index {
+            object: left,
+            index: right,
+            ..
+        } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expression(callee);
+            arguments
+                .iter()
+                .for_each(|argument| visitor.visit_expression(argument));
+        }
+        Expression::Get { object, .. } => visitor.visit_expression(object),
+        Expression::Grouping { expression, .. } => visitor.visit_expression(expression),
+        Expression::Set { object, value, .. } => {
+            visitor.visit_expression(object);
+            visitor.visit_expression(value);
+        }
+        Expression::Unary { right, .. } => visitor.visit_expression(right),
+        Expression::Literal { .. } | Expression::Super { .. } | Expression::This { .. } => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{parser::Parser, syntax_tree::Literal};
+
+    /// Collects the names of all variables that are read.
+    #[derive(Default)]
+    struct Reads<'a>(Vec<&'a str>);
+
+    impl<'a> Visitor<'a> for Reads<'a> {
+        fn visit_expression(&mut self, expression: &Expression<'a>) {
+            if let Expression::Literal {
+                value: Literal::Identifier(name),
+                ..
+            } = expression
+            {
+                self.0.push(name);
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    #[test]
+    fn visits_all_expressions() {
+        let source = "
+            var a = b;
+            fun f(c) { return c + d(e); }
+            class G { h() { this.i = j; } }
+            for (k = l; m; n = o) if (p) print q; else while (r) s.t;
+        ";
+        let declarations = Parser::new(source).parse().unwrap();
+
+        let mut reads = Reads::default();
+        declarations.iter().for_each(|decl| reads.visit_decl(decl));
+        assert_eq!(
+            reads.0,
+            vec!["b", "c", "d", "e", "j", "l", "m", "o", "p", "q", "r", "s"]
+        );
+    }
+}
@@ -0,0 +1,636 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    compiler::{codegen::compile, parser::Parser},
+    debugger::{Resume, Stepper, Stop},
+    object::Function,
+    vm::{Hook, InterpreterError, VM},
+};
+
+/// The only thread a script runs on.
+const THREAD_ID: u64 = 1;
+/// Variables are requested by reference: this one is the globals, and those above it are the
+/// locals of each frame, counting up from the script's.
+const GLOBALS_REFERENCE: usize = 1;
+/// The exit code of a script that failed, as in sysexits.h.
+const EXIT_SOFTWARE: u64 = 70;
+
+/// Serves the Debug Adapter Protocol, reading requests from `input` and writing responses and
+/// events to `output` until the client disconnects or the input ends.
+///
+/// A script is launched with the `launch` request, and starts running once the client sends
+/// `configurationDone`. While it's stopped at a breakpoint or after a step, the client can inspect
+/// it with `stackTrace`, `scopes` and `variables`, and carry on with `continue`, `next`, `stepIn`
+/// or `stepOut`. What it prints is sent as `output` events.
+pub fn serve<R: BufRead, W: Write + 'static>(input: R, output: W) -> io::Result<()> {
+    let mut server = Server {
+        input,
+        connection: Rc::new(RefCell::new(Connection { output, seq: 0 })),
+        stepper: Stepper::new(false),
+        launched: None,
+        disconnected: false,
+    };
+    while let Some(request) = read_message(&mut server.input)? {
+        match server.handle(&request, None)? {
+            Handled::Done | Handled::Resume(_) => (),
+            Handled::Run => server.run_script()?,
+            Handled::Disconnect => return Ok(()),
+        }
+        if server.disconnected {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Reads a message framed by a `Content-Length` header, or `None` at the end of the input.
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid_data("message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid_data(&e.to_string()))
+}
+
+/// Writes a message with the header that frames it.
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// The client's end, which the VM shares to send what the script prints.
+struct Connection<W> {
+    output: W,
+    seq: u64,
+}
+
+impl<W: Write> Connection<W> {
+    fn send(&mut self, mut message: Json) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Json, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+}
+
+/// Sends what a script prints to the client.
+struct ScriptOutput<W>(Rc<RefCell<Connection<W>>>);
+
+impl<W: Write> Write for ScriptOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let output = String::from_utf8_lossy(buf);
+        let body = json!({ "category": "stdout", "output": output });
+        self.0.borrow_mut().event("output", body)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Launch {
+    path: String,
+    function: Rc<Function>,
+    /// the lines that have code, which are the only ones breakpoints can be set on
+    lines: HashSet<u32>,
+    no_debug: bool,
+}
+
+/// What a request asks for, besides its response.
+enum Handled {
+    Done,
+    Run,
+    Resume(Resume),
+    Disconnect,
+}
+
+struct Server<R, W> {
+    input: R,
+    connection: Rc<RefCell<Connection<W>>>,
+    stepper: Stepper,
+    launched: Option<Launch>,
+    disconnected: bool,
+}
+
+impl<R: BufRead, W: Write + 'static> Server<R, W> {
+    /// Responds to a request. Requests that inspect the script need the VM it's stopped in.
+    fn handle(&mut self, request: &Json, vm: Option<&VM>) -> io::Result<Handled> {
+        let arguments = &request["arguments"];
+        let mut connection = self.connection.borrow_mut();
+        let command = request["command"].as_str().unwrap_or_default();
+        match (command, vm) {
+            ("initialize", _) => {
+                let capabilities = json!({ "supportsConfigurationDoneRequest": true });
+                connection.respond(request, capabilities)?;
+                connection.event("initialized", json!({}))?;
+            }
+            ("launch", _) => match launch(arguments) {
+                Ok(launched) => {
+                    // breakpoints may have been set before the launch
+                    let breakpoints = std::mem::take(&mut self.stepper.breakpoints);
+                    self.stepper = Stepper::new(arguments["stopOnEntry"] == json!(true));
+                    self.stepper.breakpoints = breakpoints;
+                    self.launched = Some(launched);
+                    connection.respond(request, json!({}))?;
+                }
+                Err(message) => connection.fail(request, &message)?,
+            },
+            ("setBreakpoints", _) => {
+                let lines: Vec<u32> = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as u32)
+                    .collect();
+                let code_lines = self.launched.as_ref().map(|launched| &launched.lines);
+                let breakpoints: Vec<_> = lines
+                    .iter()
+                    .map(|line| {
+                        let verified = code_lines.is_none_or(|lines| lines.contains(line));
+                        json!({ "verified": verified, "line": line })
+                    })
+                    .collect();
+                self.stepper.breakpoints = lines.into_iter().collect();
+                connection.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            ("configurationDone", None) if self.launched.is_some() => {
+                connection.respond(request, json!({}))?;
+                return Ok(Handled::Run);
+            }
+            ("configurationDone", _) => connection.respond(request, json!({}))?,
+            ("threads", _) => {
+                let threads = json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] });
+                connection.respond(request, threads)?;
+            }
+            ("disconnect", _) => {
+                connection.respond(request, json!({}))?;
+                self.disconnected = true;
+                return Ok(Handled::Disconnect);
+            }
+            ("continue", Some(_)) => {
+                connection.respond(request, json!({ "allThreadsContinued": true }))?;
+                return Ok(Handled::Resume(Resume::Continue));
+            }
+            ("next", Some(_)) => {
+                connection.respond(request, json!({}))?;
+                return Ok(Handled::Resume(Resume::StepOver));
+            }
+            ("stepIn", Some(_)) => {
+                connection.respond(request, json!({}))?;
+                return Ok(Handled::Resume(Resume::StepInto));
+            }
+            ("stepOut", Some(_)) => {
+                connection.respond(request, json!({}))?;
+                return Ok(Handled::Resume(Resume::StepOut));
+            }
+            ("stackTrace", Some(vm)) => {
+                let path = self.launched.as_ref().map(|launched| &launched.path);
+                let frames: Vec<_> = vm
+                    .frames()
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .map(|(id, frame)| {
+                        json!({
+                            "id": id,
+                            "name": frame.function().to_string(),
+                            "line": frame.metadata().line,
+                            "column": 1,
+                            "source": { "path": path },
+                        })
+                    })
+                    .collect();
+                let body = json!({ "totalFrames": frames.len(), "stackFrames": frames });
+                connection.respond(request, body)?;
+            }
+            ("scopes", Some(_)) => {
+                let frame = arguments["frameId"].as_u64().unwrap_or_default() as usize;
+                let scopes = json!({ "scopes": [
+                    {
+                        "name": "Locals",
+                        "variablesReference": GLOBALS_REFERENCE + 1 + frame,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Globals",
+                        "variablesReference": GLOBALS_REFERENCE,
+                        "expensive": false,
+                    },
+                ]});
+                connection.respond(request, scopes)?;
+            }
+            ("variables", Some(vm)) => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+                match variables(vm, reference as usize) {
+                    Some(variables) => {
+                        connection.respond(request, json!({ "variables": variables }))?
+                    }
+                    None => connection.fail(request, "unknown variables reference")?,
+                }
+            }
+            (
+                "continue" | "next" | "stepIn" | "stepOut" | "stackTrace" | "scopes" | "variables",
+                None,
+            ) => connection.fail(request, "the script isn't stopped")?,
+            (command, _) => {
+                connection.fail(request, &format!("unsupported request '{}'", command))?
+            }
+        }
+        Ok(Handled::Done)
+    }
+
+    fn run_script(&mut self) -> io::Result<()> {
+        let (function, no_debug) = match &self.launched {
+            Some(launched) => (launched.function.clone(), launched.no_debug),
+            None => return Ok(()),
+        };
+        let output = ScriptOutput(self.connection.clone());
+        let mut vm = VM::with_output(Box::new(output));
+        let result = if no_debug {
+            vm.interpret(function)
+        } else {
+            vm.interpret_with_hook(function, self)
+        };
+
+        let mut connection = self.connection.borrow_mut();
+        let exit_code = match result {
+            Ok(()) => Some(0),
+            Err(InterpreterError::Aborted) if self.disconnected => None,
+            Err(e) => {
+                let body = json!({ "category": "stderr", "output": format!("{}\n", e) });
+                connection.event("output", body)?;
+                Some(EXIT_SOFTWARE)
+            }
+        };
+        if let Some(exit_code) = exit_code {
+            connection.event("exited", json!({ "exitCode": exit_code }))?;
+        }
+        connection.event("terminated", json!({}))
+    }
+
+    /// Tells the client the script stopped, and answers its requests until one resumes it.
+    fn stopped(&mut self, vm: &VM, stop: Stop) -> io::Result<Option<Resume>> {
+        let reason = match stop {
+            Stop::Entry => "entry",
+            Stop::Breakpoint => "breakpoint",
+            Stop::Step => "step",
+        };
+        let body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        self.connection.borrow_mut().event("stopped", body)?;
+
+        while let Some(request) = read_message(&mut self.input)? {
+            match self.handle(&request, Some(vm))? {
+                Handled::Done | Handled::Run => (),
+                Handled::Resume(resume) => return Ok(Some(resume)),
+                Handled::Disconnect => return Ok(None),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead, W: Write + 'static> Hook for Server<R, W> {
+    fn before_instruction(&mut self, vm: &VM) -> Result<(), InterpreterError> {
+        if let Some(stop) = self.stepper.check(vm) {
+            match self.stopped(vm, stop) {
+                Ok(Some(resume)) => self.stepper.resume(vm, resume),
+                Ok(None) => return Err(InterpreterError::Aborted),
+                Err(e) => return Err(InterpreterError::Output(e.to_string())),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn launch(arguments: &Json) -> Result<Launch, String> {
+    let path = arguments["program"]
+        .as_str()
+        .ok_or("launch needs the path of a program")?;
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read lox file at {}: {}", path, e))?;
+    let declarations = Parser::new(&source)
+        .parse()
+        .map_err(|e| format!("unable to parse lox file at {}: {}", path, e))?;
+    let function = compile(&declarations).map_err(|errors| {
+        let errors: Vec<_> = errors.iter().map(|error| error.to_string()).collect();
+        format!(
+            "unable to compile lox file at {}: {}",
+            path,
+            errors.join(", ")
+        )
+    })?;
+
+    let mut lines = HashSet::new();
+    code_lines(&function, &mut lines);
+    Ok(Launch {
+        path: path.to_string(),
+        function: Rc::new(function),
+        lines,
+        no_debug: arguments["noDebug"] == json!(true),
+    })
+}
+
+fn code_lines(function: &Function, lines: &mut HashSet<u32>) {
+    lines.extend(&function.chunk.lines);
+    for constant in &function.chunk.constants {
+        if let Some(function) = constant.as_function() {
+            code_lines(function, lines);
+        }
+    }
+}
+
+/// The globals sorted by name, or the slots of a frame.
+fn variables(vm: &VM, reference: usize) -> Option<Vec<Json>> {
+    let variable = |name: &str, value: String| {
+        json!({
+            "name": name,
+            "value": value,
+            "variablesReference": 0,
+        })
+    };
+    if reference == GLOBALS_REFERENCE {
+        let mut globals: Vec<_> = vm.globals.iter().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        let globals = globals
+            .into_iter()
+            .map(|(name, value)| variable(name, value.to_string()))
+            .collect();
+        return Some(globals);
+    }
+
+    let frames = vm.frames();
+    let frame = reference.checked_sub(GLOBALS_REFERENCE + 1)?;
+    let start = frames.get(frame)?.slots();
+    let end = frames
+        .get(frame + 1)
+        .map_or(vm.stack.len(), |next| next.slots());
+    let slots = vm.stack[start..end]
+        .iter()
+        .enumerate()
+        .map(|(slot, value)| variable(&slot.to_string(), value.to_string()))
+        .collect();
+    Some(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::test_support::Output;
+
+    const SOURCE: &str = "\
+fun add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var x = add(1, 2);
+print add(x, 3);";
+
+    fn program(name: &str, source: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rlox-dap-{}-{}.lox", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    /// Plays a recorded session against the server, returning everything it sent.
+    fn session(requests: &[Json]) -> Vec<Json> {
+        let mut input = Vec::new();
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            write_message(&mut input, &request).unwrap();
+        }
+        let output = Output::default();
+        serve(input.as_slice(), output.clone()).unwrap();
+
+        let bytes = output.0.take();
+        let mut reader = bytes.as_slice();
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// A message's kind and its command or event, along with the reason a script stopped.
+    fn summary(message: &Json) -> String {
+        let name = match message["type"].as_str().unwrap() {
+            "response" if message["success"] == json!(false) => {
+                format!("failed {}", message["command"].as_str().unwrap())
+            }
+            "response" => format!("response {}", message["command"].as_str().unwrap()),
+            _ => format!("event {}", message["event"].as_str().unwrap()),
+        };
+        match message["body"]["reason"].as_str() {
+            Some(reason) => format!("{} {}", name, reason),
+            None => name,
+        }
+    }
+
+    fn find<'a>(messages: &'a [Json], command: &str) -> &'a Json {
+        messages
+            .iter()
+            .find(|message| message["command"] == json!(command))
+            .unwrap()
+    }
+
+    #[test]
+    fn breakpoint_session() {
+        let path = program("breakpoint", SOURCE);
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": { "adapterID": "rlox" } }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({
+                "command": "setBreakpoints",
+                "arguments": {
+                    "source": { "path": path },
+                    "breakpoints": [{ "line": 3 }, { "line": 4 }],
+                },
+            }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "threads" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 1 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 3 } }),
+            json!({ "command": "setBreakpoints", "arguments": { "breakpoints": [] } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let summaries: Vec<_> = messages.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "response initialize",
+                "event initialized",
+                "response launch",
+                "response setBreakpoints",
+                "response configurationDone",
+                "event stopped breakpoint",
+                "response threads",
+                "response stackTrace",
+                "response scopes",
+                "response variables",
+                "response setBreakpoints",
+                "response continue",
+                "event output",
+                "event output",
+                "event exited",
+                "event terminated",
+                "response disconnect",
+            ]
+        );
+
+        let breakpoints = &find(&messages, "setBreakpoints")["body"]["breakpoints"];
+        assert_eq!(
+            breakpoints,
+            &json!([{ "verified": true, "line": 3 }, { "verified": false, "line": 4 }])
+        );
+        let frames = &find(&messages, "stackTrace")["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], json!("<fn add>"));
+        assert_eq!(frames[0]["line"], json!(3));
+        assert_eq!(frames[1]["name"], json!("<script>"));
+        assert_eq!(frames[1]["line"], json!(5));
+        let values: Vec<_> = find(&messages, "variables")["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variable| variable["value"].as_str().unwrap())
+            .collect();
+        assert_eq!(values, vec!["<fn add>", "1", "2", "3"]);
+        assert_eq!(messages[12]["body"]["output"], json!("6"));
+        assert_eq!(messages[14]["body"]["exitCode"], json!(0));
+    }
+
+    #[test]
+    fn stepping_session() {
+        let path = program("stepping", SOURCE);
+        let messages = session(&[
+            json!({ "command": "initialize" }),
+            json!({ "command": "launch", "arguments": { "program": path, "stopOnEntry": true } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "next" }),
+            json!({ "command": "stepIn" }),
+            json!({ "command": "stepOut" }),
+            json!({ "command": "stackTrace" }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+
+        let stops: Vec<_> = messages
+            .iter()
+            .map(summary)
+            .filter(|summary| summary.starts_with("event stopped"))
+            .collect();
+        assert_eq!(
+            stops,
+            vec![
+                "event stopped entry",
+                "event stopped step",
+                "event stopped step",
+                "event stopped step",
+            ]
+        );
+        let frames = &find(&messages, "stackTrace")["body"]["stackFrames"];
+        assert_eq!(frames[0]["line"], json!(6));
+        let globals = &find(&messages, "variables")["body"]["variables"];
+        assert_eq!(
+            globals[3],
+            json!({ "name": "x", "value": "3", "variablesReference": 0 })
+        );
+        let last: Vec<_> = messages[messages.len() - 2..].iter().map(summary).collect();
+        assert_eq!(last, vec!["response disconnect", "event terminated"]);
+        assert!(!messages
+            .iter()
+            .any(|message| message["event"] == json!("exited")));
+    }
+
+    #[test]
+    fn errors() {
+        let path = program("errors", "print -nil;");
+        let messages = session(&[
+            json!({ "command": "launch", "arguments": { "program": "/does/not/exist.lox" } }),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "next" }),
+            json!({ "command": "evaluate" }),
+            json!({ "command": "configurationDone" }),
+        ]);
+        let summaries: Vec<_> = messages.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "failed launch",
+                "response launch",
+                "failed next",
+                "failed evaluate",
+                "response configurationDone",
+                "event output",
+                "event exited",
+                "event terminated",
+            ]
+        );
+        assert_eq!(
+            messages[5]["body"]["output"],
+            json!("operand must be a number\n")
+        );
+        assert_eq!(messages[6]["body"]["exitCode"], json!(70));
+    }
+}
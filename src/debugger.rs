@@ -0,0 +1,363 @@
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, Write},
+};
+
+use crate::vm::{CallFrame, Hook, InterpreterError, VM};
+
+const HELP: &str = "\
+break LINE (b)     stop whenever LINE starts running
+delete LINE (d)    remove the breakpoint on LINE
+continue (c)       run until a breakpoint
+step (s)           run until the next line, stepping into calls
+next (n)           run until the next line, stepping over calls
+out (o)            run until the current function returns
+where (w)          show the current position
+backtrace (bt)     show the call stack
+locals (l)         show the slots of the current function
+stack              show the whole stack
+globals (g)        show the global variables
+quit (q)           stop the script
+An empty line repeats the last command.";
+
+/// When to stop next, besides at breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    StepInto,
+    /// stop once a line starts at this call depth or above it
+    StepOver(usize),
+    /// stop once a line starts above this call depth
+    StepOut(usize),
+}
+
+/// How to carry on running a stopped script.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Resume {
+    Continue,
+    StepInto,
+    StepOver,
+    StepOut,
+}
+
+/// Why a script stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stop {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+/// Decides where a script stops: at breakpoints, and at the lines it's stepped to. It only stops
+/// at the start of a line.
+pub(crate) struct Stepper {
+    pub(crate) breakpoints: BTreeSet<u32>,
+    mode: Mode,
+    /// whether to stop before the first line
+    entry: bool,
+    /// the line and position of the previous instruction run by each call in progress
+    previous: Vec<(u32, usize)>,
+}
+
+impl Stepper {
+    pub(crate) fn new(stop_on_entry: bool) -> Stepper {
+        Stepper {
+            breakpoints: BTreeSet::new(),
+            mode: Mode::Continue,
+            entry: stop_on_entry,
+            previous: Vec::new(),
+        }
+    }
+
+    /// Whether to stop before the instruction the VM is about to run.
+    pub(crate) fn check(&mut self, vm: &VM) -> Option<Stop> {
+        let depth = vm.frames().len();
+        let frame = vm.frames().last()?;
+        if !self.starts_line(depth, frame) {
+            return None;
+        }
+        if std::mem::take(&mut self.entry) {
+            return Some(Stop::Entry);
+        }
+        if self.breakpoints.contains(&frame.metadata().line) {
+            return Some(Stop::Breakpoint);
+        }
+        let step = match self.mode {
+            Mode::Continue => false,
+            Mode::StepInto => true,
+            Mode::StepOver(over) => depth <= over,
+            Mode::StepOut(out) => depth < out,
+        };
+        step.then_some(Stop::Step)
+    }
+
+    pub(crate) fn resume(&mut self, vm: &VM, resume: Resume) {
+        let depth = vm.frames().len();
+        self.mode = match resume {
+            Resume::Continue => Mode::Continue,
+            Resume::StepInto => Mode::StepInto,
+            Resume::StepOver => Mode::StepOver(depth),
+            Resume::StepOut => Mode::StepOut(depth),
+        };
+    }
+
+    /// Whether the instruction about to run starts a line: it's the first in a new call, or it's
+    /// on a different line than the call's previous instruction or jumped back to the start of
+    /// the same line. Returning to a caller doesn't start a line.
+    fn starts_line(&mut self, depth: usize, frame: &CallFrame) -> bool {
+        let metadata = frame.metadata();
+        let current = (metadata.line, metadata.pos);
+        self.previous.truncate(depth);
+        if self.previous.len() < depth {
+            self.previous.resize(depth, current);
+            return true;
+        }
+        let (line, pos) = std::mem::replace(&mut self.previous[depth - 1], current);
+        line != current.0 || pos >= current.1
+    }
+}
+
+/// An interactive debugger, which stops the VM at breakpoints and steps through a script line by
+/// line. Commands are read from `input`, starting before the first line runs.
+pub struct Debugger<'a, R, W> {
+    input: R,
+    output: W,
+    source: Vec<&'a str>,
+    stepper: Stepper,
+    last_command: String,
+}
+
+impl<'a, R: BufRead, W: Write> Debugger<'a, R, W> {
+    pub fn new(input: R, output: W, source: Option<&'a str>) -> Debugger<'a, R, W> {
+        Debugger {
+            input,
+            output,
+            source: source
+                .map(|source| source.lines().collect())
+                .unwrap_or_default(),
+            stepper: Stepper::new(true),
+            last_command: String::new(),
+        }
+    }
+
+    /// Reads and runs commands until one of them resumes the script.
+    fn prompt(&mut self, vm: &VM) -> Result<Resume, InterpreterError> {
+        self.show_position(vm).map_err(output_error)?;
+        loop {
+            write!(self.output, "(rlox) ").map_err(output_error)?;
+            self.output.flush().map_err(output_error)?;
+            let mut line = String::new();
+            let read = self
+                .input
+                .read_line(&mut line)
+                .map_err(|e| InterpreterError::Native(e.to_string()))?;
+            if read == 0 {
+                return Err(InterpreterError::Aborted);
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or_default();
+            let line_argument = words.next().map(|word| word.parse::<u32>());
+            match (command, line_argument) {
+                ("break" | "b", Some(Ok(line))) => {
+                    self.stepper.breakpoints.insert(line);
+                    writeln!(self.output, "breakpoint on line {}", line)
+                }
+                ("delete" | "d", Some(Ok(line))) => {
+                    if self.stepper.breakpoints.remove(&line) {
+                        writeln!(self.output, "removed the breakpoint on line {}", line)
+                    } else {
+                        writeln!(self.output, "there's no breakpoint on line {}", line)
+                    }
+                }
+                ("break" | "b" | "delete" | "d", _) => {
+                    writeln!(self.output, "expected a line number")
+                }
+                ("continue" | "c", None) => return Ok(Resume::Continue),
+                ("step" | "s", None) => return Ok(Resume::StepInto),
+                ("next" | "n", None) => return Ok(Resume::StepOver),
+                ("out" | "o", None) => return Ok(Resume::StepOut),
+                ("where" | "w", None) => self.show_position(vm),
+                ("backtrace" | "bt", None) => self.backtrace(vm),
+                ("locals" | "l", None) => {
+                    let frame = vm.frames().last().expect("stopped without a frame");
+                    self.values(vm, frame.slots())
+                }
+                ("stack", None) => self.values(vm, 0),
+                ("globals" | "g", None) => self.globals(vm),
+                ("quit" | "q", None) => return Err(InterpreterError::Aborted),
+                ("help" | "h", None) => writeln!(self.output, "{}", HELP),
+                _ => writeln!(self.output, "unknown command '{}', try 'help'", line.trim()),
+            }
+            .map_err(output_error)?;
+        }
+    }
+
+    fn show_position(&mut self, vm: &VM) -> std::io::Result<()> {
+        let frame = vm.frames().last().expect("stopped without a frame");
+        let metadata = frame.metadata();
+        write!(
+            self.output,
+            "[line {}] in {} at {:04}",
+            metadata.line,
+            frame.function(),
+            metadata.pos
+        )?;
+        self.source_line(metadata.line)
+    }
+
+    fn backtrace(&mut self, vm: &VM) -> std::io::Result<()> {
+        for (i, frame) in vm.frames().iter().rev().enumerate() {
+            let metadata = frame.metadata();
+            writeln!(
+                self.output,
+                "#{} {} [line {}]",
+                i,
+                frame.function(),
+                metadata.line
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the stack from `start` up, by slot relative to `start`.
+    fn values(&mut self, vm: &VM, start: usize) -> std::io::Result<()> {
+        for (slot, value) in vm.stack[start..].iter().enumerate() {
+            writeln!(self.output, "{:>4}: {}", slot, value)?;
+        }
+        Ok(())
+    }
+
+    fn globals(&mut self, vm: &VM) -> std::io::Result<()> {
+        let mut globals: Vec<_> = vm.globals.iter().collect();
+        globals.sort_by_key(|(name, _)| *name);
+        for (name, value) in globals {
+            writeln!(self.output, "{} = {}", name, value)?;
+        }
+        Ok(())
+    }
+
+    fn source_line(&mut self, line: u32) -> std::io::Result<()> {
+        match self.source.get((line as usize).wrapping_sub(1)) {
+            Some(text) => writeln!(self.output, "    {}", text.trim()),
+            None => writeln!(self.output),
+        }
+    }
+}
+
+impl<'a, R: BufRead, W: Write> Hook for Debugger<'a, R, W> {
+    fn before_instruction(&mut self, vm: &VM) -> Result<(), InterpreterError> {
+        if self.stepper.check(vm).is_some() {
+            let resume = self.prompt(vm)?;
+            self.stepper.resume(vm, resume);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        // the script has stopped either way, so there's nowhere to report a failed write
+        writeln!(self.output, "script finished").ok();
+    }
+}
+
+fn output_error(e: std::io::Error) -> InterpreterError {
+    InterpreterError::Output(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::compiler::{codegen::compile, parser::Parser};
+
+    const SOURCE: &str = "\
+fun add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+var x = add(1, 2);
+print add(x, 3);";
+
+    /// Debugs `SOURCE` with the given commands, returning what the debugger wrote after the first
+    /// prompt.
+    fn debug(commands: &str) -> (Result<(), InterpreterError>, String) {
+        let declarations = Parser::new(SOURCE).parse().unwrap();
+        let function = Rc::new(compile(&declarations).unwrap());
+        let mut output = Vec::new();
+        let mut debugger = Debugger::new(commands.as_bytes(), &mut output, Some(SOURCE));
+        let result =
+            VM::with_output(Box::new(std::io::sink())).interpret_with_hook(function, &mut debugger);
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    fn stops(output: &str) -> Vec<&str> {
+        output
+            .lines()
+            .map(|line| line.trim_start_matches("(rlox) "))
+            .filter(|line| line.starts_with("[line"))
+            .collect()
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (result, output) = debug("b 3\nc\nlocals\nbt\nd 3\nc\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            stops(&output),
+            vec![
+                "[line 1] in <script> at 0000    fun add(a, b) {",
+                "[line 3] in <fn add> at 0005    return sum;",
+            ]
+        );
+        assert!(
+            output.contains("   0: <fn add>\n   1: 1\n   2: 2\n   3: 3\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("#0 <fn add> [line 3]\n#1 <script> [line 5]\n"),
+            "{}",
+            output
+        );
+        assert!(output.ends_with("script finished\n"), "{}", output);
+    }
+
+    #[test]
+    fn stepping() {
+        // into the first call and out of it, then over the second call to the end
+        let (result, output) = debug("n\ns\ns\no\nn\n");
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            stops(&output),
+            vec![
+                "[line 1] in <script> at 0000    fun add(a, b) {",
+                "[line 5] in <script> at 0004    var x = add(1, 2);",
+                "[line 2] in <fn add> at 0000    var sum = a + b;",
+                "[line 3] in <fn add> at 0005    return sum;",
+                "[line 6] in <script> at 0014    print add(x, 3);",
+            ]
+        );
+        assert!(output.ends_with("(rlox) script finished\n"), "{}", output);
+    }
+
+    #[test]
+    fn repeat_and_globals() {
+        let (_, output) = debug("n\n\ng\nq\n");
+        assert!(output
+            .contains("add = <fn add>\nclock = <native fn clock>\nlen = <native fn len>\nx = 3\n"));
+    }
+
+    #[test]
+    fn quit() {
+        let (result, output) = debug("help\nfoo\nq\n");
+        assert_eq!(result, Err(InterpreterError::Aborted));
+        assert!(output.contains("unknown command 'foo'"), "{}", output);
+        assert_eq!(debug("").0, Err(InterpreterError::Aborted));
+    }
+}
@@ -1,45 +1,477 @@
-use crate::bytecode::{
-    core::{Chunk, Instruction},
-    parser::{BytecodeParseError, InstructionMetadata},
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    io,
 };
 
-#[derive(Debug, Clone, Copy)]
-pub struct DissemblerPrinter {
+use serde_json::json;
+
+use crate::{
+    bytecode::{
+        core::{Chunk, Instruction},
+        parser::{BytecodeParseError, InstructionMetadata},
+        verifier,
+    },
+    object::Function,
+    value::{Unpacked, Value},
+};
+
+/// Writes human readable listings of chunks. Each instruction is annotated with its net effect on
+/// the stack and the depth of the stack after it runs, jump targets are labelled, and if the
+/// source is known, the first instruction of each line is followed by the line's text.
+#[derive(Debug, Clone)]
+pub struct DissemblerPrinter<'a> {
     prev_line: Option<u32>,
+    source: Vec<&'a str>,
+    labels: BTreeMap<usize, String>,
+    depths: HashMap<usize, usize>,
 }
 
-impl DissemblerPrinter {
-    pub fn new() -> DissemblerPrinter {
-        DissemblerPrinter { prev_line: None }
+impl<'a> DissemblerPrinter<'a> {
+    pub fn new(function: &Function, source: Option<&'a str>) -> DissemblerPrinter<'a> {
+        DissemblerPrinter {
+            prev_line: None,
+            source: source.map_or(Vec::new(), |source| source.lines().collect()),
+            labels: jump_labels(&function.chunk),
+            depths: verifier::stack_depths(function),
+        }
     }
 
-    pub fn dissemble(chunk: &Chunk, name: &str) {
-        println!("== {} ==", name);
-        let mut dissembler = DissemblerPrinter::new();
+    /// Writes a listing of a function's chunk, followed by those of the functions nested in it.
+    pub fn dissemble<W: io::Write>(
+        out: &mut W,
+        function: &Function,
+        source: Option<&str>,
+    ) -> io::Result<()> {
+        writeln!(out, "== {} ==", function)?;
+        let mut dissembler = DissemblerPrinter::new(function, source);
 
-        for (metadata, parsed) in chunk.iter() {
-            dissembler.print(metadata, parsed)
+        for (metadata, parsed) in function.chunk.iter() {
+            dissembler.print(out, metadata, parsed)?;
+        }
+        if let Some(label) = dissembler.labels.get(&function.chunk.code.len()) {
+            writeln!(out, "{}:", label)?;
         }
-        println!()
+        writeln!(out)?;
+
+        for function in nested_functions(&function.chunk) {
+            DissemblerPrinter::dissemble(out, function, source)?;
+        }
+        Ok(())
     }
 
-    pub fn print(
+    pub fn print<W: io::Write>(
         &mut self,
+        out: &mut W,
         metadata: InstructionMetadata,
         parsed: Result<Instruction, BytecodeParseError>,
-    ) {
-        print!("{:04} ", metadata.pos);
+    ) -> io::Result<()> {
+        if let Some(label) = self.labels.get(&metadata.pos) {
+            writeln!(out, "{}:", label)?;
+        }
+        let mut listing = format!("{:04} ", metadata.pos);
 
-        if self.prev_line == Some(metadata.line) {
-            print!("   | ")
+        let source_line = if self.prev_line == Some(metadata.line) {
+            listing.push_str("   | ");
+            None
         } else {
             self.prev_line = Some(metadata.line);
-            print!("{:4} ", metadata.line);
-        }
+            write!(listing, "{:4} ", metadata.line).unwrap();
+            let index = (metadata.line as usize).checked_sub(1);
+            index.and_then(|index| self.source.get(index))
+        };
 
         match parsed {
-            Ok(instruction) => println!("{:?}", instruction),
-            Err(e) => println!("{}", e),
+            Ok(instruction) => {
+                let mnemonic = instruction.op_code().mnemonic();
+                let operands = self.describe_operands(metadata.pos, &instruction);
+                let (needed, pushed) = verifier::stack_effect(&instruction);
+                let effect = pushed as isize - needed as isize;
+                let depth = match self.depths.get(&metadata.pos) {
+                    Some(depth) => (depth + pushed).saturating_sub(needed).to_string(),
+                    // unreachable
+                    None => "?".to_string(),
+                };
+                let text = format!("{:<16} {}", mnemonic, operands);
+                write!(listing, "{:<40} {:>+3} {:>3}", text, effect, depth).unwrap();
+            }
+            Err(e) => listing.push_str(&e.to_string()),
+        }
+        if let Some(source_line) = source_line {
+            write!(listing, "    // {}", source_line.trim()).unwrap();
+        }
+        writeln!(out, "{}", listing.trim_end())
+    }
+
+    fn describe_operands(&self, pos: usize, instruction: &Instruction) -> String {
+        match instruction {
+            Instruction::Constant(value) => format!("'{}'", value),
+            Instruction::GetGlobal(name)
+            | Instruction::DefineGlobal(name)
+            | Instruction::SetGlobal(name)
+            | Instruction::GetProperty(name)
+            | Instruction::SetProperty(name)
+            | Instruction::GetSuper(name)
+            | Instruction::Class(name)
+            | Instruction::Method(name) => format!("'{}'", name),
+            Instruction::GetLocal(byte)
+            | Instruction::SetLocal(byte)
+            | Instruction::GetUpvalue(byte)
+            | Instruction::SetUpvalue(byte)
+            | Instruction::Call(byte) => byte.to_string(),
+            Instruction::Jump(_)
+            | Instruction::JumpIfFalse(_)
+            | Instruction::JumpIfTrue(_)
+            | Instruction::Loop(_) => {
+                let target = jump_target(pos, instruction);
+                match target.and_then(|target| self.labels.get(&target)) {
+                    Some(label) => format!("-> {}", label),
+                    None => "-> outside of the chunk".to_string(),
+                }
+            }
+            Instruction::Invoke(name, arg_count) | Instruction::SuperInvoke(name, arg_count) => {
+                format!("'{}' ({} args)", name, arg_count)
+            }
+            Instruction::Closure(function, captures) => {
+                let captures: Vec<_> = captures
+                    .iter()
+                    .map(|capture| {
+                        let kind = if capture.is_local { "local" } else { "upvalue" };
+                        format!("{} {}", kind, capture.index)
+                    })
+                    .collect();
+                format!("{} [{}]", function, captures.join(", "))
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+/// Names the targets of a chunk's jumps `L0`, `L1` and so on, in the order they appear.
+fn jump_labels(chunk: &Chunk) -> BTreeMap<usize, String> {
+    let mut labels: BTreeMap<usize, String> = chunk
+        .iter()
+        .filter_map(|(metadata, parsed)| jump_target(metadata.pos, &parsed.ok()?))
+        .map(|target| (target, String::new()))
+        .collect();
+    for (i, label) in labels.values_mut().enumerate() {
+        *label = format!("L{}", i);
+    }
+    labels
+}
+
+fn nested_functions(chunk: &Chunk) -> impl Iterator<Item = &Function> {
+    chunk
+        .constants
+        .iter()
+        .filter_map(|constant| constant.as_function())
+}
+
+/// Writes a function's instructions as JSON, followed by those of the functions nested in it.
+///
+/// Each function has its `name`, `arity`, `upvalues` and `instructions`. An instruction has its
+/// `offset`, `line`, `opcode` and raw `operands`, along with the `constant` it refers to and the
+/// `target` of a jump. Instructions that can't be parsed have an `error` instead of an `opcode`.
+pub fn dissemble_json<W: io::Write>(out: &mut W, function: &Function) -> io::Result<()> {
+    let mut functions = Vec::new();
+    function_json(function, &mut functions);
+    serde_json::to_writer_pretty(&mut *out, &json!({ "functions": functions }))?;
+    writeln!(out)
+}
+
+fn function_json(function: &Function, functions: &mut Vec<serde_json::Value>) {
+    let chunk = &function.chunk;
+    let instructions: Vec<_> = chunk
+        .iter()
+        .map(|(metadata, parsed)| {
+            let mut json = json!({ "offset": metadata.pos, "line": metadata.line });
+            let instruction = match parsed {
+                Ok(instruction) => instruction,
+                Err(e) => {
+                    json["error"] = json!(e.to_string());
+                    return json;
+                }
+            };
+
+            json["opcode"] = json!(instruction.op_code().mnemonic());
+            let operand_bytes = &chunk.code[metadata.pos + 1..metadata.pos + instruction.size()];
+            json["operands"] = match instruction {
+                Instruction::Jump(offset)
+                | Instruction::JumpIfFalse(offset)
+                | Instruction::JumpIfTrue(offset)
+                | Instruction::Loop(offset) => json!([offset]),
+                _ => json!(operand_bytes),
+            };
+
+            let constant = match &instruction {
+                Instruction::Constant(value) => Some(value_json(value)),
+                Instruction::Closure(function, _) => Some(json!(function.to_string())),
+                Instruction::GetGlobal(name)
+                | Instruction::DefineGlobal(name)
+                | Instruction::SetGlobal(name)
+                | Instruction::GetProperty(name)
+                | Instruction::SetProperty(name)
+                | Instruction::GetSuper(name)
+                | Instruction::Class(name)
+                | Instruction::Method(name)
+                | Instruction::Invoke(name, _)
+                | Instruction::SuperInvoke(name, _) => Some(json!(&**name)),
+                _ => None,
+            };
+            if let Some(constant) = constant {
+                json["constant"] = constant;
+            }
+            if let Some(target) = jump_target(metadata.pos, &instruction) {
+                json["target"] = json!(target);
+            }
+            json
+        })
+        .collect();
+
+    functions.push(json!({
+        "name": function.to_string(),
+        "arity": function.arity,
+        "upvalues": function.upvalue_count,
+        "instructions": instructions,
+    }));
+    for function in nested_functions(chunk) {
+        function_json(function, functions);
+    }
+}
+
+/// JSON has no infinities or NaN, so those are written as strings, along with functions.
+fn value_json(value: &Value) -> serde_json::Value {
+    match value.unpack() {
+        Unpacked::Nil => serde_json::Value::Null,
+        Unpacked::Bool(b) => json!(b),
+        Unpacked::Number(n) if n.is_finite() => json!(n),
+        Unpacked::String(s) => json!(&*s),
+        _ => json!(value.to_string()),
+    }
+}
+
+/// Writes a chunk in the textual format read by [`assemble`](crate::assembler::assemble).
+///
+/// Jump targets are labelled, and an `@line` annotation is written whenever the source line
+/// changes. Operands are written as values if assembling the instructions would add exactly the
+/// chunk's constants. Otherwise the constants are listed up front and operands refer to them by
+/// index.
+pub fn to_assembly(chunk: &Chunk) -> String {
+    let mut out = String::new();
+    write_chunk(&mut out, chunk, 0);
+    out
+}
+
+fn write_chunk(out: &mut String, chunk: &Chunk, indent: usize) {
+    let decoded: Vec<_> = chunk.iter().collect();
+
+    let mut rebuilt = Chunk::new();
+    for (metadata, parsed) in &decoded {
+        if let Ok(instruction) = parsed {
+            rebuilt.add_instruction(metadata.line, instruction.clone());
+        }
+    }
+    let by_index = rebuilt.constants != chunk.constants;
+    if by_index {
+        for constant in &chunk.constants {
+            write!(out, "{:indent$}.constant ", "").unwrap();
+            write_value(out, constant, indent);
+            out.push('\n');
+        }
+    }
+
+    let labels = jump_labels(chunk);
+
+    let mut prev_line = None;
+    for (metadata, parsed) in &decoded {
+        if let Some(label) = labels.get(&metadata.pos) {
+            writeln!(out, "{:indent$}{}:", "", label).unwrap();
         }
+        if prev_line != Some(metadata.line) {
+            prev_line = Some(metadata.line);
+            writeln!(out, "{:indent$}@{}", "", metadata.line).unwrap();
+        }
+        let instruction = match parsed {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                writeln!(out, "{:1$}; {2}", "", indent + 4, e).unwrap();
+                continue;
+            }
+        };
+
+        let operand_indent = indent + 4;
+        write!(
+            out,
+            "{:operand_indent$}{}",
+            "",
+            instruction.op_code().mnemonic()
+        )
+        .unwrap();
+        let constant = |out: &mut String, value: &Value| {
+            out.push(' ');
+            if by_index {
+                write!(out, "#{}", chunk.code[metadata.pos + 1]).unwrap();
+            } else {
+                write_value(out, value, operand_indent);
+            }
+        };
+        let name = |out: &mut String, name: &str| {
+            out.push(' ');
+            if by_index {
+                write!(out, "#{}", chunk.code[metadata.pos + 1]).unwrap();
+            } else {
+                write_name(out, name);
+            }
+        };
+        match instruction {
+            Instruction::Constant(value) => constant(out, value),
+            Instruction::GetGlobal(n)
+            | Instruction::DefineGlobal(n)
+            | Instruction::SetGlobal(n)
+            | Instruction::GetProperty(n)
+            | Instruction::SetProperty(n)
+            | Instruction::GetSuper(n)
+            | Instruction::Class(n)
+            | Instruction::Method(n) => name(out, n),
+            Instruction::GetLocal(byte)
+            | Instruction::SetLocal(byte)
+            | Instruction::GetUpvalue(byte)
+            | Instruction::SetUpvalue(byte)
+            | Instruction::Call(byte) => write!(out, " {}", byte).unwrap(),
+            Instruction::Jump(_)
+            | Instruction::JumpIfFalse(_)
+            | Instruction::JumpIfTrue(_)
+            | Instruction::Loop(_) => {
+                let target = jump_target(metadata.pos, instruction);
+                match target.and_then(|target| labels.get(&target)) {
+                    Some(label) => write!(out, " {}", label).unwrap(),
+                    None => write!(out, " ; invalid jump target").unwrap(),
+                }
+            }
+            Instruction::Invoke(n, arg_count) | Instruction::SuperInvoke(n, arg_count) => {
+                name(out, n);
+                write!(out, " {}", arg_count).unwrap();
+            }
+            Instruction::Closure(function, captures) => {
+                let captures: Vec<_> = captures
+                    .iter()
+                    .map(|capture| {
+                        let kind = if capture.is_local { "local" } else { "upvalue" };
+                        format!("{} {}", kind, capture.index)
+                    })
+                    .collect();
+                write!(out, " [{}]", captures.join(", ")).unwrap();
+                constant(out, &Value::from(function.clone()));
+            }
+            _ => (),
+        }
+        out.push('\n');
+    }
+    if let Some(label) = labels.get(&chunk.code.len()) {
+        writeln!(out, "{:indent$}{}:", "", label).unwrap();
+    }
+}
+
+fn jump_target(pos: usize, instruction: &Instruction) -> Option<usize> {
+    let next = pos + instruction.size();
+    match instruction {
+        Instruction::Jump(offset)
+        | Instruction::JumpIfFalse(offset)
+        | Instruction::JumpIfTrue(offset) => Some(next + *offset as usize),
+        Instruction::Loop(offset) => next.checked_sub(*offset as usize),
+        _ => None,
+    }
+}
+
+/// Writes a constant. Functions are written with their chunk nested inside braces, which are
+/// indented to match the line they start on.
+fn write_value(out: &mut String, value: &Value, indent: usize) {
+    match value.unpack() {
+        Unpacked::Number(n) => write!(out, "{:?}", n).unwrap(),
+        Unpacked::String(s) => write!(out, "{:?}", s).unwrap(),
+        Unpacked::Function(function) => {
+            out.push_str("fun ");
+            if let Some(name) = &function.name {
+                write_name(out, name);
+                out.push(' ');
+            }
+            writeln!(out, "{} {} {{", function.arity, function.upvalue_count).unwrap();
+            write_chunk(out, &function.chunk, indent);
+            write!(out, "{:indent$}}}", "").unwrap();
+        }
+        _ => write!(out, "{}", value).unwrap(),
+    }
+}
+
+/// Names are written bare if they look like identifiers, and quoted otherwise.
+fn write_name(out: &mut String, name: &str) {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_identifier {
+        out.push_str(name);
+    } else {
+        write!(out, "{:?}", name).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::compiled;
+
+    #[test]
+    fn listing() {
+        let source = "fun f(a) {\n  while (a) a = nil;\n}\nprint f;";
+        let function = compiled(source);
+        let mut out = Vec::new();
+        DissemblerPrinter::dissemble(&mut out, &function, Some(source)).unwrap();
+
+        let expected = "\
+== <script> ==
+0000    1 CLOSURE          <fn f> []                +1   2    // fun f(a) {
+0002    | DEFINE_GLOBAL    'f'                      -1   1
+0004    4 GET_GLOBAL       'f'                      +1   2    // print f;
+0006    | PRINT                                     -1   1
+0007    | NIL                                       +1   2
+0008    | RETURN                                    -1   1
+
+== <fn f> ==
+L0:
+0000    2 GET_LOCAL        1                        +1   3    // while (a) a = nil;
+0002    | JUMP_IF_FALSE    -> L1                    +0   3
+0005    | POP                                       -1   2
+0006    | NIL                                       +1   3
+0007    | SET_LOCAL        1                        +0   3
+0009    | POP                                       -1   2
+0010    | LOOP             -> L0                    +0   2
+L1:
+0013    | POP                                       -1   2
+0014    | NIL                                       +1   3
+0015    | RETURN                                    -1   2
+
+";
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn json() {
+        let function = compiled("var a = 1;\nif (a) print \"b\";");
+        let mut out = Vec::new();
+        dissemble_json(&mut out, &function).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let instructions = &json["functions"][0]["instructions"];
+        assert_eq!(
+            instructions[0],
+            json!({ "offset": 0, "line": 1, "opcode": "CONSTANT", "operands": [0], "constant": 1.0 })
+        );
+        assert_eq!(
+            instructions[3],
+            json!({ "offset": 6, "line": 2, "opcode": "JUMP_IF_FALSE", "operands": [7], "target": 16 })
+        );
+        assert_eq!(json["functions"][0]["name"], "<script>");
     }
 }
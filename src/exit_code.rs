@@ -0,0 +1,90 @@
+use std::{io, string::FromUtf8Error};
+
+use crate::{
+    bytecode::{parser::BytecodeParseError, verifier::VerifyError},
+    compiler::{codegen::CompileError, parser::ParseError, scanner::ScannerError},
+    repl::ReplError,
+    vm::InterpreterError,
+};
+
+// The codes a process exits with when it fails, following the sysexits.h conventions used by clox.
+
+/// Anything else went wrong, such as a bad combination of arguments.
+pub const FAILURE: i32 = 1;
+/// The input couldn't be scanned, parsed or compiled.
+pub const DATA_ERROR: i32 = 65;
+/// The script failed while running.
+pub const SOFTWARE: i32 = 70;
+/// Reading or writing a file or stream failed.
+pub const IO_ERROR: i32 = 74;
+
+/// An error that knows which code a process should exit with when it's the cause of a failure.
+pub trait ToExitCode {
+    fn exit_code(&self) -> i32;
+}
+
+impl ToExitCode for ScannerError {
+    fn exit_code(&self) -> i32 {
+        DATA_ERROR
+    }
+}
+
+impl ToExitCode for ParseError {
+    fn exit_code(&self) -> i32 {
+        DATA_ERROR
+    }
+}
+
+impl ToExitCode for CompileError {
+    fn exit_code(&self) -> i32 {
+        DATA_ERROR
+    }
+}
+
+impl ToExitCode for BytecodeParseError {
+    fn exit_code(&self) -> i32 {
+        DATA_ERROR
+    }
+}
+
+impl ToExitCode for VerifyError {
+    fn exit_code(&self) -> i32 {
+        DATA_ERROR
+    }
+}
+
+impl ToExitCode for FromUtf8Error {
+    fn exit_code(&self) -> i32 {
+        DATA_ERROR
+    }
+}
+
+impl ToExitCode for InterpreterError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            // compiled files are checked as they're loaded, so this is bad input
+            InterpreterError::ParseError(_) => DATA_ERROR,
+            InterpreterError::Output(_) => IO_ERROR,
+            InterpreterError::Exit(code) => *code,
+            _ => SOFTWARE,
+        }
+    }
+}
+
+impl ToExitCode for ReplError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            ReplError::Parse(e) => e.exit_code(),
+            ReplError::Compile(_) => DATA_ERROR,
+            ReplError::Runtime(e) => e.exit_code(),
+            ReplError::Load(..) | ReplError::Output(_) => IO_ERROR,
+            ReplError::UnknownCommand(_) | ReplError::Usage(_) => DATA_ERROR,
+        }
+    }
+}
+
+impl ToExitCode for io::Error {
+    fn exit_code(&self) -> i32 {
+        IO_ERROR
+    }
+}
@@ -0,0 +1,610 @@
+use crate::compiler::{
+    parser::{ParseError, Parser},
+    scanner::{Comment, LosslessToken},
+    syntax_tree::{Decl, Expression, Function, Literal, Name, Stmt},
+};
+
+/// Lines are wrapped when they would be longer than this.
+pub const MAX_WIDTH: usize = 80;
+const INDENT: usize = 4;
+
+/// Formats lox source into the canonical style, keeping all comments.
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let mut parser = Parser::new(source);
+    let declarations = parser.parse()?;
+
+    let mut formatter = Formatter::new(source, parser.tokens());
+    for decl in &declarations {
+        formatter.declaration(decl);
+    }
+    Ok(formatter.finish())
+}
+
+/// A layout document, in the style of Wadler's "prettier printer". Groups are printed on a single
+/// line if they fit, otherwise their lines are broken.
+#[derive(Debug, Clone)]
+enum Doc {
+    Text(String),
+    /// A space, or a newline if the enclosing group is broken.
+    Line,
+    /// Nothing, or a newline if the enclosing group is broken.
+    SoftLine,
+    /// Indents any lines broken inside of it.
+    Nest(Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+impl Doc {
+    fn text<S: Into<String>>(s: S) -> Doc {
+        Doc::Text(s.into())
+    }
+
+    fn nest(doc: Doc) -> Doc {
+        Doc::Nest(Box::new(doc))
+    }
+
+    fn group(doc: Doc) -> Doc {
+        Doc::Group(Box::new(doc))
+    }
+
+    fn concat(docs: Vec<Doc>) -> Doc {
+        Doc::Concat(docs)
+    }
+
+    /// A comma separated list between `open` and `close`, broken one item per line if it doesn't
+    /// fit.
+    fn list(open: &str, items: Vec<Doc>, close: &str) -> Doc {
+        let mut inner = vec![Doc::SoftLine];
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                inner.push(Doc::text(","));
+                inner.push(Doc::Line);
+            }
+            inner.push(item);
+        }
+        Doc::group(Doc::concat(vec![
+            Doc::text(open),
+            Doc::nest(Doc::concat(inner)),
+            Doc::SoftLine,
+            Doc::text(close),
+        ]))
+    }
+
+    /// Renders the document starting at `column`, with broken lines indented relative to `indent`.
+    fn render(&self, indent: usize, mut column: usize) -> String {
+        let mut output = String::new();
+        let mut stack = vec![(indent, Mode::Break, self)];
+
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => {
+                    output.push_str(s);
+                    column += s.len();
+                }
+                Doc::Line | Doc::SoftLine if mode == Mode::Break => {
+                    output.push('\n');
+                    output.push_str(&" ".repeat(indent));
+                    column = indent;
+                }
+                Doc::Line => {
+                    output.push(' ');
+                    column += 1;
+                }
+                Doc::SoftLine => (),
+                Doc::Nest(doc) => stack.push((indent + INDENT, mode, doc)),
+                Doc::Group(doc) => {
+                    let mode = if fits(MAX_WIDTH as isize - column as isize, doc, &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            }
+        }
+
+        output
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Whether `doc` printed flat, followed by the rest of the current line, fits in `width` columns.
+fn fits(mut width: isize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        if width < 0 {
+            return false;
+        }
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(s) => width -= s.len() as isize,
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::Line => width -= 1,
+            Doc::SoftLine => (),
+            Doc::Nest(doc) | Doc::Group(doc) => stack.push((mode, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+        }
+    }
+}
+
+fn expression_doc(expression: &Expression) -> Doc {
+    match expression {
+        Expression::Assign { name, value, .. } => assignment_doc(Doc::text(name.text), value),
+        Expression::Binary {
+            left,
+            operator,
+            right,
+            ..
+        } => binary_doc(left, &operator.to_string(), right),
+        Expression::Call {
+            callee, arguments, ..
+        } => Doc::concat(vec![
+            expression_doc(callee),
+            Doc::list("(", arguments.iter().map(expression_doc).collect(), ")"),
+        ]),
+        Expression::Get { object, name, .. } => property_doc(object, name),
+        Expression::Grouping { expression, .. } => Doc::concat(vec![
+            Doc::text("("),
+            expression_doc(expression),
+            Doc::text(")"),
+        ]),
+        Expression::Index { object, index, .. } => Doc::concat(vec![
+            expression_doc(object),
+            Doc::text("["),
+            expression_doc(index),
+            Doc::text("]"),
+        ]),
+        Expression::Literal { value, .. } => Doc::text(literal(value)),
+        Expression::Logical {
+            left,
+            operator,
+            right,
+            ..
+        } => binary_doc(left, &operator.to_string(), right),
+        Expression::Set {
+            object,
+            name,
+            value,
+            ..
+        } => assignment_doc(property_doc(object, name), value),
+        Expression::Super { method, .. } => Doc::text(format!("super.{method}")),
+        Expression::This { .. } => Doc::text("this"),
+        Expression::Unary {
+            operator, right, ..
+        } => Doc::concat(vec![Doc::text(operator.to_string()), expression_doc(right)]),
+    }
+}
+
+fn binary_doc(left: &Expression, operator: &str, right: &Expression) -> Doc {
+    Doc::group(Doc::concat(vec![
+        expression_doc(left),
+        Doc::text(format!(" {operator}")),
+        Doc::nest(Doc::concat(vec![Doc::Line, expression_doc(right)])),
+    ]))
+}
+
+fn assignment_doc(target: Doc, value: &Expression) -> Doc {
+    Doc::group(Doc::concat(vec![
+        target,
+        Doc::text(" ="),
+        Doc::nest(Doc::concat(vec![Doc::Line, expression_doc(value)])),
+    ]))
+}
+
+fn property_doc(object: &Expression, name: &Name) -> Doc {
+    Doc::concat(vec![expression_doc(object), Doc::text(format!(".{name}"))])
+}
+
+fn literal(value: &Literal) -> String {
+    match value {
+        Literal::String(s) => format!("\"{s}\""),
+        _ => value.to_string(),
+    }
+}
+
+fn var_doc(name: &Name, initializer: &Option<Expression>) -> Doc {
+    match initializer {
+        Some(initializer) => Doc::concat(vec![
+            assignment_doc(Doc::text(format!("var {name}")), initializer),
+            Doc::text(";"),
+        ]),
+        None => Doc::text(format!("var {name};")),
+    }
+}
+
+/// The doc for statements without any nested statements, which are printed as a single group.
+fn simple_doc(stmt: &Stmt) -> Option<Doc> {
+    let doc = match stmt {
+        Stmt::Expression { expression, .. } => {
+            Doc::concat(vec![expression_doc(expression), Doc::text(";")])
+        }
+        Stmt::Print { expression, .. } => Doc::concat(vec![
+            Doc::text("print "),
+            expression_doc(expression),
+            Doc::text(";"),
+        ]),
+        Stmt::Return { value, .. } => match value {
+            Some(value) => Doc::concat(vec![
+                Doc::text("return "),
+                expression_doc(value),
+                Doc::text(";"),
+            ]),
+            None => Doc::text("return;"),
+        },
+        _ => return None,
+    };
+    Some(doc)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<Comment<'a>>,
+    next_comment: usize,
+    output: String,
+    indent: usize,
+    /// The end of the last statement or comment written, to preserve blank lines between them.
+    last_end: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str, tokens: &[LosslessToken<'a>]) -> Formatter<'a> {
+        Formatter {
+            source,
+            comments: tokens.iter().flat_map(LosslessToken::comments).collect(),
+            next_comment: 0,
+            output: String::new(),
+            indent: 0,
+            last_end: None,
+        }
+    }
+
+    fn peek_comment(&self) -> Option<Comment<'a>> {
+        self.comments.get(self.next_comment).copied()
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.output.is_empty() || self.output.ends_with('\n')
+    }
+
+    fn write_doc(&mut self, doc: Doc) {
+        if self.at_line_start() {
+            self.output.push_str(&" ".repeat(self.indent));
+        }
+        let line_start = self.output.rfind('\n').map_or(0, |i| i + 1);
+        let column = self.output.len() - line_start;
+        self.output.push_str(&doc.render(self.indent, column));
+    }
+
+    /// Finishes the current line, pulling up a comment that followed `end` on the same line.
+    fn end_line(&mut self, end: usize) {
+        if self.at_line_start() {
+            return;
+        }
+        self.last_end = Some(end);
+
+        if let Some(comment) = self
+            .peek_comment()
+            .filter(|c| c.trailing && c.offset >= end && self.only_spaces_between(end, c.offset))
+        {
+            self.next_comment += 1;
+            self.output.push(' ');
+            self.output.push_str(comment.text);
+            self.last_end = Some(comment.offset + comment.text.len());
+        }
+        self.output.push('\n');
+    }
+
+    fn only_spaces_between(&self, start: usize, end: usize) -> bool {
+        self.source[start..end]
+            .chars()
+            .all(|c| c.is_whitespace() && c != '\n')
+    }
+
+    /// Keeps (at most) one blank line if the source had any between the last item and `offset`.
+    fn blank_line_before(&mut self, offset: usize) {
+        if let Some(last_end) = self.last_end.filter(|&end| end < offset) {
+            if self.source[last_end..offset].matches('\n').count() > 1 {
+                self.output.push('\n');
+            }
+        }
+    }
+
+    /// Writes all comments before `offset` on their own lines.
+    fn comments_before(&mut self, offset: usize, keep_blank_lines: bool) {
+        while let Some(comment) = self.peek_comment().filter(|c| c.offset < offset) {
+            self.next_comment += 1;
+            if !self.at_line_start() {
+                self.output.push('\n');
+            }
+            if keep_blank_lines {
+                self.blank_line_before(comment.offset);
+            }
+            self.output.push_str(&" ".repeat(self.indent));
+            self.output.push_str(comment.text);
+            self.output.push('\n');
+            self.last_end = Some(comment.offset + comment.text.len());
+        }
+    }
+
+    /// Writes a declaration on its own line(s), along with the comments before and after it.
+    fn declaration(&mut self, decl: &Decl) {
+        let span = decl.span();
+        self.comments_before(span.start, true);
+        self.blank_line_before(span.start);
+
+        match decl {
+            Decl::Class {
+                name,
+                superclass,
+                methods,
+                span,
+            } => {
+                let header = match superclass {
+                    Some(superclass) => format!("class {name} < {superclass}"),
+                    None => format!("class {name}"),
+                };
+                self.write_doc(Doc::text(header));
+                self.braces(span.end, |formatter| {
+                    for method in methods {
+                        formatter.comments_before(method.span.start, true);
+                        formatter.blank_line_before(method.span.start);
+                        formatter.function(method, "");
+                        formatter.end_line(method.span.end);
+                    }
+                });
+            }
+            Decl::Fun { function, .. } => self.function(function, "fun "),
+            Decl::Var {
+                name, initializer, ..
+            } => self.simple(var_doc(name, initializer), span.end),
+            Decl::Stmt(stmt) => self.statement(stmt),
+        }
+
+        self.end_line(span.end);
+    }
+
+    /// Writes a statement, leaving the output after its last character.
+    fn statement(&mut self, stmt: &Stmt) {
+        if let Some(doc) = simple_doc(stmt) {
+            self.simple(doc, stmt.span().end);
+            return;
+        }
+
+        match stmt {
+            Stmt::Block { declarations, span } => self.block(declarations, span.end),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                let mut header = vec![Doc::text("for (")];
+                match initializer.as_deref() {
+                    Some(Decl::Var {
+                        name, initializer, ..
+                    }) => header.push(var_doc(name, initializer)),
+                    Some(Decl::Stmt(stmt)) => header.extend(simple_doc(stmt)),
+                    _ => header.push(Doc::text(";")),
+                }
+                if let Some(condition) = condition {
+                    header.push(Doc::text(" "));
+                    header.push(expression_doc(condition));
+                }
+                header.push(Doc::text(";"));
+                if let Some(increment) = increment {
+                    header.push(Doc::text(" "));
+                    header.push(expression_doc(increment));
+                }
+                header.push(Doc::text(")"));
+                self.write_doc(Doc::concat(header));
+                self.body(body);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.write_doc(Doc::concat(vec![
+                    Doc::text("if ("),
+                    expression_doc(condition),
+                    Doc::text(")"),
+                ]));
+                self.body(then_branch);
+
+                if let Some(else_branch) = else_branch {
+                    if self.at_line_start() {
+                        self.write_doc(Doc::text("else"));
+                    } else {
+                        self.output.push_str(" else");
+                    }
+                    match else_branch.as_ref() {
+                        nested @ Stmt::If { .. } => {
+                            self.output.push(' ');
+                            self.statement(nested);
+                        }
+                        else_branch => self.body(else_branch),
+                    }
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.write_doc(Doc::concat(vec![
+                    Doc::text("while ("),
+                    expression_doc(condition),
+                    Doc::text(")"),
+                ]));
+                self.body(body);
+            }
+            Stmt::Expression { .. } | Stmt::Print { .. } | Stmt::Return { .. } => {
+                unreachable!("simple statements have a doc")
+            }
+        }
+    }
+
+    fn simple(&mut self, doc: Doc, end: usize) {
+        // comments inside of a statement are moved above it
+        self.comments_before(end, false);
+        self.write_doc(doc);
+    }
+
+    fn function(&mut self, function: &Function, keyword: &str) {
+        let params = function
+            .params
+            .iter()
+            .map(|param| Doc::text(param.text))
+            .collect();
+        self.write_doc(Doc::concat(vec![
+            Doc::text(format!("{keyword}{}", function.name)),
+            Doc::list("(", params, ")"),
+        ]));
+        self.block(&function.body, function.span.end);
+    }
+
+    /// Writes the body of a control flow statement. Blocks stay on the same line as the header,
+    /// other statements are indented on the next line.
+    fn body(&mut self, body: &Stmt) {
+        if let Stmt::Block { declarations, span } = body {
+            self.block(declarations, span.end);
+            return;
+        }
+
+        let span = body.span();
+        self.output.push('\n');
+        self.last_end = None;
+        self.indent += INDENT;
+        self.comments_before(span.start, true);
+        self.statement(body);
+        self.end_line(span.end);
+        self.indent -= INDENT;
+    }
+
+    /// Writes a block whose closing brace ends at `end`.
+    fn block(&mut self, declarations: &[Decl], end: usize) {
+        self.braces(end, |formatter| {
+            for decl in declarations {
+                formatter.declaration(decl);
+            }
+        });
+    }
+
+    /// Writes braces around `contents`, which should write whole lines. Empty braces are kept on
+    /// one line.
+    fn braces<F: FnOnce(&mut Self)>(&mut self, end: usize, contents: F) {
+        if !self.at_line_start() {
+            self.output.push(' ');
+        }
+        self.write_doc(Doc::text("{\n"));
+        let contents_start = self.output.len();
+        self.indent += INDENT;
+        self.last_end = None;
+
+        contents(self);
+        self.comments_before(end, true);
+
+        self.indent -= INDENT;
+        if self.output.len() == contents_start {
+            self.output.pop();
+            self.output.push('}');
+        } else {
+            self.write_doc(Doc::text("}"));
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.comments_before(usize::MAX, true);
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_case::test_case;
+
+    #[test_case("1+2*3;", "1 + 2 * 3;\n"; "spacing")]
+    #[test_case("  a ;\n\n\n\nb;c;", "a;\n\nb;\nc;\n"; "blank lines")]
+    #[test_case("- ( 1 )  ;", "-(1);\n"; "unary and grouping")]
+    #[test_case("\"a\"  ==  .5;", "\"a\" == 0.5;\n"; "literals")]
+    #[test_case("", ""; "empty")]
+    fn format(input: &str, expected: &str) {
+        assert_eq!(format_source(input).unwrap(), expected)
+    }
+
+    #[test_case("var a=1;var b;print a and b or !true;", "var a = 1;\nvar b;\nprint a and b or !true;\n"; "simple statements")]
+    #[test_case("{var a;{}}", "{\n    var a;\n    {}\n}\n"; "blocks")]
+    #[test_case("if(a)print 1;else if(b){print 2;}else print 3;", "if (a)\n    print 1;\nelse if (b) {\n    print 2;\n} else\n    print 3;\n"; "if else")]
+    #[test_case("while(a<b){a=a+1;}", "while (a < b) {\n    a = a + 1;\n}\n"; "while loop")]
+    #[test_case("for(var i=0;i<3;i=i+1)print i;for(;;){}", "for (var i = 0; i < 3; i = i + 1)\n    print i;\nfor (;;) {}\n"; "for loop")]
+    #[test_case("print args( )[ i+1 ];", "print args()[i + 1];\n"; "index")]
+    #[test_case("fun f(a,b){return a(b).c;}", "fun f(a, b) {\n    return a(b).c;\n}\n"; "function")]
+    #[test_case("class A<B{init(){this.a=super.b();}\n\nc(){return;}}", "class A < B {\n    init() {\n        this.a = super.b();\n    }\n\n    c() {\n        return;\n    }\n}\n"; "class")]
+    fn statements(input: &str, expected: &str) {
+        assert_eq!(format_source(input).unwrap(), expected)
+    }
+
+    #[test_case("// header\n\n1;  // one\n// two\n2 +\n// inside\n3;\n// footer", "// header\n\n1; // one\n// two\n// inside\n2 + 3;\n// footer\n"; "comments")]
+    #[test_case("a;\n\n  // end", "a;\n\n// end\n"; "blank line before comment")]
+    #[test_case("{ // open\n  a; // a\n\n  // close\n} // end", "{\n    // open\n    a; // a\n\n    // close\n} // end\n"; "comments in blocks")]
+    #[test_case("if (a) // then\n  b;\nelse {}", "if (a)\n    // then\n    b;\nelse {}\n"; "comments in bodies")]
+    #[test_case("fun f() { a; } // f", "fun f() {\n    a;\n} // f\n"; "comment after closing brace")]
+    fn comments(input: &str, expected: &str) {
+        assert_eq!(format_source(input).unwrap(), expected)
+    }
+
+    #[test]
+    fn wrapping() {
+        let input = format!("{a} + {a} * {a} - {a};", a = "variable_with_a_long_name");
+        let expected = "variable_with_a_long_name +\n    variable_with_a_long_name * variable_with_a_long_name -\n    variable_with_a_long_name;\n";
+        assert_eq!(format_source(&input).unwrap(), expected);
+    }
+
+    #[test]
+    fn wrapping_nested() {
+        let input = format!(
+            "{a} * ({a} - {a}) == {a} + {a} + {a};",
+            a = "long_name_number_one"
+        );
+        let expected = "long_name_number_one * (long_name_number_one - long_name_number_one) ==\n    long_name_number_one + long_name_number_one + long_name_number_one;\n";
+        assert_eq!(format_source(&input).unwrap(), expected);
+    }
+
+    #[test]
+    fn wrapping_arguments() {
+        let input = format!(
+            "{{ function({a}, {a}, {a}); }}",
+            a = "argument_with_a_long_name"
+        );
+        let expected = "{\n    function(\n        argument_with_a_long_name,\n        argument_with_a_long_name,\n        argument_with_a_long_name\n    );\n}\n";
+        assert_eq!(format_source(&input).unwrap(), expected);
+    }
+
+    #[test_case("1+2*3;")]
+    #[test_case("// a\n1;//b\n\n\n( 2 )\n// c\n== 3;//d\n//e")]
+    #[test_case("aaaaaaaaaaaaaaaaaaaa + bbbbbbbbbbbbbbbbbbbbbbbbb + cccccccccccccccccccccccccccc + ddddddddddddddddddd + eeeeeeeeeeeeeeeeeeeeeeeee;")]
+    #[test_case("class A < B { // c\n init(a, b) { this.a = a; } // d\n\n\n m() { if (a) { return; } else if (b) print 1; else { while (true) print 2; } } }")]
+    #[test_case("fun f() {\n  for (a = 1; ; ) // x\n    f(aaaaaaaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbbbbbbb, cccccccccccccccccccccccccc, dddd);\n}")]
+    fn idempotent(input: &str) {
+        let once = format_source(input).unwrap();
+        let twice = format_source(&once).unwrap();
+        assert_eq!(once, twice)
+    }
+}
@@ -0,0 +1,191 @@
+use std::{io::Write, rc::Rc};
+
+use thiserror::Error;
+
+use crate::{
+    compiler::{
+        codegen::{compile_returning, CompileError},
+        parser::{ParseError, Parser},
+        syntax_tree::{Decl, Stmt},
+    },
+    value::Value,
+    vm::{InterpreterError, Limits, VM},
+};
+
+/// Why lox code given to an [`Interpreter`] couldn't be run to the end.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum LoxError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))]
+    Compile(Vec<CompileError>),
+    #[error(transparent)]
+    Runtime(#[from] InterpreterError),
+}
+
+/// Runs lox code from a rust program. Globals defined by one call to [`eval`](Interpreter::eval)
+/// can be used by the next, and the program can read and write them, or give scripts natives to
+/// call back into it.
+///
+/// ```
+/// use rlox::{Interpreter, Value};
+///
+/// let mut lox = Interpreter::new();
+/// lox.register_native("double", 1, |args| {
+///     let n = args[0].as_number().unwrap_or_default();
+///     Ok(Value::from(n * 2.0))
+/// });
+/// lox.set_global("x", 20.0);
+/// lox.eval("var y = double(x) + 2;").unwrap();
+/// assert_eq!(lox.get_global("y"), Some(Value::from(42.0)));
+/// assert_eq!(lox.eval("y / 2").unwrap(), Value::from(21.0));
+/// ```
+pub struct Interpreter {
+    vm: VM,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter whose scripts print to stdout.
+    pub fn new() -> Interpreter {
+        Interpreter { vm: VM::new() }
+    }
+
+    /// Creates an interpreter whose scripts print to `output` rather than stdout.
+    pub fn with_output(output: Box<dyn Write>) -> Interpreter {
+        Interpreter {
+            vm: VM::with_output(output),
+        }
+    }
+
+    /// Runs lox code, which is either a program or a single expression without a semicolon.
+    /// Returns the value of the program's last statement when that's an expression, and nil
+    /// otherwise.
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        let declarations = parse_entry(source)?;
+        let function = compile_returning(&declarations).map_err(LoxError::Compile)?;
+        Ok(self.vm.evaluate(Rc::new(function))?)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.vm.globals.get(name).cloned()
+    }
+
+    pub fn set_global<V: Into<Value>>(&mut self, name: &str, value: V) {
+        self.vm.globals.insert(Rc::from(name), value.into());
+    }
+
+    /// Defines a global function implemented in rust, which scripts call with `arity` arguments.
+    /// Errors it returns stop the script.
+    pub fn register_native<F>(&mut self, name: &str, arity: u8, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, InterpreterError> + 'static,
+    {
+        self.vm.define_native(name, arity, function);
+    }
+
+    /// Bounds what each call to [`eval`](Interpreter::eval) may use, for running untrusted code.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.vm.limits = limits;
+    }
+}
+
+/// Parses lox code as a program, or failing that, as an expression without a semicolon after it.
+pub(crate) fn parse_entry(source: &str) -> Result<Vec<Decl<'_>>, ParseError> {
+    match Parser::new(source).parse() {
+        Ok(declarations) => Ok(declarations),
+        Err(error) => {
+            let mut parser = Parser::new(source);
+            match parser.expression() {
+                Ok(expression) if parser.is_at_end() => {
+                    let span = expression.span();
+                    Ok(vec![Decl::Stmt(Stmt::Expression { expression, span })])
+                }
+                _ => Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Output;
+    use test_case::test_case;
+
+    #[test_case("1 + 2", Value::from(3.0); "expression")]
+    #[test_case("var a = \"x\"; a + a;", Value::from("xx"); "last statement")]
+    #[test_case("var a = 1;", Value::NIL; "declaration")]
+    #[test_case("", Value::NIL; "empty")]
+    #[test_case("fun f(n) { return n * 2; } f(4);", Value::from(8.0); "call")]
+    fn eval(source: &str, expected: Value) {
+        assert_eq!(Interpreter::new().eval(source), Ok(expected));
+    }
+
+    #[test_case("1 +", |e| matches!(e, LoxError::Parse(_)); "parse")]
+    #[test_case("return 1;", |e| matches!(e, LoxError::Compile(errors) if errors.len() == 1); "compile")]
+    #[test_case("-nil", |e| *e == LoxError::Runtime(InterpreterError::OperandMustBeNumber); "runtime")]
+    fn eval_error(source: &str, expected: fn(&LoxError) -> bool) {
+        let error = Interpreter::new().eval(source).unwrap_err();
+        assert!(expected(&error), "{:?}", error);
+    }
+
+    #[test]
+    fn globals_and_natives() {
+        let output = Output::default();
+        let mut lox = Interpreter::with_output(Box::new(output.clone()));
+        lox.set_global("greeting", "hello");
+        lox.register_native("shout", 1, |args| match args[0].as_string() {
+            Some(s) => Ok(Value::from(s.to_uppercase().as_str())),
+            None => Err(InterpreterError::Native("shout needs a string".to_string())),
+        });
+
+        lox.eval("var loud = shout(greeting); print loud;").unwrap();
+        assert_eq!(lox.get_global("loud"), Some(Value::from("HELLO")));
+        assert_eq!(lox.get_global("quiet"), None);
+        assert_eq!(
+            lox.eval("shout(1)"),
+            Err(LoxError::Runtime(InterpreterError::Native(
+                "shout needs a string".to_string()
+            )))
+        );
+        // a failed script doesn't lose the globals
+        assert_eq!(lox.eval("loud").unwrap(), Value::from("HELLO"));
+        assert_eq!(String::from_utf8(output.0.take()).unwrap(), "HELLO\n");
+    }
+
+    #[test]
+    fn limits() {
+        let mut lox = Interpreter::new();
+        lox.set_limits(Limits {
+            instructions: Some(1000),
+            ..Limits::default()
+        });
+        assert_eq!(
+            lox.eval("while (true) {}"),
+            Err(LoxError::Runtime(InterpreterError::InstructionLimit(1000)))
+        );
+        assert_eq!(lox.eval("1"), Ok(Value::from(1.0)));
+    }
+
+    #[test]
+    fn too_deeply_nested() {
+        let source = "(".repeat(1000) + "1" + &")".repeat(1000);
+        // with as much stack as the main thread has, like the parser's tests
+        let result = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(move || Interpreter::new().eval(&source).map(|_| ()))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(matches!(
+            result,
+            Err(LoxError::Parse(ParseError::TooDeeplyNested(_)))
+        ));
+    }
+}
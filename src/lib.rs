@@ -0,0 +1,31 @@
+//! A lox interpreter, compiler and bytecode VM.
+//!
+//! Rust programs run lox code through an [`Interpreter`], which keeps the globals scripts define
+//! between calls to [`eval`](Interpreter::eval) and lets the program read and write them, or give
+//! scripts natives to call.
+
+mod assembler;
+mod bench;
+mod bytecode;
+#[doc(hidden)]
+pub mod cli;
+mod compiler;
+mod dap;
+mod debugger;
+mod dissembler;
+mod exit_code;
+mod formatter;
+mod interpreter;
+mod lsp;
+mod object;
+mod process;
+mod profiler;
+mod repl;
+#[cfg(test)]
+mod test_support;
+mod value;
+mod vm;
+
+pub use interpreter::{Interpreter, LoxError};
+pub use value::{Unpacked, Value};
+pub use vm::{InterpreterError, Limits};
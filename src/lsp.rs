@@ -0,0 +1,913 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use serde_json::{json, Value as Json};
+
+use crate::{
+    compiler::{
+        codegen::compile,
+        parser::Parser,
+        resolver::{resolve, Resolution, Symbol, SymbolKind},
+        scanner::{LosslessToken, Token, KEYWORDS},
+        syntax_tree::Name,
+    },
+    // the language server protocol is framed the same way as the debug adapter protocol
+    dap::{read_message, write_message},
+    process::{self, Permissions},
+    vm::VM,
+};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+const ERROR: u64 = 1;
+const WARNING: u64 = 2;
+
+/// The kinds of semantic token, in the order of the legend sent to the client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenType {
+    Keyword,
+    String,
+    Number,
+    Comment,
+    Operator,
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Method,
+    Property,
+}
+
+const TOKEN_TYPES: &[&str] = &[
+    "keyword",
+    "string",
+    "number",
+    "comment",
+    "operator",
+    "variable",
+    "parameter",
+    "function",
+    "class",
+    "method",
+    "property",
+];
+
+/// The only semantic token modifier, marking the name in a declaration.
+const DECLARATION: u32 = 1;
+
+impl From<SymbolKind> for TokenType {
+    fn from(kind: SymbolKind) -> TokenType {
+        match kind {
+            SymbolKind::Variable => TokenType::Variable,
+            SymbolKind::Parameter => TokenType::Parameter,
+            SymbolKind::Function => TokenType::Function,
+            SymbolKind::Class => TokenType::Class,
+            SymbolKind::Method => TokenType::Method,
+        }
+    }
+}
+
+/// Serves the Language Server Protocol, reading messages from `input` and writing to `output`
+/// until the client sends `exit` or the input ends.
+///
+/// Documents are kept in sync with the client through incremental changes, and are analyzed again
+/// whenever they change, publishing their diagnostics.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        natives: natives(),
+    };
+    while let Some(message) = read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        server.handle(&message)?;
+    }
+    Ok(())
+}
+
+/// Converts between byte offsets and LSP positions, which count lines from zero and characters in
+/// UTF-16 code units.
+struct LineIndex<'a> {
+    text: &'a str,
+    /// the offset each line starts at
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> LineIndex<'a> {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        LineIndex { text, starts }
+    }
+
+    fn line_text(&self, line: usize) -> &'a str {
+        let start = self.starts[line];
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.text.len(), |&end| end - 1);
+        self.text[start..end].trim_end_matches('\r')
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.starts[line]..offset].encode_utf16().count();
+        (line, character)
+    }
+
+    fn offset(&self, position: &Json) -> usize {
+        let line = position["line"].as_u64().unwrap_or_default() as usize;
+        let character = position["character"].as_u64().unwrap_or_default() as usize;
+        if line >= self.starts.len() {
+            return self.text.len();
+        }
+        let mut units = 0;
+        for (i, c) in self.line_text(line).char_indices() {
+            if units >= character {
+                return self.starts[line] + i;
+            }
+            units += c.len_utf16();
+        }
+        self.starts[line] + self.line_text(line).len()
+    }
+
+    fn json_position(&self, offset: usize) -> Json {
+        let (line, character) = self.position(offset);
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, start: usize, end: usize) -> Json {
+        json!({ "start": self.json_position(start), "end": self.json_position(end) })
+    }
+
+    /// The range of a line counting from one, as errors do, without its indentation.
+    fn line_range(&self, line: usize) -> Json {
+        let line = line.clamp(1, self.starts.len()) - 1;
+        let text = self.line_text(line);
+        let start = self.starts[line] + text.len() - text.trim_start().len();
+        self.range(start, self.starts[line] + text.len())
+    }
+}
+
+/// What's known about a document: its tokens, and when it parses, what its names refer to.
+struct Analysis<'a> {
+    index: LineIndex<'a>,
+    tokens: Vec<LosslessToken<'a>>,
+    resolution: Option<Resolution<'a>>,
+    diagnostics: Vec<Json>,
+}
+
+/// The names of the natives scripts can call, including the ones the command line may allow.
+fn natives() -> HashSet<Rc<str>> {
+    let mut vm = VM::new();
+    process::define_natives(&mut vm, &[], Permissions::default());
+    vm.globals.into_keys().collect()
+}
+
+fn analyze<'a>(text: &'a str, natives: &HashSet<Rc<str>>) -> Analysis<'a> {
+    let index = LineIndex::new(text);
+    let mut parser = Parser::new(text);
+    let parsed = parser.parse();
+    let tokens = parser.tokens().to_vec();
+    let diagnostic = |range: Json, severity: u64, message: String| {
+        json!({
+            "range": range,
+            "severity": severity,
+            "source": "rlox",
+            "message": message,
+        })
+    };
+
+    let mut diagnostics = Vec::new();
+    let resolution = match parsed {
+        Ok(declarations) => {
+            if let Err(errors) = compile(&declarations) {
+                for error in errors {
+                    let range = index.line_range(error.location().line);
+                    diagnostics.push(diagnostic(range, ERROR, error.to_string()));
+                }
+            }
+            let resolution = resolve(&declarations);
+            for (name, symbol) in &resolution.references {
+                if symbol.is_none() && !natives.contains(name.text) {
+                    let range = index.range(name.span.start, name.span.end);
+                    let message = format!("undefined variable '{}'", name.text);
+                    diagnostics.push(diagnostic(range, WARNING, message));
+                }
+            }
+            Some(resolution)
+        }
+        Err(error) => {
+            let range = index.line_range(error.location().line);
+            diagnostics.push(diagnostic(range, ERROR, error.to_string()));
+            None
+        }
+    };
+
+    Analysis {
+        index,
+        tokens,
+        resolution,
+        diagnostics,
+    }
+}
+
+struct Document {
+    text: String,
+    version: Json,
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+    /// the globals defined by the VM, which scripts can use without declaring them
+    natives: HashSet<Rc<str>>,
+}
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, message: &Json) -> io::Result<()> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id,
+            None => return self.notification(method, params),
+        };
+        let response = match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        write_message(&mut self.output, &response)
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let version = params["textDocument"]["version"].clone();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                let document = Document {
+                    text: text.to_string(),
+                    version,
+                };
+                self.documents.insert(uri.to_string(), document);
+            }
+            "textDocument/didChange" => {
+                let document = match self.documents.get_mut(uri) {
+                    Some(document) => document,
+                    None => return Ok(()),
+                };
+                let changes = params["contentChanges"].as_array().into_iter().flatten();
+                for change in changes {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    match change.get("range") {
+                        Some(range) => {
+                            let index = LineIndex::new(&document.text);
+                            let start = index.offset(&range["start"]);
+                            let end = index.offset(&range["end"]).max(start);
+                            document.text.replace_range(start..end, text);
+                        }
+                        None => document.text = text.to_string(),
+                    }
+                }
+                document.version = version;
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.publish(uri, Json::Null, Vec::new());
+            }
+            _ => return Ok(()),
+        }
+
+        let document = &self.documents[uri];
+        let diagnostics = analyze(&document.text, &self.natives).diagnostics;
+        let version = document.version.clone();
+        self.publish(uri, version, diagnostics)
+    }
+
+    fn publish(&mut self, uri: &str, version: Json, diagnostics: Vec<Json>) -> io::Result<()> {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "version": version, "diagnostics": diagnostics },
+        });
+        write_message(&mut self.output, &notification)
+    }
+
+    fn request(&self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        if method == "initialize" {
+            return Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 2 },
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                    "semanticTokensProvider": {
+                        "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": ["declaration"] },
+                        "full": true,
+                    },
+                },
+                "serverInfo": { "name": "rlox", "version": env!("CARGO_PKG_VERSION") },
+            }));
+        }
+        if method == "shutdown" {
+            return Ok(Json::Null);
+        }
+
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let document = self
+            .documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("unknown document '{}'", uri)))?;
+        let analysis = analyze(&document.text, &self.natives);
+        let offset = analysis.index.offset(&params["position"]);
+        let location = |name: &Name| json!({ "uri": uri, "range": name_range(&analysis, name) });
+
+        let result = match method {
+            "textDocument/definition" => match analysis.name_at(offset) {
+                Some((_, symbol)) => location(&analysis.symbol(symbol).name),
+                None => Json::Null,
+            },
+            "textDocument/references" => match analysis.name_at(offset) {
+                Some((_, symbol)) => {
+                    let resolution = analysis.resolution.as_ref().expect("resolved a name");
+                    let declaration = params["context"]["includeDeclaration"] == json!(true);
+                    let declaration = declaration.then_some(&resolution.symbols[symbol].name);
+                    let names = declaration
+                        .into_iter()
+                        .chain(resolution.references_to(symbol));
+                    Json::Array(names.map(location).collect())
+                }
+                None => Json::Null,
+            },
+            "textDocument/hover" => match analysis.name_at(offset) {
+                Some((name, symbol)) => json!({
+                    "contents": { "kind": "markdown", "value": hover(&analysis, symbol) },
+                    "range": name_range(&analysis, &name),
+                }),
+                None => Json::Null,
+            },
+            "textDocument/documentSymbol" => Json::Array(document_symbols(&analysis, None)),
+            "textDocument/semanticTokens/full" => json!({ "data": semantic_tokens(&analysis) }),
+            "textDocument/completion" => Json::Array(self.completions(&analysis, offset)),
+            _ => {
+                let message = format!("unsupported method '{}'", method);
+                return Err((METHOD_NOT_FOUND, message));
+            }
+        };
+        Ok(result)
+    }
+
+    /// Keywords, the VM's globals and the names in scope. When the document doesn't parse, every
+    /// identifier in it is offered instead of the names in scope.
+    fn completions(&self, analysis: &Analysis, offset: usize) -> Vec<Json> {
+        let item = |label: &str, kind: u64, detail: String| {
+            json!({
+                "label": label,
+                "kind": kind,
+                "detail": detail,
+            })
+        };
+        let mut keywords: Vec<_> = KEYWORDS.keys().collect();
+        keywords.sort();
+        let mut items: Vec<_> = keywords
+            .into_iter()
+            .map(|keyword| item(keyword, 14, "keyword".to_string()))
+            .collect();
+
+        let mut names = BTreeSet::new();
+        match &analysis.resolution {
+            Some(resolution) => {
+                for symbol in resolution.visible_at(offset) {
+                    names.insert(symbol.name.text);
+                    let kind = match symbol.kind {
+                        SymbolKind::Function | SymbolKind::Method => 3,
+                        SymbolKind::Class => 7,
+                        SymbolKind::Variable | SymbolKind::Parameter => 6,
+                    };
+                    items.push(item(symbol.name.text, kind, describe(symbol)));
+                }
+            }
+            None => {
+                let identifiers: BTreeSet<_> = analysis
+                    .tokens
+                    .iter()
+                    .filter_map(|token| match token.token {
+                        Some(Ok(Token::Identifier(name))) => Some(name),
+                        _ => None,
+                    })
+                    .collect();
+                for name in identifiers {
+                    names.insert(name);
+                    items.push(item(name, 6, "variable".to_string()));
+                }
+            }
+        }
+
+        let mut natives: Vec<_> = self.natives.iter().collect();
+        natives.sort();
+        for native in natives {
+            if !names.contains(native.as_ref()) {
+                items.push(item(native, 3, "native function".to_string()));
+            }
+        }
+        items
+    }
+}
+
+impl<'a> Analysis<'a> {
+    fn name_at(&self, offset: usize) -> Option<(Name<'a>, usize)> {
+        self.resolution.as_ref()?.name_at(offset)
+    }
+
+    fn symbol(&self, symbol: usize) -> &Symbol<'a> {
+        &self.resolution.as_ref().expect("resolved a name").symbols[symbol]
+    }
+}
+
+fn name_range(analysis: &Analysis, name: &Name) -> Json {
+    analysis.index.range(name.span.start, name.span.end)
+}
+
+/// The kind of a symbol, such as `local variable` or `parameter`.
+fn describe(symbol: &Symbol) -> String {
+    match symbol.kind {
+        SymbolKind::Parameter | SymbolKind::Method => symbol.kind.to_string(),
+        _ if symbol.global => format!("global {}", symbol.kind),
+        _ => format!("local {}", symbol.kind),
+    }
+}
+
+/// The kind of a symbol and where it was declared.
+fn hover(analysis: &Analysis, symbol: usize) -> String {
+    let symbol = analysis.symbol(symbol);
+    let mut hover = format!(
+        "```lox\n({}) {}\n```\ndeclared on line {}",
+        describe(symbol),
+        symbol.name.text,
+        symbol.name.span.line
+    );
+    if let Some(parent) = symbol.parent {
+        hover.push_str(&format!(" in `{}`", analysis.symbol(parent).name.text));
+    }
+    hover
+}
+
+/// Classes, functions, methods and global variables, with what's declared in them as children.
+fn document_symbols(analysis: &Analysis, parent: Option<usize>) -> Vec<Json> {
+    let resolution = match &analysis.resolution {
+        Some(resolution) => resolution,
+        None => return Vec::new(),
+    };
+    resolution
+        .symbols
+        .iter()
+        .enumerate()
+        .filter(|(_, symbol)| symbol.parent == parent)
+        .filter_map(|(index, symbol)| {
+            let kind = match symbol.kind {
+                SymbolKind::Class => 5,
+                SymbolKind::Method => 6,
+                SymbolKind::Function => 12,
+                SymbolKind::Variable if symbol.global => 13,
+                SymbolKind::Variable | SymbolKind::Parameter => return None,
+            };
+            Some(json!({
+                "name": symbol.name.text,
+                "detail": describe(symbol),
+                "kind": kind,
+                "range": analysis.index.range(symbol.span.start, symbol.span.end),
+                "selectionRange": name_range(analysis, &symbol.name),
+                "children": document_symbols(analysis, Some(index)),
+            }))
+        })
+        .collect()
+}
+
+/// Every token worth highlighting, encoded as the client expects: each token is five numbers, the
+/// line and start relative to the previous token, its length, type and modifiers.
+fn semantic_tokens(analysis: &Analysis) -> Vec<u32> {
+    let mut names = HashMap::new();
+    if let Some(resolution) = &analysis.resolution {
+        for (name, symbol) in &resolution.references {
+            if let Some(symbol) = symbol {
+                let kind = resolution.symbols[*symbol].kind;
+                names.insert(name.span.start, (TokenType::from(kind), 0));
+            }
+        }
+        for symbol in &resolution.symbols {
+            let token = (TokenType::from(symbol.kind), DECLARATION);
+            names.insert(symbol.name.span.start, token);
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut after_dot = false;
+    for token in &analysis.tokens {
+        for comment in token.comments() {
+            tokens.push((comment.offset, comment.text.len(), TokenType::Comment, 0));
+        }
+
+        let token_type = match token.token {
+            Some(Ok(Token::Identifier(_))) => match names.get(&token.offset) {
+                Some(&token_type) => Some(token_type),
+                None if after_dot => Some((TokenType::Property, 0)),
+                None => Some((TokenType::Variable, 0)),
+            },
+            Some(Ok(Token::String(_))) => Some((TokenType::String, 0)),
+            Some(Ok(Token::Number(_))) => Some((TokenType::Number, 0)),
+            Some(Ok(
+                Token::Minus
+                | Token::Plus
+                | Token::Star
+                | Token::Slash
+                | Token::Bang
+                | Token::BangEqual
+                | Token::Equal
+                | Token::EqualEqual
+                | Token::Greater
+                | Token::GreaterEqual
+                | Token::Less
+                | Token::LessEqual,
+            )) => Some((TokenType::Operator, 0)),
+            Some(Ok(token)) if KEYWORDS.values().any(|keyword| *keyword == token) => {
+                Some((TokenType::Keyword, 0))
+            }
+            _ => None,
+        };
+        if let Some((token_type, modifiers)) = token_type {
+            tokens.push((token.offset, token.text.len(), token_type, modifiers));
+        }
+        after_dot = token.token == Some(Ok(Token::Dot));
+    }
+    tokens.sort_by_key(|&(start, ..)| start);
+
+    let mut data = Vec::new();
+    let (mut previous_line, mut previous_character) = (0, 0);
+    for (start, length, token_type, modifiers) in tokens {
+        // tokens can't span lines, so strings with newlines in them are split up
+        let text = &analysis.index.text[start..start + length];
+        let mut offset = start;
+        for line in text.split('\n') {
+            let (line_number, character) = analysis.index.position(offset);
+            let delta_line = line_number - previous_line;
+            let delta_character = if delta_line == 0 {
+                character - previous_character
+            } else {
+                character
+            };
+            data.extend([
+                delta_line as u32,
+                delta_character as u32,
+                line.encode_utf16().count() as u32,
+                token_type as u32,
+                modifiers,
+            ]);
+            (previous_line, previous_character) = (line_number, character);
+            offset += line.len() + 1;
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.lox";
+
+    const SOURCE: &str = "\
+var total = 0;
+fun add(n) {
+    // keep a running total
+    total = total + n;
+    return totl;
+}
+class Counter {
+    count() { return add(1); }
+}";
+
+    fn position(line: u64, character: u64) -> Json {
+        json!({ "line": line, "character": character })
+    }
+
+    fn at(line: u64, character: u64) -> Json {
+        json!({ "textDocument": { "uri": URI }, "position": position(line, character) })
+    }
+
+    /// Plays a recorded session against the server, returning everything it sent.
+    fn session(messages: &[Json]) -> Vec<Json> {
+        let mut input = Vec::new();
+        let mut id = 0;
+        for message in messages {
+            let mut message = message.clone();
+            message["jsonrpc"] = json!("2.0");
+            let method = message["method"].as_str().unwrap();
+            let notification =
+                matches!(method, "initialized" | "exit") || method.starts_with("textDocument/did");
+            if !notification {
+                id += 1;
+                message["id"] = json!(id);
+            }
+            write_message(&mut input, &message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(input.as_slice(), &mut output).unwrap();
+
+        let mut reader = output.as_slice();
+        let mut sent = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            sent.push(message);
+        }
+        sent
+    }
+
+    fn open(text: &str) -> Json {
+        json!({
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "version": 1, "text": text } },
+        })
+    }
+
+    fn request(method: &str, params: Json) -> Json {
+        json!({ "method": method, "params": params })
+    }
+
+    fn messages(diagnostics: &Json) -> Vec<(u64, &str)> {
+        diagnostics["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|diagnostic| {
+                let line = diagnostic["range"]["start"]["line"].as_u64().unwrap();
+                (line, diagnostic["message"].as_str().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diagnostics_follow_changes() {
+        let sent = session(&[
+            request("initialize", json!({})),
+            json!({ "method": "initialized", "params": {} }),
+            open(SOURCE),
+            json!({
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{
+                        "range": { "start": position(4, 11), "end": position(4, 15) },
+                        "text": "total",
+                    }],
+                },
+            }),
+            json!({
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 3 },
+                    "contentChanges": [{ "text": "print 1 +;\nreturn 2;" }],
+                },
+            }),
+            json!({
+                "method": "textDocument/didClose",
+                "params": { "textDocument": { "uri": URI } },
+            }),
+            request("shutdown", Json::Null),
+            json!({ "method": "exit" }),
+        ]);
+
+        assert_eq!(sent.len(), 6);
+        assert_eq!(sent[0]["id"], json!(1));
+        assert_eq!(
+            sent[0]["result"]["capabilities"]["textDocumentSync"]["change"],
+            json!(2)
+        );
+        assert_eq!(messages(&sent[1]), vec![(4, "undefined variable 'totl'")]);
+        assert_eq!(
+            sent[1]["params"]["diagnostics"][0]["range"],
+            json!({ "start": position(4, 11), "end": position(4, 15) })
+        );
+        assert_eq!(sent[2]["params"]["version"], json!(2));
+        assert_eq!(messages(&sent[2]), vec![]);
+        assert_eq!(
+            messages(&sent[3]),
+            vec![(0, "expected expression at line 1, found ';'")]
+        );
+        assert_eq!(messages(&sent[4]), vec![]);
+        assert_eq!(sent[5]["result"], Json::Null);
+    }
+
+    #[test]
+    fn compile_errors() {
+        let sent = session(&[open(
+            "var a = 1;\n{\n  var b = 1;\n  var b = 2;\n}\nreturn a;",
+        )]);
+        assert_eq!(
+            messages(&sent[0]),
+            vec![
+                (3, "already a variable named 'b' in this scope at line 4"),
+                (5, "can't return from top-level code at line 6"),
+            ]
+        );
+        assert_eq!(
+            sent[0]["params"]["diagnostics"][0]["range"],
+            json!({ "start": position(3, 2), "end": position(3, 12) })
+        );
+    }
+
+    #[test]
+    fn navigation() {
+        let sent = session(&[
+            open(SOURCE),
+            request("textDocument/definition", at(3, 14)),
+            request("textDocument/definition", at(0, 5)),
+            request("textDocument/definition", at(0, 0)),
+            request("textDocument/references", {
+                let mut params = at(0, 6);
+                params["context"] = json!({ "includeDeclaration": true });
+                params
+            }),
+            request("textDocument/hover", at(3, 20)),
+            request("textDocument/hover", at(7, 21)),
+            request(
+                "textDocument/definition",
+                json!({ "textDocument": { "uri": "file:///other.lox" } }),
+            ),
+            request("textDocument/formatting", at(0, 0)),
+        ]);
+
+        let range = |line, start, end| {
+            json!({
+                "uri": URI,
+                "range": { "start": position(line, start), "end": position(line, end) },
+            })
+        };
+        assert_eq!(sent[1]["result"], range(0, 4, 9));
+        assert_eq!(sent[2]["result"], range(0, 4, 9));
+        assert_eq!(sent[3]["result"], Json::Null);
+        assert_eq!(
+            sent[4]["result"],
+            json!([range(0, 4, 9), range(3, 4, 9), range(3, 12, 17)])
+        );
+        assert_eq!(
+            sent[5]["result"]["contents"]["value"],
+            json!("```lox\n(parameter) n\n```\ndeclared on line 2 in `add`")
+        );
+        assert_eq!(
+            sent[5]["result"]["range"],
+            json!({ "start": position(3, 20), "end": position(3, 21) })
+        );
+        assert_eq!(
+            sent[6]["result"]["contents"]["value"],
+            json!("```lox\n(global function) add\n```\ndeclared on line 2")
+        );
+        assert_eq!(sent[7]["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(sent[8]["error"]["code"], json!(METHOD_NOT_FOUND));
+    }
+
+    #[test]
+    fn document_symbols() {
+        let sent = session(&[
+            open(SOURCE),
+            request("textDocument/documentSymbol", at(0, 0)),
+        ]);
+        let summary = |symbol: &Json| {
+            let children: Vec<_> = symbol["children"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|child| child["name"].as_str().unwrap().to_string())
+                .collect();
+            (
+                symbol["name"].as_str().unwrap().to_string(),
+                symbol["kind"].clone(),
+                children,
+            )
+        };
+        let symbols: Vec<_> = sent[1]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(summary)
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("total".to_string(), json!(13), vec![]),
+                ("add".to_string(), json!(12), vec![]),
+                ("Counter".to_string(), json!(5), vec!["count".to_string()]),
+            ]
+        );
+        assert_eq!(
+            sent[1]["result"][1]["range"],
+            json!({ "start": position(1, 0), "end": position(5, 1) })
+        );
+    }
+
+    #[test]
+    fn completion() {
+        let labels = |source: &str, at_position: Json| -> Vec<String> {
+            let sent = session(&[
+                open(source),
+                request("textDocument/completion", at_position),
+            ]);
+            sent[1]["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|item| item["kind"] != json!(14))
+                .map(|item| {
+                    format!(
+                        "{} ({})",
+                        item["label"].as_str().unwrap(),
+                        item["detail"].as_str().unwrap()
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(
+            labels(SOURCE, at(3, 4)),
+            vec![
+                "Counter (global class)",
+                "add (global function)",
+                "n (parameter)",
+                "total (global variable)",
+                "args (native function)",
+                "clock (native function)",
+                "exit (native function)",
+                "getenv (native function)",
+                "len (native function)",
+            ]
+        );
+        assert_eq!(
+            labels("var alpha = 1;\nprint al", at(1, 8)),
+            vec![
+                "al (variable)",
+                "alpha (variable)",
+                "args (native function)",
+                "clock (native function)",
+                "exit (native function)",
+                "getenv (native function)",
+                "len (native function)",
+            ]
+        );
+
+        let sent = session(&[open(""), request("textDocument/completion", at(0, 0))]);
+        assert_eq!(
+            sent[1]["result"][0],
+            json!({ "label": "and", "kind": 14, "detail": "keyword" })
+        );
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let source = "var s = \"a\nb\"; // note\nclass C { m() { return this.x; } }";
+        let sent = session(&[
+            open(source),
+            request("textDocument/semanticTokens/full", at(0, 0)),
+        ]);
+        let data: Vec<u32> = serde_json::from_value(sent[1]["result"]["data"].clone()).unwrap();
+        let tokens: Vec<_> = data.chunks(5).map(|token| token.to_vec()).collect();
+        let keyword = TokenType::Keyword as u32;
+        assert_eq!(
+            tokens,
+            vec![
+                vec![0, 0, 3, keyword, 0],
+                vec![0, 4, 1, TokenType::Variable as u32, DECLARATION],
+                vec![0, 2, 1, TokenType::Operator as u32, 0],
+                vec![0, 2, 2, TokenType::String as u32, 0],
+                vec![1, 0, 2, TokenType::String as u32, 0],
+                vec![0, 4, 7, TokenType::Comment as u32, 0],
+                vec![1, 0, 5, keyword, 0],
+                vec![0, 6, 1, TokenType::Class as u32, DECLARATION],
+                vec![0, 4, 1, TokenType::Method as u32, DECLARATION],
+                vec![0, 6, 6, keyword, 0],
+                vec![0, 7, 4, keyword, 0],
+                vec![0, 5, 1, TokenType::Property as u32, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn utf16_positions() {
+        let index = LineIndex::new("a\n\"é😀\" b");
+        let offset = "a\n\"é😀\" ".len();
+        assert_eq!(index.position(offset), (1, 6));
+        assert_eq!(index.offset(&position(1, 6)), offset);
+        assert_eq!(index.offset(&position(1, 99)), offset + 1);
+        assert_eq!(index.offset(&position(9, 0)), offset + 1);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{bytecode::core::Chunk, value::Value, vm::InterpreterError};
+
+/// A compiled function. The top level script is a function without a name.
+#[derive(PartialEq)]
+pub struct Function {
+    pub name: Option<Rc<str>>,
+    pub arity: u8,
+    pub upvalue_count: u8,
+    pub chunk: Chunk,
+}
+
+impl Function {
+    pub fn new(name: Option<Rc<str>>) -> Function {
+        Function {
+            name,
+            arity: 0,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// A function along with the variables it captured from its enclosing functions.
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A captured variable. It refers to a slot on the stack while that is still alive, and holds the
+/// value itself once the slot is popped.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub type NativeFn = Box<dyn Fn(&[Value]) -> Result<Value, InterpreterError>>;
+
+/// A function implemented in rust.
+pub struct Native {
+    pub name: String,
+    pub arity: u8,
+    pub function: NativeFn,
+}
+
+pub struct Class {
+    pub name: Rc<str>,
+    pub methods: RefCell<HashMap<Rc<str>, Rc<Closure>>>,
+}
+
+impl Class {
+    pub fn new(name: Rc<str>) -> Class {
+        Class {
+            name,
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<HashMap<Rc<str>, Value>>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Instance {
+        Instance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+/// An immutable sequence of values, indexed from 0.
+pub struct List {
+    pub elements: Vec<Value>,
+}
+
+/// A method along with the instance it was accessed on.
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+impl fmt::Display for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+impl fmt::Display for BoundMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, element) in self.elements.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", element)?;
+        }
+        write!(f, "]")
+    }
+}
+
+// objects can form cycles, so debug output only names them rather than recursing into them
+macro_rules! debug_as_display {
+    ($($object:ty),*) => {
+        $(
+            impl fmt::Debug for $object {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    write!(f, "{}", self)
+                }
+            }
+        )*
+    };
+}
+
+debug_as_display!(
+    Function,
+    Closure,
+    Native,
+    Class,
+    Instance,
+    BoundMethod,
+    List
+);
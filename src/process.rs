@@ -0,0 +1,129 @@
+use std::rc::Rc;
+
+use crate::{
+    object::List,
+    value::Value,
+    vm::{InterpreterError, VM},
+};
+
+/// What scripts may do to the process running them. Everything is denied by default, so that
+/// untrusted scripts can be run safely.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Permissions {
+    /// reading environment variables with `getenv`
+    pub env: bool,
+    /// ending the process with `exit`
+    pub exit: bool,
+}
+
+/// Defines the natives that let scripts see the process they run in:
+///
+/// * `args()`, a list of the arguments given to the script, not including its path
+/// * `getenv(name)`, an environment variable, or nil if it isn't set
+/// * `exit(code)`, which stops the script and makes the process exit with `code`
+///
+/// Natives that aren't permitted are still defined, but fail when called.
+pub fn define_natives(vm: &mut VM, args: &[String], permissions: Permissions) {
+    let args = Value::from(Rc::new(List {
+        elements: args.iter().map(|arg| Value::from(arg.as_str())).collect(),
+    }));
+    vm.define_native("args", 0, move |_| Ok(args.clone()));
+
+    vm.define_native("getenv", 1, move |arguments| {
+        if !permissions.env {
+            return Err(denied("getenv", "--allow-env"));
+        }
+        let name = arguments[0].as_string().ok_or_else(|| {
+            InterpreterError::Native("getenv expects the name of a variable".to_string())
+        })?;
+        Ok(std::env::var(&**name).map_or(Value::NIL, |value| Value::from(value.as_str())))
+    });
+
+    vm.define_native("exit", 1, move |arguments| {
+        if !permissions.exit {
+            return Err(denied("exit", "--allow-exit"));
+        }
+        // exiting unwinds the VM like an error, and the caller ends the process
+        match arguments[0].as_number() {
+            Some(code) if code.fract() == 0.0 && (0.0..=255.0).contains(&code) => {
+                Err(InterpreterError::Exit(code as i32))
+            }
+            _ => Err(InterpreterError::Native(
+                "exit code must be an integer from 0 to 255".to_string(),
+            )),
+        }
+    });
+}
+
+fn denied(native: &str, flag: &str) -> InterpreterError {
+    InterpreterError::Native(format!(
+        "{} isn't permitted, run with {} to allow it",
+        native, flag
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{compiled, Output};
+    use test_case::test_case;
+
+    fn run(source: &str, permissions: Permissions) -> (String, Result<(), InterpreterError>) {
+        let output = Output::default();
+        let mut vm = VM::with_output(Box::new(output.clone()));
+        define_natives(&mut vm, &["a".to_string(), "b c".to_string()], permissions);
+        let result = vm.interpret(Rc::new(compiled(source)));
+        let printed = String::from_utf8(output.0.take()).unwrap();
+        (printed, result)
+    }
+
+    #[test]
+    fn arguments() {
+        let source = "
+            var args = args();
+            print args;
+            for (var i = 0; i < len(args); i = i + 1) print args[i];
+        ";
+        assert_eq!(
+            run(source, Permissions::default()),
+            ("[a, b c]\na\nb c\n".to_string(), Ok(()))
+        );
+    }
+
+    #[test]
+    fn getenv() {
+        let permissions = Permissions {
+            env: true,
+            exit: false,
+        };
+        let source = "print getenv(\"PATH\") != nil; print getenv(\"RLOX_SURELY_UNSET\");";
+        assert_eq!(
+            run(source, permissions),
+            ("true\nnil\n".to_string(), Ok(()))
+        );
+    }
+
+    #[test]
+    fn exit() {
+        let permissions = Permissions {
+            env: false,
+            exit: true,
+        };
+        let source = "print 1; exit(3); print 2;";
+        assert_eq!(
+            run(source, permissions),
+            ("1\n".to_string(), Err(InterpreterError::Exit(3)))
+        );
+        let (_, result) = run("exit(256);", permissions);
+        assert!(matches!(result, Err(InterpreterError::Native(_))));
+    }
+
+    #[test_case("getenv(\"PATH\");"; "getenv")]
+    #[test_case("exit(0);"; "exit")]
+    fn denied_by_default(source: &str) {
+        let (_, result) = run(source, Permissions::default());
+        assert!(
+            matches!(result, Err(InterpreterError::Native(e)) if e.contains("isn't permitted"))
+        );
+    }
+}
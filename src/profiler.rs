@@ -0,0 +1,317 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bytecode::core::OpCode,
+    object::Function,
+    vm::{Hook, InterpreterError, VM},
+};
+
+/// The number of source lines listed in a report.
+const HOT_LINES: usize = 20;
+
+/// Counts the instructions a script runs by opcode, source line and function, and times its
+/// functions.
+#[derive(Default)]
+pub struct Profiler {
+    opcodes: HashMap<OpCode, u64>,
+    lines: HashMap<u32, u64>,
+    functions: HashMap<String, FunctionProfile>,
+    /// instructions run by each call stack, as the function names joined by `;`
+    stacks: HashMap<String, u64>,
+    calls: Vec<Call>,
+    stack: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub calls: u64,
+    pub instructions: u64,
+    /// time spent in the function, excluding the functions it called
+    pub self_time: Duration,
+    /// time spent in the function, including the functions it called
+    pub total_time: Duration,
+}
+
+/// A call being timed.
+struct Call {
+    name: String,
+    start: Instant,
+    child_time: Duration,
+    /// the length of the call stack key before this call was added to it
+    stack_len: usize,
+    /// whether the function is already further down the stack, so its total time is only counted
+    /// for the outermost call
+    recursive: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn opcode_count(&self, op: OpCode) -> u64 {
+        self.opcodes.get(&op).copied().unwrap_or_default()
+    }
+
+    pub fn line_count(&self, line: u32) -> u64 {
+        self.lines.get(&line).copied().unwrap_or_default()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionProfile> {
+        self.functions.get(name)
+    }
+
+    fn push_call(&mut self, function: &Function, now: Instant) {
+        let name = function_name(function);
+        let recursive = self.calls.iter().any(|call| call.name == name);
+        let stack_len = self.stack.len();
+        if !self.stack.is_empty() {
+            self.stack.push(';');
+        }
+        self.stack.push_str(&name);
+        self.functions.entry(name.clone()).or_default().calls += 1;
+        self.calls.push(Call {
+            name,
+            start: now,
+            child_time: Duration::ZERO,
+            stack_len,
+            recursive,
+        });
+    }
+
+    fn pop_call(&mut self, now: Instant) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        self.stack.truncate(call.stack_len);
+        let elapsed = now - call.start;
+        let profile = self.functions.entry(call.name).or_default();
+        profile.self_time += elapsed.saturating_sub(call.child_time);
+        if !call.recursive {
+            profile.total_time += elapsed;
+        }
+        if let Some(caller) = self.calls.last_mut() {
+            caller.child_time += elapsed;
+        }
+    }
+
+    /// Writes the functions sorted by their self time, then the opcodes and source lines that ran
+    /// the most instructions. Lines are followed by their source, when it's given.
+    pub fn report<W: io::Write>(&self, out: &mut W, source: Option<&str>) -> io::Result<()> {
+        let total: u64 = self.opcodes.values().sum();
+        let percent = |count: u64| count as f64 * 100.0 / total.max(1) as f64;
+        writeln!(out, "{} instructions", total)?;
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<24} {:>10} {:>14} {:>10} {:>10}",
+            "function", "calls", "instructions", "self ms", "total ms"
+        )?;
+        let mut functions: Vec<_> = self
+            .functions
+            .keys()
+            .filter_map(|name| Some((name.as_str(), self.function(name)?)))
+            .collect();
+        functions.sort_by_key(|&(name, profile)| (Reverse(profile.self_time), name));
+        for (name, profile) in functions {
+            writeln!(
+                out,
+                "{:<24} {:>10} {:>14} {:>10.3} {:>10.3}",
+                name,
+                profile.calls,
+                profile.instructions,
+                profile.self_time.as_secs_f64() * 1000.0,
+                profile.total_time.as_secs_f64() * 1000.0
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<24} {:>14} {:>7}", "opcode", "instructions", "%")?;
+        let mut opcodes: Vec<_> = self.opcodes.keys().copied().collect();
+        opcodes.sort_by_key(|&op| (Reverse(self.opcode_count(op)), u8::from(op)));
+        for op in opcodes {
+            let count = self.opcode_count(op);
+            writeln!(
+                out,
+                "{:<24} {:>14} {:>6.1}%",
+                op.mnemonic(),
+                count,
+                percent(count)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<24} {:>14} {:>7}", "line", "instructions", "%")?;
+        let source_lines: Vec<_> = source
+            .map(|source| source.lines().collect())
+            .unwrap_or_default();
+        let mut lines: Vec<_> = self.lines.keys().copied().collect();
+        lines.sort_by_key(|&line| (Reverse(self.line_count(line)), line));
+        for line in lines.into_iter().take(HOT_LINES) {
+            let count = self.line_count(line);
+            write!(out, "{:<24} {:>14} {:>6.1}%", line, count, percent(count))?;
+            match source_lines.get((line as usize).wrapping_sub(1)) {
+                Some(text) => writeln!(out, "    {}", text.trim())?,
+                None => writeln!(out)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the instructions run by each call stack in the collapsed format read by flame graph
+    /// tools, one `outer;inner count` line per stack.
+    pub fn write_collapsed<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Hook for Profiler {
+    fn before_instruction(&mut self, vm: &VM) -> Result<(), InterpreterError> {
+        let frames = vm.frames();
+        // calls and returns are noticed at the first instruction run after them
+        if frames.len() != self.calls.len() {
+            let now = Instant::now();
+            while self.calls.len() > frames.len() {
+                self.pop_call(now);
+            }
+            for frame in &frames[self.calls.len()..] {
+                self.push_call(frame.function(), now);
+            }
+        }
+
+        let frame = match frames.last() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        let metadata = frame.metadata();
+        let chunk = &frame.function().chunk;
+        if let Some(op) = chunk
+            .code
+            .get(metadata.pos)
+            .and_then(|&byte| OpCode::try_from(byte).ok())
+        {
+            *self.opcodes.entry(op).or_default() += 1;
+        }
+        *self.lines.entry(metadata.line).or_default() += 1;
+        if let Some(call) = self.calls.last() {
+            if let Some(profile) = self.functions.get_mut(&call.name) {
+                profile.instructions += 1;
+            }
+        }
+        match self.stacks.get_mut(self.stack.as_str()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        let now = Instant::now();
+        while !self.calls.is_empty() {
+            self.pop_call(now);
+        }
+    }
+}
+
+fn function_name(function: &Function) -> String {
+    match &function.name {
+        Some(name) => name.to_string(),
+        None => "<script>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::compiler::{codegen::compile, parser::Parser};
+
+    fn profile(source: &str) -> Profiler {
+        let declarations = Parser::new(source).parse().unwrap();
+        let function = Rc::new(compile(&declarations).unwrap());
+        let mut profiler = Profiler::new();
+        let mut vm = VM::with_output(Box::new(io::sink()));
+        vm.interpret_with_hook(function, &mut profiler).unwrap();
+        assert_eq!(
+            profiler.opcodes.values().sum::<u64>(),
+            vm.instructions_executed()
+        );
+        profiler
+    }
+
+    #[test]
+    fn counts() {
+        let profiler = profile(
+            "fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 2) + fib(n - 1);
+            }
+            print fib(5);",
+        );
+
+        let fib = profiler.function("fib").unwrap();
+        assert_eq!(fib.calls, 15);
+        assert!(fib.total_time >= fib.self_time);
+        assert_eq!(profiler.function("<script>").unwrap().calls, 1);
+        assert_eq!(profiler.opcode_count(OpCode::Call), 15);
+        assert_eq!(profiler.opcode_count(OpCode::Print), 1);
+        // GET_LOCAL, CONSTANT and LESS, then JUMP_IF_FALSE and POP for every call
+        assert_eq!(profiler.line_count(2), 15 * 5 + 8 * 2);
+    }
+
+    #[test]
+    fn collapsed_stacks() {
+        let profiler = profile(
+            "fun inner() { return 1; }
+            fun outer() { return inner() + inner(); }
+            outer();",
+        );
+        let mut out = Vec::new();
+        profiler.write_collapsed(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<script> 9\n<script>;outer 6\n<script>;outer;inner 4\n"
+        );
+    }
+
+    #[test]
+    fn report() {
+        let profiler = profile("var a = 1;\nprint a + 2;");
+        let mut out = Vec::new();
+        profiler
+            .report(&mut out, Some("var a = 1;\nprint a + 2;"))
+            .unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.starts_with("8 instructions\n"), "{}", report);
+        assert!(
+            report.contains("<script>                          1              8"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("GET_GLOBAL                            1   12.5%"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("2                                     6   75.0%    print a + 2;"),
+            "{}",
+            report
+        );
+    }
+}
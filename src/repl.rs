@@ -0,0 +1,401 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    rc::Rc,
+    time::Instant,
+};
+
+use thiserror::Error;
+
+use crate::{
+    compiler::{
+        codegen::{compile, compile_returning, CompileError},
+        parser::{ParseError, Parser},
+        scanner::{Scanner, ScannerError, Token},
+        syntax_tree::{Decl, Stmt},
+    },
+    dissembler::DissemblerPrinter,
+    interpreter::parse_entry,
+    value::Value,
+    vm::{InterpreterError, VM},
+};
+
+const HELP: &str = "\
+:disasm CODE    show the bytecode CODE compiles to
+:ast CODE       show the syntax tree of CODE
+:tokens CODE    show the tokens CODE is scanned into
+:globals        show the global variables
+:stack          show the calls and stack of the last entry that failed
+:load PATH      run a lox file
+:reset          forget every global that's been declared
+:time CODE      run CODE, showing how long it took
+:help           show this help
+An entry that ends in an expression has its value printed.";
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ReplError {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))]
+    Compile(Vec<CompileError>),
+    #[error(transparent)]
+    Runtime(#[from] InterpreterError),
+    #[error("unknown command ':{0}', try ':help'")]
+    UnknownCommand(String),
+    #[error("usage: {0}")]
+    Usage(&'static str),
+    #[error("unable to read lox file at {0}: {1}")]
+    Load(String, String),
+    #[error("unable to write output: {0}")]
+    Output(String),
+}
+
+impl From<io::Error> for ReplError {
+    fn from(e: io::Error) -> Self {
+        ReplError::Output(e.to_string())
+    }
+}
+
+/// Runs lox an entry at a time in a single VM, so globals declared by one entry can be used by the
+/// ones after it. Entries starting with `:` are commands for looking into the compiler and the VM.
+pub struct Repl {
+    vm: VM,
+    /// the globals the VM started with, which are kept when it's reset
+    natives: HashMap<Rc<str>, Value>,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl::with_output(Box::new(std::io::stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Repl {
+        Repl::with_vm(VM::with_output(output))
+    }
+
+    pub fn with_vm(vm: VM) -> Repl {
+        Repl {
+            natives: vm.globals.clone(),
+            vm,
+        }
+    }
+
+    /// Runs an entry or a command, writing what commands show to `out`. What scripts print goes to
+    /// the VM's output.
+    pub fn run<W: Write>(&mut self, entry: &str, out: &mut W) -> Result<(), ReplError> {
+        let command = match entry.trim().strip_prefix(':') {
+            Some(command) => command,
+            None => return self.eval(entry),
+        };
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, argument)| (name, argument.trim()));
+        let code = |usage| match argument {
+            "" => Err(ReplError::Usage(usage)),
+            code => Ok(code),
+        };
+
+        match name {
+            "disasm" => {
+                let code = code(":disasm CODE")?;
+                let function =
+                    compile_returning(&parse_entry(code)?).map_err(ReplError::Compile)?;
+                DissemblerPrinter::dissemble(out, &function, Some(code))?;
+            }
+            "ast" => write_ast(out, code(":ast CODE")?)?,
+            "tokens" => write_tokens(out, code(":tokens CODE")?)?,
+            "globals" => {
+                let mut globals: Vec<_> = self.vm.globals.iter().collect();
+                globals.sort_by_key(|(name, _)| *name);
+                for (name, value) in globals {
+                    writeln!(out, "{} = {}", name, value)?;
+                }
+            }
+            "stack" => {
+                if self.vm.backtrace.is_empty() {
+                    writeln!(out, "nothing has failed")?;
+                }
+                for call in &self.vm.backtrace {
+                    writeln!(out, "[line {}] in {}", call.line, call.function)?;
+                    for (slot, value) in (call.slots..).zip(&call.stack) {
+                        writeln!(out, "{:>4}: {}", slot, value)?;
+                    }
+                }
+            }
+            "load" => {
+                let path = code(":load PATH")?;
+                let source = std::fs::read_to_string(path)
+                    .map_err(|e| ReplError::Load(path.to_string(), e.to_string()))?;
+                let declarations = Parser::new(&source).parse()?;
+                let function = compile(&declarations).map_err(ReplError::Compile)?;
+                self.vm.interpret(Rc::new(function))?;
+            }
+            "reset" => {
+                self.vm.globals = self.natives.clone();
+                self.vm.backtrace.clear();
+            }
+            "time" => {
+                let declarations = parse_entry(code(":time CODE")?)?;
+                let function = compile_returning(&declarations).map_err(ReplError::Compile)?;
+                let instructions = self.vm.instructions_executed();
+                let start = Instant::now();
+                let result = self.vm.evaluate(Rc::new(function));
+                let elapsed = start.elapsed();
+                let instructions = self.vm.instructions_executed() - instructions;
+                writeln!(
+                    out,
+                    "took {:.3} ms, running {} instructions",
+                    elapsed.as_secs_f64() * 1000.0,
+                    instructions
+                )?;
+                self.echo(&declarations, result?)?;
+            }
+            "help" => writeln!(out, "{}", HELP)?,
+            _ => return Err(ReplError::UnknownCommand(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Runs an entry. When its last statement is an expression, with or without a semicolon after
+    /// it, that expression's value is printed.
+    pub fn eval(&mut self, source: &str) -> Result<(), ReplError> {
+        let declarations = parse_entry(source)?;
+        let function = compile_returning(&declarations).map_err(ReplError::Compile)?;
+        let value = self.vm.evaluate(Rc::new(function))?;
+        self.echo(&declarations, value)
+    }
+
+    /// Prints the value an entry returned, if it ends in an expression rather than returning nil
+    /// because it ends in some other statement.
+    fn echo(&mut self, declarations: &[Decl<'_>], value: Value) -> Result<(), ReplError> {
+        if let Some(Decl::Stmt(Stmt::Expression { .. })) = declarations.last() {
+            self.vm.print(&value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+/// Writes the tokens scanned from the source, one per line along with the line they're on.
+pub fn write_tokens<W: Write>(out: &mut W, source: &str) -> io::Result<()> {
+    for (location, token) in &mut Scanner::new(source) {
+        match token {
+            Ok(token) => writeln!(out, "{}: {:?}", location, token)?,
+            Err(e) => writeln!(out, "{}: error: {}", location, e)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes the syntax tree of each declaration in the source as an s-expression, one per line. Like
+/// an entry, the source can also be a lone expression.
+pub fn write_ast<W: Write>(out: &mut W, source: &str) -> Result<(), ReplError> {
+    for decl in parse_entry(source)? {
+        writeln!(out, "{}", decl)?;
+    }
+    Ok(())
+}
+
+/// Whether an entry is ready to run, rather than needing more lines to close its braces,
+/// brackets, parentheses or strings. Commands are always a single line.
+pub fn is_complete(source: &str) -> bool {
+    if source.trim_start().starts_with(':') {
+        return true;
+    }
+    let mut depth = 0;
+    for (_, token) in &mut Scanner::new(source) {
+        match token {
+            Ok(Token::LeftParen | Token::LeftBrace | Token::LeftBracket) => depth += 1,
+            Ok(Token::RightParen | Token::RightBrace | Token::RightBracket) => depth -= 1,
+            Err(ScannerError::UnterminatedString) => return false,
+            _ => (),
+        }
+    }
+    depth <= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+    use crate::test_support::Output;
+
+    /// Runs each entry in turn, returning what was printed along with the results.
+    fn session(entries: &[&str]) -> (String, Vec<Result<(), ReplError>>) {
+        let output = Output::default();
+        let mut repl = Repl::with_output(Box::new(output.clone()));
+        let results = entries.iter().map(|entry| repl.eval(entry)).collect();
+        let printed = String::from_utf8(output.0.take()).unwrap();
+        (printed, results)
+    }
+
+    #[test]
+    fn echoes_expressions() {
+        let (printed, _) = session(&[
+            "1 + 2",
+            "\"a\" + \"b\";",
+            "print 3;",
+            "var a = 4;",
+            "a = 5",
+            "var b = a; b + 1;",
+            "nil",
+        ]);
+        assert_eq!(printed, "3\nab\n3\n5\n6\nnil\n");
+    }
+
+    #[test]
+    fn globals_persist() {
+        let (printed, results) = session(&[
+            "fun square(n) { return n * n; }",
+            "var x = square(3);",
+            "x + 1",
+        ]);
+        assert!(results.iter().all(Result::is_ok), "{:?}", results);
+        assert_eq!(printed, "10\n");
+    }
+
+    #[test]
+    fn errors_leave_the_vm_usable() {
+        let (printed, results) = session(&["var a = 1;", "-nil", "1 +", "return 1;", "a"]);
+        assert_eq!(
+            results[1],
+            Err(ReplError::Runtime(InterpreterError::OperandMustBeNumber))
+        );
+        assert!(matches!(results[2], Err(ReplError::Parse(_))));
+        assert!(matches!(results[3], Err(ReplError::Compile(_))));
+        assert_eq!(printed, "1\n");
+    }
+
+    #[test_case("print 1;", true; "statement")]
+    #[test_case("fun f() {", false; "open brace")]
+    #[test_case("fun f() {\n  return (1 +\n", false; "open parenthesis")]
+    #[test_case("print args()[", false; "open bracket")]
+    #[test_case("fun f() {\n  return 1;\n}", true; "closed")]
+    #[test_case("print \"a", false; "open string")]
+    #[test_case("print 1; }", true; "extra brace")]
+    #[test_case(":ast fun f() {", true; "command")]
+    fn completeness(source: &str, complete: bool) {
+        assert_eq!(is_complete(source), complete);
+    }
+
+    /// Runs each entry in turn, returning what commands and scripts wrote, in that order.
+    fn commands(entries: &[&str]) -> (String, String) {
+        let output = Output::default();
+        let mut repl = Repl::with_output(Box::new(output.clone()));
+        let mut out = Vec::new();
+        for entry in entries {
+            if let Err(e) = repl.run(entry, &mut out) {
+                writeln!(out, "error: {}", e).unwrap();
+            }
+        }
+        let printed = String::from_utf8(output.0.take()).unwrap();
+        (String::from_utf8(out).unwrap(), printed)
+    }
+
+    #[test]
+    fn inspecting_code() {
+        let (out, printed) = commands(&[":ast 1 + 2", ":ast var a = -b;", ":tokens a = \"b"]);
+        assert_eq!(
+            out,
+            "(; (+ 1 2))\n\
+             (var a (- b))\n\
+             line 1: Identifier(\"a\")\n\
+             line 1: Equal\n\
+             line 1: error: unterminated string\n"
+        );
+        assert_eq!(printed, "");
+
+        let (out, _) = commands(&[":disasm 1 + 2"]);
+        assert!(out.starts_with("== <script> ==\n"), "{}", out);
+        assert!(out.contains("RETURN"), "{}", out);
+    }
+
+    #[test]
+    fn inspecting_the_vm() {
+        let (out, printed) = commands(&[
+            "var a = 1;",
+            ":globals",
+            ":stack",
+            ":reset",
+            ":globals",
+            "a",
+            ":time 1 + 1",
+        ]);
+        let out: Vec<_> = out.lines().collect();
+        assert_eq!(
+            out[..7],
+            [
+                "a = 1",
+                "clock = <native fn clock>",
+                "len = <native fn len>",
+                "nothing has failed",
+                "clock = <native fn clock>",
+                "len = <native fn len>",
+                "error: undefined variable 'a'",
+            ]
+        );
+        assert!(out[7].starts_with("took "), "{:?}", out);
+        assert!(out[7].ends_with(" ms, running 4 instructions"), "{:?}", out);
+        assert_eq!(printed, "2\n");
+    }
+
+    #[test]
+    fn inspecting_a_failure() {
+        let (out, _) = commands(&[
+            "fun f(a) { var b = a + 1; return b + nil; }",
+            "f(1);",
+            ":stack",
+            "1 + 1",
+            ":stack",
+            ":reset",
+            ":stack",
+        ]);
+        let failure = "[line 1] in <fn f>\n   \
+                          1: <fn f>\n   \
+                          2: 1\n   \
+                          3: 2\n\
+                       [line 1] in <script>\n   \
+                          0: <script>\n";
+        assert_eq!(
+            out,
+            format!(
+                "error: operands must be two numbers or two strings\n{}{}nothing has failed\n",
+                failure, failure
+            )
+        );
+    }
+
+    #[test]
+    fn loading_files() {
+        let path = std::env::temp_dir().join(format!("rlox-repl-{}.lox", std::process::id()));
+        std::fs::write(&path, "var loaded = 1;\nprint \"loaded\";").unwrap();
+        let load = format!(":load {}", path.display());
+        let (out, printed) = commands(&[&load, "loaded + 1", ":load /does/not/exist.lox"]);
+        assert_eq!(printed, "loaded\n2\n");
+        assert!(
+            out.starts_with("error: unable to read lox file at /does/not/exist.lox"),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn bad_commands() {
+        let (out, _) = commands(&[":frobnicate", ":ast", ":help"]);
+        let out: Vec<_> = out.lines().collect();
+        assert_eq!(
+            out[..3],
+            [
+                "error: unknown command ':frobnicate', try ':help'",
+                "error: usage: :ast CODE",
+                ":disasm CODE    show the bytecode CODE compiles to",
+            ]
+        );
+    }
+}
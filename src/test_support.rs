@@ -0,0 +1,29 @@
+//! Helpers shared by the tests of several modules.
+
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use crate::{
+    compiler::{codegen::compile, parser::Parser},
+    object::Function,
+};
+
+/// Compiles a program, panicking if it doesn't parse or compile.
+pub fn compiled(source: &str) -> Function {
+    let declarations = Parser::new(source).parse().unwrap();
+    compile(&declarations).unwrap()
+}
+
+/// Collects everything written to it, such as what a VM prints. Clones share the same buffer, so
+/// one can be given away as output while another reads what was written.
+#[derive(Clone, Default)]
+pub struct Output(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use std::{fmt, ops::Not, rc::Rc};
+
+use crate::{
+    bytecode::core::BinaryOp,
+    object::{BoundMethod, Class, Closure, Function, Instance, List, Native},
+    vm::InterpreterError,
+};
+
+#[cfg(feature = "nan-boxing")]
+mod nan_boxed;
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged;
+
+#[cfg(feature = "nan-boxing")]
+pub use nan_boxed::Value;
+#[cfg(not(feature = "nan-boxing"))]
+pub use tagged::Value;
+
+/// A value taken apart so that it can be matched on. Both representations of [`Value`] convert to
+/// and from this.
+#[derive(Debug, Clone)]
+pub enum Unpacked {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    Native(Rc<Native>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    BoundMethod(Rc<BoundMethod>),
+    List(Rc<List>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unpack() {
+            Unpacked::Nil => write!(f, "nil"),
+            Unpacked::Bool(b) => write!(f, "{}", b),
+            Unpacked::Number(n) => write!(f, "{}", n),
+            Unpacked::String(s) => write!(f, "{}", s),
+            Unpacked::Function(function) => write!(f, "{}", function),
+            Unpacked::Closure(closure) => write!(f, "{}", closure),
+            Unpacked::Native(native) => write!(f, "{}", native),
+            Unpacked::Class(class) => write!(f, "{}", class),
+            Unpacked::Instance(instance) => write!(f, "{}", instance),
+            Unpacked::BoundMethod(bound) => write!(f, "{}", bound),
+            Unpacked::List(list) => write!(f, "{}", list),
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.unpack())
+    }
+}
+
+/// Lox equality: strings compare by contents, functions by their code and every other object by
+/// identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        if let (Some(a), Some(b)) = (self.as_number(), other.as_number()) {
+            return a == b;
+        }
+        match (self.unpack(), other.unpack()) {
+            (Unpacked::Nil, Unpacked::Nil) => true,
+            (Unpacked::Bool(a), Unpacked::Bool(b)) => a == b,
+            (Unpacked::String(a), Unpacked::String(b)) => a == b,
+            (Unpacked::Function(a), Unpacked::Function(b)) => a == b,
+            (Unpacked::Closure(a), Unpacked::Closure(b)) => Rc::ptr_eq(&a, &b),
+            (Unpacked::Native(a), Unpacked::Native(b)) => Rc::ptr_eq(&a, &b),
+            (Unpacked::Class(a), Unpacked::Class(b)) => Rc::ptr_eq(&a, &b),
+            (Unpacked::Instance(a), Unpacked::Instance(b)) => Rc::ptr_eq(&a, &b),
+            (Unpacked::BoundMethod(a), Unpacked::BoundMethod(b)) => Rc::ptr_eq(&a, &b),
+            (Unpacked::List(a), Unpacked::List(b)) => Rc::ptr_eq(&a, &b),
+            _ => false,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Unpacked::Number(n).into()
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Unpacked::Bool(b).into()
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Unpacked::String(Rc::from(s)).into()
+    }
+}
+
+impl From<Rc<str>> for Value {
+    fn from(s: Rc<str>) -> Self {
+        Unpacked::String(s).into()
+    }
+}
+
+macro_rules! from_object {
+    ($($object:ident),*) => {
+        $(
+            impl From<Rc<$object>> for Value {
+                fn from(object: Rc<$object>) -> Self {
+                    Unpacked::$object(object).into()
+                }
+            }
+        )*
+    };
+}
+
+from_object!(
+    Function,
+    Closure,
+    Native,
+    Class,
+    Instance,
+    BoundMethod,
+    List
+);
+
+impl Not for Value {
+    type Output = Value;
+
+    fn not(self) -> Value {
+        Value::from(self.is_falsey())
+    }
+}
+
+impl Value {
+    pub fn negate(self) -> Result<Value, InterpreterError> {
+        match self.as_number() {
+            Some(n) => Ok(Value::from(-n)),
+            None => Err(InterpreterError::OperandMustBeNumber),
+        }
+    }
+
+    pub fn apply_binary_op(a: Value, b: Value, op: BinaryOp) -> Result<Value, InterpreterError> {
+        if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            return Ok(match op {
+                BinaryOp::Add => Value::from(a + b),
+                BinaryOp::Subtract => Value::from(a - b),
+                BinaryOp::Multiply => Value::from(a * b),
+                BinaryOp::Divide => Value::from(a / b),
+                BinaryOp::Equal => Value::from(a == b),
+                BinaryOp::Greater => Value::from(a > b),
+                BinaryOp::Less => Value::from(a < b),
+            });
+        }
+        match (op, a.as_string(), b.as_string()) {
+            (BinaryOp::Equal, _, _) => Ok(Value::from(a == b)),
+            (BinaryOp::Add, Some(a), Some(b)) => Ok(Value::from([&**a, &**b].concat().as_str())),
+            (BinaryOp::Add, _, _) => Err(InterpreterError::OperandsMustBeNumbersOrStrings),
+            _ => Err(InterpreterError::OperandsMustBeNumbers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(Value::from(1.0), Value::from(2.0), BinaryOp::Add, Ok(Value::from(3.0)); "add")]
+    #[test_case(Value::from("a"), Value::from("b"), BinaryOp::Add, Ok(Value::from("ab")); "concatenate")]
+    #[test_case(Value::from(1.0), Value::from("b"), BinaryOp::Add, Err(InterpreterError::OperandsMustBeNumbersOrStrings); "add mismatch")]
+    #[test_case(Value::from(1.0), Value::NIL, BinaryOp::Multiply, Err(InterpreterError::OperandsMustBeNumbers); "multiply nil")]
+    #[test_case(Value::from(1.0), Value::from(2.0), BinaryOp::Less, Ok(Value::from(true)); "less")]
+    #[test_case(Value::from("a"), Value::from("a"), BinaryOp::Equal, Ok(Value::from(true)); "string equality")]
+    #[test_case(Value::NIL, Value::from(false), BinaryOp::Equal, Ok(Value::from(false)); "mixed equality")]
+    #[test_case(Value::from(f64::NAN), Value::from(f64::NAN), BinaryOp::Equal, Ok(Value::from(false)); "nan equality")]
+    fn binary_op(a: Value, b: Value, op: BinaryOp, expected: Result<Value, InterpreterError>) {
+        assert_eq!(Value::apply_binary_op(a, b, op), expected);
+    }
+
+    #[test_case(Value::NIL; "nil value")]
+    #[test_case(Value::from(false); "false value")]
+    #[test_case(Value::from(-0.0); "negative zero")]
+    #[test_case(Value::from(f64::INFINITY); "infinity")]
+    #[test_case(Value::from("a"); "string value")]
+    #[test_case(Value::from(Rc::new(Function::new(None))); "function")]
+    #[test_case(Value::from(Rc::new(List { elements: vec![Value::from(1.0), Value::from("a")] })); "list")]
+    fn round_trip(value: Value) {
+        let unpacked = Value::from(value.unpack());
+        assert_eq!(unpacked, value);
+        assert_eq!(unpacked.to_string(), value.to_string());
+    }
+
+    #[test]
+    fn objects_compare_by_identity() {
+        let class = Rc::new(Class::new(Rc::from("A")));
+        let a = Value::from(Rc::new(Instance::new(class.clone())));
+        let b = Value::from(Rc::new(Instance::new(class)));
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+    }
+}
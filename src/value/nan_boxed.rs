@@ -0,0 +1,201 @@
+use std::{marker::PhantomData, rc::Rc};
+
+use super::Unpacked;
+use crate::object::{BoundMethod, Class, Closure, Function, Instance, List, Native};
+
+// a number is stored as its own bits. Everything else hides in the payload of a quiet NaN, which
+// arithmetic never produces: the sign bit marks an object pointer, otherwise the low bits say
+// which singleton it is
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+const NIL: u64 = QNAN | 1;
+const FALSE: u64 = QNAN | 2;
+const TRUE: u64 = QNAN | 3;
+const OBJECT: u64 = SIGN_BIT | QNAN;
+
+// objects are at least 8 byte aligned, so the low bits of their pointers hold their type
+const TYPE_MASK: u64 = 0b111;
+const STRING: u64 = 0;
+const FUNCTION: u64 = 1;
+const CLOSURE: u64 = 2;
+const NATIVE: u64 = 3;
+const CLASS: u64 = 4;
+const INSTANCE: u64 = 5;
+const BOUND_METHOD: u64 = 6;
+const LIST: u64 = 7;
+
+/// A value packed into a single `u64`. Objects are reference counted pointers, and strings are
+/// boxed once more so that their pointers are thin.
+pub struct Value {
+    bits: u64,
+    // holds `Rc`s, so mustn't be sent between threads
+    _rc: PhantomData<Rc<()>>,
+}
+
+impl Value {
+    pub const NIL: Value = Value::from_bits(NIL);
+
+    const fn from_bits(bits: u64) -> Value {
+        Value {
+            bits,
+            _rc: PhantomData,
+        }
+    }
+
+    fn object<T>(object: Rc<T>, object_type: u64) -> Value {
+        let pointer = Rc::into_raw(object) as u64;
+        assert_eq!(
+            pointer & (OBJECT | TYPE_MASK),
+            0,
+            "object pointer doesn't fit in a NaN"
+        );
+        Value::from_bits(OBJECT | pointer | object_type)
+    }
+
+    fn object_type(&self) -> Option<u64> {
+        (self.bits & OBJECT == OBJECT).then_some(self.bits & TYPE_MASK)
+    }
+
+    fn pointer<T>(&self) -> *const T {
+        (self.bits & !(OBJECT | TYPE_MASK)) as *const T
+    }
+
+    /// Takes another reference to the object.
+    ///
+    /// # Safety
+    ///
+    /// The value must be an object of type `T`.
+    unsafe fn rc<T>(&self) -> Rc<T> {
+        let pointer = self.pointer::<T>();
+        Rc::increment_strong_count(pointer);
+        Rc::from_raw(pointer)
+    }
+
+    pub fn unpack(&self) -> Unpacked {
+        if let Some(n) = self.as_number() {
+            return Unpacked::Number(n);
+        }
+        // SAFETY: the type bits always match the pointer they were packed with
+        unsafe {
+            match self.object_type() {
+                None if self.bits == NIL => Unpacked::Nil,
+                None => Unpacked::Bool(self.bits == TRUE),
+                Some(STRING) => Unpacked::String((*self.pointer::<Rc<str>>()).clone()),
+                Some(FUNCTION) => Unpacked::Function(self.rc()),
+                Some(CLOSURE) => Unpacked::Closure(self.rc()),
+                Some(NATIVE) => Unpacked::Native(self.rc()),
+                Some(CLASS) => Unpacked::Class(self.rc()),
+                Some(INSTANCE) => Unpacked::Instance(self.rc()),
+                Some(BOUND_METHOD) => Unpacked::BoundMethod(self.rc()),
+                Some(_) => Unpacked::List(self.rc()),
+            }
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        (self.bits & QNAN != QNAN).then(|| f64::from_bits(self.bits))
+    }
+
+    pub fn as_string(&self) -> Option<&Rc<str>> {
+        // SAFETY: the pointer lives as long as this reference to it
+        (self.object_type() == Some(STRING)).then(|| unsafe { &*self.pointer::<Rc<str>>() })
+    }
+
+    pub fn as_function(&self) -> Option<&Function> {
+        // SAFETY: the pointer lives as long as this reference to it
+        (self.object_type() == Some(FUNCTION)).then(|| unsafe { &*self.pointer::<Function>() })
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_falsey(&self) -> bool {
+        self.bits == NIL || self.bits == FALSE
+    }
+}
+
+impl From<Unpacked> for Value {
+    fn from(unpacked: Unpacked) -> Self {
+        match unpacked {
+            Unpacked::Nil => Value::NIL,
+            Unpacked::Bool(false) => Value::from_bits(FALSE),
+            Unpacked::Bool(true) => Value::from_bits(TRUE),
+            // other NaNs could look like a boxed value
+            Unpacked::Number(n) if n.is_nan() => Value::from_bits(f64::NAN.to_bits()),
+            Unpacked::Number(n) => Value::from_bits(n.to_bits()),
+            Unpacked::String(s) => Value::object(Rc::new(s), STRING),
+            Unpacked::Function(function) => Value::object(function, FUNCTION),
+            Unpacked::Closure(closure) => Value::object(closure, CLOSURE),
+            Unpacked::Native(native) => Value::object(native, NATIVE),
+            Unpacked::Class(class) => Value::object(class, CLASS),
+            Unpacked::Instance(instance) => Value::object(instance, INSTANCE),
+            Unpacked::BoundMethod(bound) => Value::object(bound, BOUND_METHOD),
+            Unpacked::List(list) => Value::object(list, LIST),
+        }
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        // SAFETY: the type bits always match the pointer they were packed with
+        unsafe {
+            match self.object_type() {
+                None => (),
+                Some(STRING) => Rc::increment_strong_count(self.pointer::<Rc<str>>()),
+                Some(FUNCTION) => Rc::increment_strong_count(self.pointer::<Function>()),
+                Some(CLOSURE) => Rc::increment_strong_count(self.pointer::<Closure>()),
+                Some(NATIVE) => Rc::increment_strong_count(self.pointer::<Native>()),
+                Some(CLASS) => Rc::increment_strong_count(self.pointer::<Class>()),
+                Some(INSTANCE) => Rc::increment_strong_count(self.pointer::<Instance>()),
+                Some(BOUND_METHOD) => Rc::increment_strong_count(self.pointer::<BoundMethod>()),
+                Some(_) => Rc::increment_strong_count(self.pointer::<List>()),
+            }
+        }
+        Value::from_bits(self.bits)
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        // SAFETY: the type bits always match the pointer they were packed with
+        unsafe {
+            match self.object_type() {
+                None => (),
+                Some(STRING) => Rc::decrement_strong_count(self.pointer::<Rc<str>>()),
+                Some(FUNCTION) => Rc::decrement_strong_count(self.pointer::<Function>()),
+                Some(CLOSURE) => Rc::decrement_strong_count(self.pointer::<Closure>()),
+                Some(NATIVE) => Rc::decrement_strong_count(self.pointer::<Native>()),
+                Some(CLASS) => Rc::decrement_strong_count(self.pointer::<Class>()),
+                Some(INSTANCE) => Rc::decrement_strong_count(self.pointer::<Instance>()),
+                Some(BOUND_METHOD) => Rc::decrement_strong_count(self.pointer::<BoundMethod>()),
+                Some(_) => Rc::decrement_strong_count(self.pointer::<List>()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size() {
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+
+    #[test]
+    fn reference_counts() {
+        let class = Rc::new(Class::new(Rc::from("A")));
+        let value = Value::from(class.clone());
+        let copy = value.clone();
+        assert_eq!(Rc::strong_count(&class), 3);
+        drop(value);
+        drop(copy);
+        assert_eq!(Rc::strong_count(&class), 1);
+    }
+
+    #[test]
+    fn nan_is_a_number() {
+        let nan = f64::from_bits(OBJECT | 1);
+        assert!(nan.is_nan());
+        assert!(Value::from(nan).as_number().unwrap().is_nan());
+    }
+}
@@ -0,0 +1,48 @@
+use std::rc::Rc;
+
+use super::Unpacked;
+use crate::object::Function;
+
+/// A value stored as a tagged enum, which is twice the size of a number.
+#[derive(Clone)]
+pub struct Value(Unpacked);
+
+impl From<Unpacked> for Value {
+    fn from(unpacked: Unpacked) -> Self {
+        Value(unpacked)
+    }
+}
+
+impl Value {
+    pub const NIL: Value = Value(Unpacked::Nil);
+
+    pub fn unpack(&self) -> Unpacked {
+        self.0.clone()
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self.0 {
+            Unpacked::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&Rc<str>> {
+        match &self.0 {
+            Unpacked::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&Function> {
+        match &self.0 {
+            Unpacked::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_falsey(&self) -> bool {
+        matches!(self.0, Unpacked::Nil | Unpacked::Bool(false))
+    }
+}
@@ -1,19 +1,217 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::Write,
+    mem::size_of,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use thiserror::Error;
+
 use crate::{
     bytecode::{
-        core::{Chunk, Instruction},
-        parser::BytecodeParseError,
+        core::{BinaryOp, Capture, Instruction, OpCode},
+        parser::{BytecodeParseError, BytecodeParser, InstructionMetadata},
     },
-    value::Value,
+    object::{BoundMethod, Class, Closure, Function, Instance, Native, Upvalue},
+    value::{Unpacked, Value},
 };
 
+/// The maximum depth of nested calls.
+pub const FRAMES_MAX: usize = 64;
+/// How many instructions run between checks of the limits that aren't checked every instruction.
+const CHECK_INTERVAL: u64 = 1024;
+
+/// Bounds on what a script may use, so that untrusted scripts can't run forever or take all the
+/// memory. Each script a VM interprets gets the whole of each limit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    /// the number of instructions a script may run
+    pub instructions: Option<u64>,
+    /// the bytes a script may allocate for strings and objects, including strings returned by
+    /// natives, counted as they're created and never given back, so this bounds the garbage a
+    /// script makes as well as what it keeps
+    pub heap_bytes: Option<usize>,
+    /// the number of values on the stack, checked as each one is pushed
+    pub stack: Option<usize>,
+    /// the depth of nested calls, which can't go past the VM's own maximum of 64 however high
+    /// it's set
+    pub frames: Option<usize>,
+    /// how long a script may run for in wall-clock time, checked every few instructions
+    pub timeout: Option<Duration>,
+}
+
+/// What the running script has used of its limits.
+#[derive(Default)]
+struct Usage {
+    /// the instruction count at which the script runs out of instructions
+    instructions_end: u64,
+    /// the instruction count at which the limits are next checked
+    next_check: u64,
+    heap_bytes: usize,
+    /// the most values the stack can hold
+    stack_max: usize,
+    /// when the script has to be finished by
+    deadline: Option<Instant>,
+}
+
+impl Usage {
+    /// Starts counting a script's usage, when the VM has run `instructions` for earlier scripts.
+    fn new(limits: &Limits, instructions: u64) -> Usage {
+        let instructions_end = limits
+            .instructions
+            .map_or(u64::MAX, |limit| instructions.saturating_add(limit));
+        Usage {
+            instructions_end,
+            next_check: instructions.min(instructions_end),
+            heap_bytes: 0,
+            stack_max: limits.stack.unwrap_or(usize::MAX),
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+}
+
+/// A function invocation that is in progress.
+pub struct CallFrame {
+    closure: Rc<Closure>,
+    /// the position of the next instruction in the closure's chunk
+    ip: usize,
+    /// the stack slot of the called value, with the arguments and locals above it
+    slots: usize,
+}
+
+impl CallFrame {
+    pub fn function(&self) -> &Function {
+        &self.closure.function
+    }
+
+    /// The position and line of the next instruction to run.
+    pub fn metadata(&self) -> InstructionMetadata {
+        let line = self.function().chunk.lines.get(self.ip);
+        InstructionMetadata::new(self.ip, line.copied().unwrap_or_default())
+    }
+
+    /// The stack slot of the called value, with the arguments and locals above it.
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+}
+
+/// Watches a VM run a script, e.g. to profile or debug it.
+pub trait Hook {
+    /// Called before each instruction runs, which is at the top frame's
+    /// [`metadata`](CallFrame::metadata). Returning an error stops the script.
+    fn before_instruction(&mut self, vm: &VM) -> Result<(), InterpreterError>;
+
+    /// Called once the script has stopped running, whether it returned or failed.
+    fn finish(&mut self) {}
+}
+
+/// Runs before each instruction. Nothing is observed without a hook, and the call compiles away.
+trait Observer {
+    fn observe(&mut self, vm: &mut VM, ip: usize) -> Result<(), InterpreterError>;
+}
+
+impl Observer for () {
+    #[inline(always)]
+    fn observe(&mut self, _vm: &mut VM, _ip: usize) -> Result<(), InterpreterError> {
+        Ok(())
+    }
+}
+
+impl Observer for &mut dyn Hook {
+    fn observe(&mut self, vm: &mut VM, ip: usize) -> Result<(), InterpreterError> {
+        vm.frame_mut().ip = ip;
+        self.before_instruction(vm)
+    }
+}
+
 pub struct VM {
     pub stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    pub globals: HashMap<Rc<str>, Value>,
+    /// captured variables that still refer to the stack, sorted by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    output: Box<dyn Write>,
+    instructions: u64,
+    pub limits: Limits,
+    usage: Usage,
+    /// the calls in progress when the last script failed, innermost first, kept after the stack
+    /// is cleared so they can be looked into
+    pub backtrace: Vec<FailedCall>,
+}
+
+/// A call that was in progress when a script failed.
+#[derive(Debug, Clone)]
+pub struct FailedCall {
+    pub function: Rc<Function>,
+    /// the line of the instruction that failed, or made the call above this one
+    pub line: u32,
+    /// the stack slot of the called value
+    pub slots: usize,
+    /// the called value, arguments and locals, and any temporaries above them
+    pub stack: Vec<Value>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Error)]
 pub enum InterpreterError {
+    #[error("invalid bytecode: {0}")]
     ParseError(BytecodeParseError),
+    #[error("popped from an empty stack")]
     EmptyStack,
+    #[error("invalid local slot ({0})")]
+    InvalidLocal(u8),
+    #[error("invalid upvalue index ({0})")]
+    InvalidUpvalue(u8),
+    #[error("operand must be a number")]
+    OperandMustBeNumber,
+    #[error("operands must be numbers")]
+    OperandsMustBeNumbers,
+    #[error("operands must be two numbers or two strings")]
+    OperandsMustBeNumbersOrStrings,
+    #[error("undefined variable '{0}'")]
+    UndefinedVariable(String),
+    #[error("undefined property '{0}'")]
+    UndefinedProperty(String),
+    #[error("can only call functions and classes")]
+    NotCallable,
+    #[error("expected {expected} arguments but got {found}")]
+    WrongArity { expected: u8, found: u8 },
+    #[error("stack overflow")]
+    StackOverflow,
+    #[error("stack grew past {0} values")]
+    StackLimit(usize),
+    #[error("calls nested more than {0} deep")]
+    FrameLimit(usize),
+    #[error("ran out of instructions after running {0}")]
+    InstructionLimit(u64),
+    #[error("allocated more than {0} bytes")]
+    HeapLimit(usize),
+    #[error("ran for longer than {0:?}")]
+    DeadlineExceeded(Duration),
+    #[error("only instances have properties")]
+    OnlyInstancesHaveProperties,
+    #[error("only instances have fields")]
+    OnlyInstancesHaveFields,
+    #[error("only instances have methods")]
+    OnlyInstancesHaveMethods,
+    #[error("superclass must be a class")]
+    SuperclassMustBeClass,
+    #[error("only lists can be indexed")]
+    OnlyListsCanBeIndexed,
+    #[error("index must be a number")]
+    IndexMustBeNumber,
+    #[error("index {index} is out of bounds for a list of length {length}")]
+    IndexOutOfBounds { index: f64, length: usize },
+    #[error("{0}")]
+    Native(String),
+    #[error("unable to write output: {0}")]
+    Output(String),
+    #[error("stopped before finishing")]
+    Aborted,
+    #[error("exited with code {0}")]
+    Exit(i32),
 }
 
 impl From<BytecodeParseError> for InterpreterError {
@@ -24,67 +222,1056 @@ impl From<BytecodeParseError> for InterpreterError {
 
 enum ControlFlow {
     Continue,
+    /// a new frame may be running
+    Call,
+    /// the previous frame is running again
+    Return,
     Break,
 }
 
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}
+
 impl VM {
     pub fn new() -> VM {
-        VM { stack: Vec::new() }
+        VM::with_output(Box::new(std::io::stdout()))
+    }
+
+    /// Creates a VM that prints to `output` rather than stdout.
+    pub fn with_output(output: Box<dyn Write>) -> VM {
+        let mut vm = VM {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            output,
+            instructions: 0,
+            limits: Limits::default(),
+            usage: Usage::default(),
+            backtrace: Vec::new(),
+        };
+        vm.define_native("clock", 0, |_| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| InterpreterError::Native(e.to_string()))?;
+            Ok(Value::from(now.as_secs_f64()))
+        });
+        vm.define_native("len", 1, |arguments| match arguments[0].unpack() {
+            Unpacked::List(list) => Ok(Value::from(list.elements.len() as f64)),
+            Unpacked::String(s) => Ok(Value::from(s.chars().count() as f64)),
+            _ => Err(InterpreterError::Native(
+                "len expects a list or a string".to_string(),
+            )),
+        });
+        vm
+    }
+
+    /// The number of instructions run so far, across every script this VM has interpreted.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn define_native<F>(&mut self, name: &str, arity: u8, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, InterpreterError> + 'static,
+    {
+        let native = Native {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        self.globals
+            .insert(Rc::from(name), Value::from(Rc::new(native)));
     }
 
     fn stack_pop(&mut self) -> Result<Value, InterpreterError> {
         self.stack.pop().ok_or(InterpreterError::EmptyStack)
     }
 
-    fn interpret(&mut self, instruction: Instruction) -> Result<ControlFlow, InterpreterError> {
-        match instruction {
-            Instruction::Return => {
-                println!("{:?}", self.stack_pop()?);
-                Ok(ControlFlow::Break)
+    fn peek(&self, distance: usize) -> Result<&Value, InterpreterError> {
+        self.stack
+            .len()
+            .checked_sub(distance + 1)
+            .map(|index| &self.stack[index])
+            .ok_or(InterpreterError::EmptyStack)
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no active call frame")
+    }
+
+    fn local_slot(&self, slot: u8) -> Result<usize, InterpreterError> {
+        let index = self.frame().slots + slot as usize;
+        if index < self.stack.len() {
+            Ok(index)
+        } else {
+            Err(InterpreterError::InvalidLocal(slot))
+        }
+    }
+
+    fn upvalue(&self, index: u8) -> Result<Rc<RefCell<Upvalue>>, InterpreterError> {
+        self.frame()
+            .closure
+            .upvalues
+            .get(index as usize)
+            .cloned()
+            .ok_or(InterpreterError::InvalidUpvalue(index))
+    }
+
+    fn call_value(&mut self, callee: Unpacked, arg_count: u8) -> Result<(), InterpreterError> {
+        match callee {
+            Unpacked::Closure(closure) => self.call(closure, arg_count),
+            Unpacked::Native(native) => {
+                if arg_count != native.arity {
+                    return Err(InterpreterError::WrongArity {
+                        expected: native.arity,
+                        found: arg_count,
+                    });
+                }
+                let args_start = self.stack.len() - arg_count as usize;
+                let result = (native.function)(&self.stack[args_start..])?;
+                if let Some(string) = result.as_string() {
+                    self.allocate(string.len())?;
+                }
+                self.stack.truncate(args_start - 1);
+                self.push(result)?;
+                Ok(())
+            }
+            Unpacked::Class(class) => {
+                let callee_slot = self.stack.len() - arg_count as usize - 1;
+                self.allocate(size_of::<Instance>())?;
+                self.stack[callee_slot] = Value::from(Rc::new(Instance::new(class.clone())));
+                let initializer = class.methods.borrow().get("init").cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => Err(InterpreterError::WrongArity {
+                        expected: 0,
+                        found: arg_count,
+                    }),
+                    None => Ok(()),
+                }
             }
-            Instruction::Constant(value) => {
-                self.stack.push(value);
-                Ok(ControlFlow::Continue)
+            Unpacked::BoundMethod(bound) => {
+                let callee_slot = self.stack.len() - arg_count as usize - 1;
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), arg_count)
             }
-            Instruction::Negate => {
-                let value = self.stack_pop()?;
-                self.stack.push(-value);
-                Ok(ControlFlow::Continue)
+            _ => Err(InterpreterError::NotCallable),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, arg_count: u8) -> Result<(), InterpreterError> {
+        if arg_count != closure.function.arity {
+            return Err(InterpreterError::WrongArity {
+                expected: closure.function.arity,
+                found: arg_count,
+            });
+        }
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(InterpreterError::StackOverflow);
+        }
+        match self.limits.frames {
+            Some(limit) if self.frames.len() >= limit => {
+                return Err(InterpreterError::FrameLimit(limit))
+            }
+            _ => (),
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count as usize - 1,
+        });
+        Ok(())
+    }
+
+    fn invoke(&mut self, name: &str, arg_count: u8) -> Result<(), InterpreterError> {
+        let instance = match self.peek(arg_count as usize)?.unpack() {
+            Unpacked::Instance(instance) => instance,
+            _ => return Err(InterpreterError::OnlyInstancesHaveMethods),
+        };
+        let field = instance.fields.borrow().get(name).cloned();
+        match field {
+            Some(field) => {
+                let callee_slot = self.stack.len() - arg_count as usize - 1;
+                let callee = field.unpack();
+                self.stack[callee_slot] = field;
+                self.call_value(callee, arg_count)
+            }
+            None => self.invoke_from_class(&instance.class, name, arg_count),
+        }
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: &Class,
+        name: &str,
+        arg_count: u8,
+    ) -> Result<(), InterpreterError> {
+        let method = class.methods.borrow().get(name).cloned();
+        match method {
+            Some(method) => self.call(method, arg_count),
+            None => Err(InterpreterError::UndefinedProperty(name.to_string())),
+        }
+    }
+
+    /// Replaces the instance at the top of the stack with its method bound to it.
+    fn bind_method(&mut self, class: &Class, name: &str) -> Result<(), InterpreterError> {
+        let method = class
+            .methods
+            .borrow()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| InterpreterError::UndefinedProperty(name.to_string()))?;
+        self.allocate(size_of::<BoundMethod>())?;
+        let receiver = self.stack_pop()?;
+        let bound = BoundMethod { receiver, method };
+        self.push(Value::from(Rc::new(bound)))?;
+        Ok(())
+    }
+
+    /// Pushes a value, as long as the stack has room for it under its limit.
+    #[inline(always)]
+    fn push(&mut self, value: Value) -> Result<(), InterpreterError> {
+        if self.stack.len() >= self.usage.stack_max {
+            return Err(InterpreterError::StackLimit(self.usage.stack_max));
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Counts bytes the script allocated against its heap limit.
+    fn allocate(&mut self, bytes: usize) -> Result<(), InterpreterError> {
+        self.usage.heap_bytes += bytes;
+        match self.limits.heap_bytes {
+            Some(limit) if self.usage.heap_bytes > limit => Err(InterpreterError::HeapLimit(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the instruction and time limits, which the run loop only does every so often.
+    #[cold]
+    #[inline(never)]
+    fn check_limits(&mut self) -> Result<(), InterpreterError> {
+        if self.instructions >= self.usage.instructions_end {
+            return Err(InterpreterError::InstructionLimit(
+                self.limits.instructions.unwrap_or(u64::MAX),
+            ));
+        }
+        if self
+            .usage
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(InterpreterError::DeadlineExceeded(
+                self.limits.timeout.unwrap_or_default(),
+            ));
+        }
+        self.usage.next_check =
+            (self.instructions + CHECK_INTERVAL).min(self.usage.instructions_end);
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open >= slot));
+        if let Some(position) = position {
+            let existing = &self.open_upvalues[position];
+            if matches!(*existing.borrow(), Upvalue::Open(open) if open == slot) {
+                return existing.clone();
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let position = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of all captured variables at or above `slot` off the stack.
+    fn close_upvalues(&mut self, slot: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(open) if open >= slot => {
+                    *upvalue = Upvalue::Closed(stack[open].clone());
+                    false
+                }
+                _ => true,
             }
-            Instruction::BinaryOp(op) => {
-                let b = self.stack_pop()?;
-                let a = self.stack_pop()?;
-                let result = Value::apply_binary_op(a, b, op);
+        });
+    }
 
-                log::trace!("{a} {op} {b} = {r}", a = a, op = op, b = b, r = result);
+    /// Runs the instruction at the reader's position, reading its opcode and operands straight
+    /// from the bytecode.
+    #[inline(always)]
+    fn execute(
+        &mut self,
+        reader: &mut BytecodeParser<'_>,
+    ) -> Result<ControlFlow, InterpreterError> {
+        let byte = match reader.chunk.code.get(reader.pos) {
+            Some(&byte) => byte,
+            None => return Ok(ControlFlow::Break),
+        };
+        #[cfg(feature = "trace")]
+        self.trace(reader);
+        let op =
+            OpCode::try_from(byte).map_err(|_| BytecodeParseError::UnknownInstruction(byte))?;
+        reader.pos += 1;
 
-                self.stack.push(result);
-                Ok(ControlFlow::Continue)
+        match op {
+            OpCode::Return => return self.return_from_call(),
+            OpCode::Constant => {
+                let (_, value) = reader.read_constant_at(op)?;
+                self.push(value.clone())?;
+            }
+            OpCode::Negate => self.negate()?,
+            OpCode::Add => self.binary_op(BinaryOp::Add)?,
+            OpCode::Subtract => self.binary_op(BinaryOp::Subtract)?,
+            OpCode::Multiply => self.binary_op(BinaryOp::Multiply)?,
+            OpCode::Divide => self.binary_op(BinaryOp::Divide)?,
+            OpCode::Equal => self.binary_op(BinaryOp::Equal)?,
+            OpCode::Greater => self.binary_op(BinaryOp::Greater)?,
+            OpCode::Less => self.binary_op(BinaryOp::Less)?,
+            OpCode::Nil => self.push(Value::NIL)?,
+            OpCode::True => self.push(Value::from(true))?,
+            OpCode::False => self.push(Value::from(false))?,
+            OpCode::Pop => {
+                self.stack_pop()?;
+            }
+            OpCode::GetLocal => self.get_local(reader.read_byte(op)?)?,
+            OpCode::SetLocal => self.set_local(reader.read_byte(op)?)?,
+            OpCode::GetGlobal => self.get_global(reader.read_string(op)?)?,
+            OpCode::DefineGlobal => self.define_global(reader.read_string(op)?)?,
+            OpCode::SetGlobal => self.set_global(reader.read_string(op)?)?,
+            OpCode::GetUpvalue => self.get_upvalue(reader.read_byte(op)?)?,
+            OpCode::SetUpvalue => self.set_upvalue(reader.read_byte(op)?)?,
+            OpCode::GetProperty => self.get_property(reader.read_string(op)?)?,
+            OpCode::SetProperty => self.set_property(reader.read_string(op)?)?,
+            OpCode::GetSuper => self.get_super(reader.read_string(op)?)?,
+            OpCode::Not => {
+                let value = self.stack_pop()?;
+                self.push(!value)?;
+            }
+            OpCode::Print => self.print_value()?,
+            OpCode::Jump => {
+                let offset = reader.read_short(op)?;
+                reader.pos += offset as usize;
+            }
+            OpCode::JumpIfFalse => {
+                let offset = reader.read_short(op)?;
+                if self.peek(0)?.is_falsey() {
+                    reader.pos += offset as usize;
+                }
+            }
+            OpCode::JumpIfTrue => {
+                let offset = reader.read_short(op)?;
+                if !self.peek(0)?.is_falsey() {
+                    reader.pos += offset as usize;
+                }
+            }
+            OpCode::Loop => {
+                let offset = reader.read_short(op)?;
+                reader.pos -= offset as usize;
+            }
+            OpCode::Call => {
+                let arg_count = reader.read_byte(op)?;
+                let callee = self.peek(arg_count as usize)?.unpack();
+                self.frame_mut().ip = reader.pos;
+                self.call_value(callee, arg_count)?;
+                return Ok(ControlFlow::Call);
+            }
+            OpCode::Invoke => {
+                let name = reader.read_string(op)?;
+                let arg_count = reader.read_byte(op)?;
+                self.frame_mut().ip = reader.pos;
+                self.invoke(name, arg_count)?;
+                return Ok(ControlFlow::Call);
             }
+            OpCode::SuperInvoke => {
+                let name = reader.read_string(op)?;
+                let arg_count = reader.read_byte(op)?;
+                self.frame_mut().ip = reader.pos;
+                self.super_invoke(name, arg_count)?;
+                return Ok(ControlFlow::Call);
+            }
+            OpCode::Closure => {
+                let (constant_id, constant) = reader.read_constant_at(op)?;
+                let function = match constant.unpack() {
+                    Unpacked::Function(function) => function,
+                    _ => return Err(BytecodeParseError::ExpectedFunction(op, constant_id).into()),
+                };
+                let upvalues = (0..function.upvalue_count)
+                    .map(|_| {
+                        let is_local = reader.read_byte(op)? != 0;
+                        let index = reader.read_byte(op)?;
+                        self.capture(is_local, index)
+                    })
+                    .collect::<Result<_, InterpreterError>>()?;
+                // shared upvalues are counted by every closure that captures them
+                self.allocate(
+                    size_of::<Closure>()
+                        + function.upvalue_count as usize * size_of::<RefCell<Upvalue>>(),
+                )?;
+                let closure = Closure { function, upvalues };
+                self.push(Value::from(Rc::new(closure)))?;
+            }
+            OpCode::CloseUpvalue => self.close_upvalue()?,
+            OpCode::Class => self.class(reader.read_string(op)?)?,
+            OpCode::Inherit => self.inherit()?,
+            OpCode::Method => self.method(reader.read_string(op)?)?,
+            OpCode::Index => self.index()?,
         }
+        Ok(ControlFlow::Continue)
     }
 
-    pub fn interpret_chunk(chunk: Chunk) -> Result<(), InterpreterError> {
-        let mut vm = VM::new();
+    /// Decodes the instruction at the reader's position, and runs it. This is how the vm used to
+    /// dispatch, and is kept to measure [`VM::execute`] against.
+    fn execute_decoded(
+        &mut self,
+        reader: &mut BytecodeParser<'_>,
+    ) -> Result<ControlFlow, InterpreterError> {
+        #[cfg(feature = "trace")]
+        self.trace(reader);
+        let instruction = match reader.next() {
+            Some((_, instruction)) => instruction?,
+            None => return Ok(ControlFlow::Break),
+        };
 
-        for (metadata, parsed) in chunk.iter() {
-            log::trace!("stack: {:?}", vm.stack);
-            match parsed {
-                Ok(instruction) => {
-                    log::debug!("{:04} {:4} {:?}", metadata.pos, metadata.line, instruction)
+        match instruction {
+            Instruction::Return => return self.return_from_call(),
+            Instruction::Constant(value) => self.push(value)?,
+            Instruction::Negate => self.negate()?,
+            Instruction::BinaryOp(op) => self.binary_op(op)?,
+            Instruction::Nil => self.push(Value::NIL)?,
+            Instruction::True => self.push(Value::from(true))?,
+            Instruction::False => self.push(Value::from(false))?,
+            Instruction::Pop => {
+                self.stack_pop()?;
+            }
+            Instruction::GetLocal(slot) => self.get_local(slot)?,
+            Instruction::SetLocal(slot) => self.set_local(slot)?,
+            Instruction::GetGlobal(name) => self.get_global(&name)?,
+            Instruction::DefineGlobal(name) => self.define_global(&name)?,
+            Instruction::SetGlobal(name) => self.set_global(&name)?,
+            Instruction::GetUpvalue(index) => self.get_upvalue(index)?,
+            Instruction::SetUpvalue(index) => self.set_upvalue(index)?,
+            Instruction::GetProperty(name) => self.get_property(&name)?,
+            Instruction::SetProperty(name) => self.set_property(&name)?,
+            Instruction::GetSuper(name) => self.get_super(&name)?,
+            Instruction::Not => {
+                let value = self.stack_pop()?;
+                self.push(!value)?;
+            }
+            Instruction::Print => self.print_value()?,
+            Instruction::Jump(offset) => reader.pos += offset as usize,
+            Instruction::JumpIfFalse(offset) => {
+                if self.peek(0)?.is_falsey() {
+                    reader.pos += offset as usize;
                 }
-                Err(e) => {
-                    log::error!("{:04} {:4} {:?}", metadata.pos, metadata.line, e)
+            }
+            Instruction::JumpIfTrue(offset) => {
+                if !self.peek(0)?.is_falsey() {
+                    reader.pos += offset as usize;
                 }
-            };
+            }
+            Instruction::Loop(offset) => reader.pos -= offset as usize,
+            Instruction::Call(arg_count) => {
+                let callee = self.peek(arg_count as usize)?.unpack();
+                self.frame_mut().ip = reader.pos;
+                self.call_value(callee, arg_count)?;
+                return Ok(ControlFlow::Call);
+            }
+            Instruction::Invoke(name, arg_count) => {
+                self.frame_mut().ip = reader.pos;
+                self.invoke(&name, arg_count)?;
+                return Ok(ControlFlow::Call);
+            }
+            Instruction::SuperInvoke(name, arg_count) => {
+                self.frame_mut().ip = reader.pos;
+                self.super_invoke(&name, arg_count)?;
+                return Ok(ControlFlow::Call);
+            }
+            Instruction::Closure(function, captures) => {
+                let upvalues = captures
+                    .into_iter()
+                    .map(|Capture { is_local, index }| self.capture(is_local, index))
+                    .collect::<Result<_, _>>()?;
+                // shared upvalues are counted by every closure that captures them
+                self.allocate(
+                    size_of::<Closure>()
+                        + function.upvalue_count as usize * size_of::<RefCell<Upvalue>>(),
+                )?;
+                let closure = Closure { function, upvalues };
+                self.push(Value::from(Rc::new(closure)))?;
+            }
+            Instruction::CloseUpvalue => self.close_upvalue()?,
+            Instruction::Class(name) => self.class(&name)?,
+            Instruction::Inherit => self.inherit()?,
+            Instruction::Method(name) => self.method(&name)?,
+            Instruction::Index => self.index()?,
+        }
+        Ok(ControlFlow::Continue)
+    }
+
+    fn return_from_call(&mut self) -> Result<ControlFlow, InterpreterError> {
+        let result = self.stack_pop()?;
+        let frame = self.frames.pop().expect("no active call frame");
+        self.close_upvalues(frame.slots);
+        self.stack.truncate(frame.slots);
+        // the script's own result is left for `start` to take
+        self.push(result)?;
+        if self.frames.is_empty() {
+            return Ok(ControlFlow::Break);
+        }
+        Ok(ControlFlow::Return)
+    }
+
+    fn negate(&mut self) -> Result<(), InterpreterError> {
+        let value = self.stack_pop()?;
+        self.push(value.negate()?)?;
+        Ok(())
+    }
+
+    fn binary_op(&mut self, op: BinaryOp) -> Result<(), InterpreterError> {
+        let b = self.stack_pop()?;
+        let a = self.stack_pop()?;
+        let result = Value::apply_binary_op(a, b, op)?;
+        if let Some(string) = result.as_string().filter(|_| op == BinaryOp::Add) {
+            self.allocate(string.len())?;
+        }
+        self.push(result)?;
+        Ok(())
+    }
+
+    fn get_local(&mut self, slot: u8) -> Result<(), InterpreterError> {
+        let value = self.stack[self.local_slot(slot)?].clone();
+        self.push(value)?;
+        Ok(())
+    }
+
+    fn set_local(&mut self, slot: u8) -> Result<(), InterpreterError> {
+        let index = self.local_slot(slot)?;
+        self.stack[index] = self.peek(0)?.clone();
+        Ok(())
+    }
+
+    fn get_global(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        let value = self
+            .globals
+            .get(name)
+            .cloned()
+            .ok_or_else(|| InterpreterError::UndefinedVariable(name.to_string()))?;
+        self.push(value)?;
+        Ok(())
+    }
+
+    fn define_global(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        let value = self.stack_pop()?;
+        self.globals.insert(name.clone(), value);
+        Ok(())
+    }
+
+    fn set_global(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        let value = self.peek(0)?.clone();
+        match self.globals.get_mut(name) {
+            Some(global) => *global = value,
+            None => return Err(InterpreterError::UndefinedVariable(name.to_string())),
+        }
+        Ok(())
+    }
+
+    fn get_upvalue(&mut self, index: u8) -> Result<(), InterpreterError> {
+        let value = match &*self.upvalue(index)?.borrow() {
+            Upvalue::Open(slot) => self.stack[*slot].clone(),
+            Upvalue::Closed(value) => value.clone(),
+        };
+        self.push(value)?;
+        Ok(())
+    }
+
+    fn set_upvalue(&mut self, index: u8) -> Result<(), InterpreterError> {
+        let value = self.peek(0)?.clone();
+        match &mut *self.upvalue(index)?.borrow_mut() {
+            Upvalue::Open(slot) => self.stack[*slot] = value,
+            Upvalue::Closed(closed) => *closed = value,
+        }
+        Ok(())
+    }
 
-            let instruction = parsed?;
-            match vm.interpret(instruction)? {
-                ControlFlow::Break => break,
-                ControlFlow::Continue => (),
+    fn get_property(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        let instance = match self.peek(0)?.unpack() {
+            Unpacked::Instance(instance) => instance,
+            _ => return Err(InterpreterError::OnlyInstancesHaveProperties),
+        };
+        let field = instance.fields.borrow().get(name).cloned();
+        match field {
+            Some(value) => {
+                self.stack_pop()?;
+                self.push(value)?;
+                Ok(())
             }
+            None => self.bind_method(&instance.class, name),
         }
+    }
+
+    fn set_property(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        let instance = match self.peek(1)?.unpack() {
+            Unpacked::Instance(instance) => instance,
+            _ => return Err(InterpreterError::OnlyInstancesHaveFields),
+        };
+        let value = self.stack_pop()?;
+        let previous = instance
+            .fields
+            .borrow_mut()
+            .insert(name.clone(), value.clone());
+        if previous.is_none() {
+            self.allocate(size_of::<(Rc<str>, Value)>())?;
+        }
+        self.stack_pop()?;
+        self.push(value)?;
+        Ok(())
+    }
+
+    fn get_super(&mut self, name: &str) -> Result<(), InterpreterError> {
+        match self.stack_pop()?.unpack() {
+            Unpacked::Class(superclass) => self.bind_method(&superclass, name),
+            _ => Err(InterpreterError::SuperclassMustBeClass),
+        }
+    }
+
+    fn print_value(&mut self) -> Result<(), InterpreterError> {
+        let value = self.stack_pop()?;
+        self.print(&value)
+    }
+
+    fn super_invoke(&mut self, name: &str, arg_count: u8) -> Result<(), InterpreterError> {
+        match self.stack_pop()?.unpack() {
+            Unpacked::Class(superclass) => self.invoke_from_class(&superclass, name, arg_count),
+            _ => Err(InterpreterError::SuperclassMustBeClass),
+        }
+    }
+
+    /// Finds the upvalue a new closure captures, either a local of the running frame or one of
+    /// its own upvalues.
+    fn capture(
+        &mut self,
+        is_local: bool,
+        index: u8,
+    ) -> Result<Rc<RefCell<Upvalue>>, InterpreterError> {
+        if is_local {
+            Ok(self.capture_upvalue(self.local_slot(index)?))
+        } else {
+            self.upvalue(index)
+        }
+    }
 
+    fn close_upvalue(&mut self) -> Result<(), InterpreterError> {
+        self.close_upvalues(self.stack.len() - 1);
+        self.stack_pop()?;
         Ok(())
     }
+
+    fn class(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        self.allocate(size_of::<Class>())?;
+        let class = Class::new(name.clone());
+        self.push(Value::from(Rc::new(class)))
+    }
+
+    fn inherit(&mut self) -> Result<(), InterpreterError> {
+        let superclass = match self.peek(1)?.unpack() {
+            Unpacked::Class(superclass) => superclass,
+            _ => return Err(InterpreterError::SuperclassMustBeClass),
+        };
+        if let Unpacked::Class(subclass) = self.stack_pop()?.unpack() {
+            let methods = superclass.methods.borrow().clone();
+            subclass.methods.borrow_mut().extend(methods);
+        }
+        Ok(())
+    }
+
+    fn method(&mut self, name: &Rc<str>) -> Result<(), InterpreterError> {
+        let method = self.stack_pop()?;
+        if let (Unpacked::Closure(method), Unpacked::Class(class)) =
+            (method.unpack(), self.peek(0)?.unpack())
+        {
+            class.methods.borrow_mut().insert(name.clone(), method);
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), InterpreterError> {
+        let index = self.stack_pop()?;
+        let list = match self.stack_pop()?.unpack() {
+            Unpacked::List(list) => list,
+            _ => return Err(InterpreterError::OnlyListsCanBeIndexed),
+        };
+        let index = index
+            .as_number()
+            .ok_or(InterpreterError::IndexMustBeNumber)?;
+        let element = (index.fract() == 0.0 && index >= 0.0)
+            .then(|| list.elements.get(index as usize))
+            .flatten()
+            .ok_or(InterpreterError::IndexOutOfBounds {
+                index,
+                length: list.elements.len(),
+            })?;
+        self.push(element.clone())?;
+        Ok(())
+    }
+
+    /// Logs the stack, and the instruction about to run.
+    #[cfg(feature = "trace")]
+    fn trace(&self, reader: &BytecodeParser<'_>) {
+        log::trace!("stack: {:?}", self.stack);
+        let mut parser = BytecodeParser {
+            chunk: reader.chunk,
+            pos: reader.pos,
+        };
+        match parser.next() {
+            Some((metadata, Ok(instruction))) => {
+                log::debug!("{:04} {:4} {:?}", metadata.pos, metadata.line, instruction)
+            }
+            Some((metadata, Err(e))) => {
+                log::debug!("{:04} {:4} {}", metadata.pos, metadata.line, e)
+            }
+            None => (),
+        }
+    }
+
+    /// Runs until the script returns, decoding each instruction first when `DECODE` is set.
+    fn run<O: Observer, const DECODE: bool>(
+        &mut self,
+        observer: &mut O,
+    ) -> Result<(), InterpreterError> {
+        loop {
+            // the running frame's code is read through one reader until another frame runs, and
+            // its position is only written back to the frame when another frame is called or an
+            // error needs a stack trace
+            let closure = self.frame().closure.clone();
+            let mut reader = BytecodeParser {
+                chunk: &closure.function.chunk,
+                pos: self.frame().ip,
+            };
+            loop {
+                // a single comparison, so that limits are cheap enough to always be on
+                let checked = if self.instructions >= self.usage.next_check {
+                    self.check_limits()
+                } else {
+                    Ok(())
+                };
+                self.instructions += 1;
+                let ip = reader.pos;
+                let result = checked
+                    .and_then(|_| observer.observe(self, ip))
+                    .and_then(|_| {
+                        if DECODE {
+                            self.execute_decoded(&mut reader)
+                        } else {
+                            self.execute(&mut reader)
+                        }
+                    });
+                match result {
+                    Ok(ControlFlow::Continue) => (),
+                    Ok(ControlFlow::Call | ControlFlow::Return) => break,
+                    Ok(ControlFlow::Break) => return Ok(()),
+                    Err(e) => {
+                        // past the failed instruction's opcode, so its line is reported
+                        self.frame_mut().ip = reader.pos;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs a compiled script. Globals are kept afterwards, so later scripts can refer to them.
+    pub fn interpret(&mut self, function: Rc<Function>) -> Result<(), InterpreterError> {
+        self.start::<_, false>(function, &mut ()).map(|_| ())
+    }
+
+    /// Runs a compiled script like [`interpret`](VM::interpret), returning the value it returned.
+    /// Compiled programs return nil unless they were compiled with
+    /// [`compile_returning`](crate::compiler::codegen::compile_returning).
+    pub fn evaluate(&mut self, function: Rc<Function>) -> Result<Value, InterpreterError> {
+        self.start::<_, false>(function, &mut ())
+    }
+
+    /// Prints a value to the VM's output, the way a print statement does.
+    pub fn print(&mut self, value: &Value) -> Result<(), InterpreterError> {
+        writeln!(self.output, "{}", value).map_err(|e| InterpreterError::Output(e.to_string()))
+    }
+
+    /// Runs a compiled script like [`interpret`](VM::interpret), calling the hook before every
+    /// instruction.
+    pub fn interpret_with_hook(
+        &mut self,
+        function: Rc<Function>,
+        mut hook: &mut dyn Hook,
+    ) -> Result<(), InterpreterError> {
+        let result = self.start::<_, false>(function, &mut hook);
+        hook.finish();
+        result.map(|_| ())
+    }
+
+    /// Runs a compiled script like [`VM::interpret`], but decodes each instruction before running
+    /// it. This is slower, and only kept to benchmark the vm's dispatch against.
+    pub fn interpret_decoded(&mut self, function: Rc<Function>) -> Result<(), InterpreterError> {
+        self.start::<_, true>(function, &mut ()).map(|_| ())
+    }
+
+    /// The calls in progress, innermost last.
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    fn start<O: Observer, const DECODE: bool>(
+        &mut self,
+        function: Rc<Function>,
+        observer: &mut O,
+    ) -> Result<Value, InterpreterError> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+        });
+        self.usage = Usage::new(&self.limits, self.instructions);
+        let result = self
+            .push(Value::from(closure.clone()))
+            .and_then(|_| self.call(closure, 0))
+            .and_then(|_| self.run::<O, DECODE>(observer));
+
+        if let Err(e) = &result {
+            self.backtrace = self.backtrace();
+            // a script is only aborted or exited on purpose, so its stack trace isn't interesting
+            if !matches!(e, InterpreterError::Aborted | InterpreterError::Exit(_)) {
+                for call in &self.backtrace {
+                    log::error!("[line {}] in {}", call.line, call.function);
+                }
+            }
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result.map(|_| self.stack.pop().unwrap_or(Value::NIL))
+    }
+
+    /// The calls in progress, innermost first, with the stack each of them was using.
+    fn backtrace(&self) -> Vec<FailedCall> {
+        let mut end = self.stack.len();
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let lines = &frame.closure.function.chunk.lines;
+                let line = lines
+                    .get(frame.ip.saturating_sub(1))
+                    .copied()
+                    .unwrap_or_default();
+                let slots = frame.slots.min(end);
+                let stack = self.stack[slots..end].to_vec();
+                end = slots;
+                FailedCall {
+                    function: frame.closure.function.clone(),
+                    line,
+                    slots,
+                    stack,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bytecode::{peephole::optimize_function, verifier},
+        compiler::{codegen::compile, constant_folding::fold_constants, parser::Parser},
+        object::List,
+        test_support::Output,
+    };
+    use test_case::test_case;
+
+    fn compiled(source: &str, optimize: bool) -> Rc<Function> {
+        let mut declarations = Parser::new(source).parse().unwrap();
+        if optimize {
+            declarations = fold_constants(declarations);
+        }
+        let mut function = compile(&declarations).unwrap();
+        if optimize {
+            function = optimize_function(&function).unwrap();
+        }
+        assert_eq!(verifier::verify(&function), Ok(()));
+        Rc::new(function)
+    }
+
+    fn run(source: &str, optimize: bool) -> (Result<(), InterpreterError>, String) {
+        let output = Output::default();
+        let result =
+            VM::with_output(Box::new(output.clone())).interpret(compiled(source, optimize));
+        let printed = String::from_utf8(output.0.take()).unwrap();
+        (result, printed)
+    }
+
+    #[test_case("print -(2 * 3) + 4;", "-2\n"; "arithmetic")]
+    #[test_case("print \"a\" + \"b\" == \"ab\";", "true\n"; "strings")]
+    #[test_case("var a = 1; { var a = 2; { var b = a + 1; print b; } } print a;", "3\n1\n"; "scopes")]
+    #[test_case("for (var i = 0; i < 3; i = i + 1) print i;", "0\n1\n2\n"; "for loop")]
+    #[test_case("var i = 0; while (i < 2) { print i; i = i + 1; }", "0\n1\n"; "while loop")]
+    #[test_case("print nil or 1 and 2;", "2\n"; "logical")]
+    #[test_case("var x = false; print !x and 1; if (!x) print 2; while (!x) x = true;", "1\n2\n"; "negated conditions")]
+    #[test_case("if (1 >= 2) print 1; else print 2;", "2\n"; "if else")]
+    #[test_case("fun f(n) { if (n < 2) return n; return f(n - 1) + f(n - 2); } print f(10);", "55\n"; "recursion")]
+    #[test_case("
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+        }
+        var c = counter();
+        c();
+        print c();
+    ", "2\n"; "closure")]
+    #[test_case("
+        var fs;
+        { var a = 1; fun f() { print a; } fs = f; a = 2; }
+        fs();
+    ", "2\n"; "closed upvalue")]
+    #[test_case("
+        class A {
+            init(name) { this.name = name; }
+            greet() { return \"hi \" + this.name; }
+        }
+        class B < A {
+            greet() { return super.greet() + \"!\"; }
+        }
+        var b = B(\"bob\");
+        print b.greet();
+        var greet = b.greet;
+        print greet();
+        print b;
+    ", "hi bob!\nhi bob!\nB instance\n"; "classes")]
+    fn program(source: &str, expected: &str) {
+        for optimize in [false, true] {
+            let (result, printed) = run(source, optimize);
+            assert_eq!(result, Ok(()));
+            assert_eq!(printed, expected);
+        }
+
+        let output = Output::default();
+        let mut vm = VM::with_output(Box::new(output.clone()));
+        assert_eq!(vm.interpret_decoded(compiled(source, false)), Ok(()));
+        assert_eq!(String::from_utf8(output.0.take()).unwrap(), expected);
+    }
+
+    #[test_case("print 1 + nil;", InterpreterError::OperandsMustBeNumbersOrStrings; "add nil")]
+    #[test_case("print -\"a\";", InterpreterError::OperandMustBeNumber; "negate string")]
+    #[test_case("print x;", InterpreterError::UndefinedVariable("x".to_string()); "undefined variable")]
+    #[test_case("fun f(a) {} f();", InterpreterError::WrongArity { expected: 1, found: 0 }; "arity")]
+    #[test_case("fun f() { f(); } f();", InterpreterError::StackOverflow; "stack overflow")]
+    #[test_case("class A {} print A().b;", InterpreterError::UndefinedProperty("b".to_string()); "undefined property")]
+    #[test_case("1();", InterpreterError::NotCallable; "not callable")]
+    #[test_case("print \"a\"[0];", InterpreterError::OnlyListsCanBeIndexed; "index string")]
+    fn runtime_error(source: &str, expected: InterpreterError) {
+        for optimize in [false, true] {
+            let (result, _) = run(source, optimize);
+            assert_eq!(result, Err(expected.clone()));
+        }
+    }
+
+    #[test_case("print l; print l[1]; print len(l); print len(\"héllo\");", Ok("[1, a]\na\n2\n5\n"); "elements")]
+    #[test_case("print l[2];", Err(InterpreterError::IndexOutOfBounds { index: 2.0, length: 2 }); "past the end")]
+    #[test_case("print l[-1];", Err(InterpreterError::IndexOutOfBounds { index: -1.0, length: 2 }); "negative")]
+    #[test_case("print l[0.5];", Err(InterpreterError::IndexOutOfBounds { index: 0.5, length: 2 }); "fraction")]
+    #[test_case("print l[nil];", Err(InterpreterError::IndexMustBeNumber); "nil index")]
+    fn lists(source: &str, expected: Result<&str, InterpreterError>) {
+        for decode in [false, true] {
+            let output = Output::default();
+            let mut vm = VM::with_output(Box::new(output.clone()));
+            let list = Rc::new(List {
+                elements: vec![Value::from(1.0), Value::from("a")],
+            });
+            vm.globals.insert(Rc::from("l"), Value::from(list));
+            let function = compiled(source, false);
+            let result = if decode {
+                vm.interpret_decoded(function)
+            } else {
+                vm.interpret(function)
+            };
+            let printed = String::from_utf8(output.0.take()).unwrap();
+            assert_eq!(result.map(|_| printed.as_str()), expected);
+        }
+    }
+
+    #[test]
+    fn globals_persist() {
+        let mut vm = VM::with_output(Box::new(Output::default()));
+        for source in ["var a = 1;", "a = a + 1;"] {
+            let declarations = Parser::new(source).parse().unwrap();
+            vm.interpret(Rc::new(compile(&declarations).unwrap()))
+                .unwrap();
+        }
+        assert_eq!(vm.globals.get("a"), Some(&Value::from(2.0)));
+    }
+
+    const FOREVER: &str = "while (true) {}";
+
+    #[test_case(FOREVER, Limits { instructions: Some(5000), ..Limits::default() }, InterpreterError::InstructionLimit(5000); "instructions")]
+    #[test_case(FOREVER, Limits { timeout: Some(Duration::ZERO), ..Limits::default() }, InterpreterError::DeadlineExceeded(Duration::ZERO); "timeout")]
+    #[test_case("var s = \"a\"; while (true) s = s + s;", Limits { heap_bytes: Some(1 << 20), ..Limits::default() }, InterpreterError::HeapLimit(1 << 20); "string heap")]
+    #[test_case("class A {} while (true) A();", Limits { heap_bytes: Some(1 << 16), ..Limits::default() }, InterpreterError::HeapLimit(1 << 16); "instance heap")]
+    #[test_case("fun f(a, b, c, d) {} f(1, 2, 3, 4);", Limits { stack: Some(4), ..Limits::default() }, InterpreterError::StackLimit(4); "stack on call")]
+    #[test_case("{ var a; var b; var c; var d; }", Limits { stack: Some(4), ..Limits::default() }, InterpreterError::StackLimit(4); "stack on push")]
+    #[test_case("fun f(n) { if (n > 0) f(n - 1); } f(3);", Limits { frames: Some(3), ..Limits::default() }, InterpreterError::FrameLimit(3); "frames")]
+    #[test_case("fun f() { f(); } f();", Limits { frames: Some(1000), ..Limits::default() }, InterpreterError::StackOverflow; "frames past the maximum")]
+    fn limits(source: &str, limits: Limits, expected: InterpreterError) {
+        for decode in [false, true] {
+            let mut vm = VM::with_output(Box::new(Output::default()));
+            vm.limits = limits;
+            let function = compiled(source, false);
+            let result = if decode {
+                vm.interpret_decoded(function)
+            } else {
+                vm.interpret(function)
+            };
+            assert_eq!(result, Err(expected.clone()));
+        }
+    }
+
+    #[test]
+    fn native_strings_count_towards_the_heap() {
+        let mut vm = VM::with_output(Box::new(Output::default()));
+        vm.define_native("big", 0, |_| Ok(Value::from("x".repeat(1000).as_str())));
+        vm.limits = Limits {
+            heap_bytes: Some(1 << 16),
+            ..Limits::default()
+        };
+        assert_eq!(
+            vm.interpret(compiled("while (true) big();", false)),
+            Err(InterpreterError::HeapLimit(1 << 16))
+        );
+    }
+
+    #[test]
+    fn limits_apply_to_each_script() {
+        let mut vm = VM::with_output(Box::new(Output::default()));
+        vm.limits = Limits {
+            instructions: Some(100),
+            heap_bytes: Some(1000),
+            ..Limits::default()
+        };
+        let source = "for (var i = 0; i < 5; i = i + 1) { var s = \"ab\" + \"cd\"; }";
+        for _ in 0..10 {
+            assert_eq!(vm.interpret(compiled(source, false)), Ok(()));
+        }
+    }
 }
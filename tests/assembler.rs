@@ -0,0 +1,86 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+fn rlox(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .env("RUST_LOG", "off")
+        .output()
+        .expect("unable to run rlox");
+    assert!(output.status.success(), "rlox {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A path in a temporary directory, named after the test so tests don't clash.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rlox-assembler-{}", name))
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let source = temp_path("round-trip.lox");
+    std::fs::write(
+        &source,
+        "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        class A { init(x) { this.x = x; } get() { return this.x; } }
+        for (var i = 0; i < 5; i = i + 1) print fib(i) + A(i).get();",
+    )
+    .unwrap();
+    let compiled = temp_path("round-trip-compiled.loxc");
+    let assembly = temp_path("round-trip.s");
+    let assembled = temp_path("round-trip-assembled.loxc");
+
+    rlox(&["compile", path(&source), "-o", path(&compiled)]);
+    rlox(&["compile", "-S", path(&source), "-o", path(&assembly)]);
+    rlox(&["asm", path(&assembly), "-o", path(&assembled)]);
+
+    assert_eq!(
+        rlox(&["disasm", "--asm", path(&compiled)]),
+        std::fs::read_to_string(&assembly).unwrap()
+    );
+    assert_eq!(
+        std::fs::read(&assembled).unwrap(),
+        std::fs::read(&compiled).unwrap()
+    );
+    assert_eq!(rlox(&["run", path(&assembled)]), "0\n2\n3\n5\n7\n");
+}
+
+#[test]
+fn hand_written() {
+    let assembly = temp_path("hand-written.s");
+    std::fs::write(
+        &assembly,
+        "@1
+    CONSTANT 1.5
+    CONSTANT 2
+    ADD
+    PRINT
+    NIL
+    RETURN
+",
+    )
+    .unwrap();
+    let assembled = temp_path("hand-written.loxc");
+
+    rlox(&["asm", path(&assembly), "-o", path(&assembled)]);
+    assert_eq!(rlox(&["run", path(&assembled)]), "3.5\n");
+}
+
+#[test]
+fn unverified() {
+    let assembly = temp_path("unverified.s");
+    std::fs::write(&assembly, "POP\nRETURN\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(["asm", path(&assembly)])
+        .env("RUST_LOG", "off")
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
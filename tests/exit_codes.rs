@@ -0,0 +1,110 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use test_case::test_case;
+
+// the sysexits.h codes from src/exit_code.rs
+const FAILURE: i32 = 1;
+const DATA_ERROR: i32 = 65;
+const SOFTWARE: i32 = 70;
+const IO_ERROR: i32 = 74;
+
+fn rlox(args: &[&str], stdin: &str) -> i32 {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .env("RUST_LOG", "off")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("unable to run rlox");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait().unwrap().code().expect("rlox was killed")
+}
+
+/// Writes a lox file to a temporary directory, named after the test so tests don't clash.
+fn lox_file(name: &str, source: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rlox-exit-code-{}.lox", name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+#[test_case("print 1 + 2;", 0; "success")]
+#[test_case("print (1;", DATA_ERROR; "parse error")]
+#[test_case("print \"unterminated;", DATA_ERROR; "scanner error")]
+#[test_case("return 1;", DATA_ERROR; "compile error")]
+#[test_case("print -nil;", SOFTWARE; "runtime error")]
+#[test_case("fun f() { f(); } f();", SOFTWARE; "stack overflow")]
+fn eval(code: &str, expected: i32) {
+    assert_eq!(rlox(&["-e", code], ""), expected);
+}
+
+#[test_case("print 1 + 2;", 0; "success")]
+#[test_case("print (1;", DATA_ERROR; "parse error")]
+#[test_case("return 1;", DATA_ERROR; "compile error")]
+#[test_case("print -nil;", SOFTWARE; "runtime error")]
+fn run_stdin(code: &str, expected: i32) {
+    assert_eq!(rlox(&["run", "-"], code), expected);
+}
+
+#[test]
+fn files() {
+    let good = lox_file("good", b"print 1;");
+    let bad = lox_file("bad", b"var;");
+    let not_utf8 = lox_file("not-utf8", b"print \"\xff\";");
+    let missing = std::env::temp_dir().join("rlox-exit-code-missing.lox");
+    let path = |path: &PathBuf| path.to_str().unwrap().to_string();
+
+    assert_eq!(rlox(&["run", &path(&good)], ""), 0);
+    assert_eq!(rlox(&["run", &path(&bad)], ""), DATA_ERROR);
+    assert_eq!(rlox(&["run", &path(&not_utf8)], ""), DATA_ERROR);
+    assert_eq!(rlox(&["run", &path(&missing)], ""), IO_ERROR);
+    assert_eq!(rlox(&["check", &path(&good), &path(&bad)], ""), DATA_ERROR);
+    assert_eq!(rlox(&["check", &path(&missing)], ""), IO_ERROR);
+    assert_eq!(rlox(&["ast", &path(&bad)], ""), DATA_ERROR);
+    assert_eq!(rlox(&["fmt", "--check", &path(&missing)], ""), IO_ERROR);
+    // source that happens to start with the magic bytes of compiled files is still source, so
+    // this fails at runtime, as LOXC isn't defined
+    let magic = lox_file("magic", b"LOXC;");
+    assert_eq!(rlox(&["run", &path(&magic)], ""), SOFTWARE);
+    // compiling stdin without an output is a usage error
+    assert_eq!(rlox(&["compile", "-"], "print 1;"), FAILURE);
+}
+
+#[test]
+fn script_exit() {
+    assert_eq!(rlox(&["--allow-exit", "-e", "exit(3);"], ""), 3);
+    assert_eq!(rlox(&["-e", "exit(3);"], ""), SOFTWARE);
+    let script = lox_file("args", b"exit(len(args()));");
+    let script = script.to_str().unwrap();
+    assert_eq!(rlox(&["run", "--allow-exit", script, "a", "b"], ""), 2);
+    assert_eq!(rlox(&["--allow-exit", script, "a"], ""), 1);
+    assert_eq!(
+        rlox(&["debug", "--allow-exit", script, "a", "b", "c"], "c\n"),
+        3
+    );
+}
+
+#[test]
+fn nesting() {
+    let nested = |depth| "(".repeat(depth) + "1" + &")".repeat(depth) + ";";
+    assert_eq!(rlox(&["-e", &nested(250)], ""), 0);
+    assert_eq!(rlox(&["-e", &nested(1000)], ""), DATA_ERROR);
+    let blocks = "{".repeat(1000) + &"}".repeat(1000);
+    assert_eq!(rlox(&["-e", &blocks], ""), DATA_ERROR);
+}
+
+#[test_case(&["--max-instructions", "1000"]; "instructions")]
+#[test_case(&["--timeout", "0.1"]; "timeout")]
+fn limits(flags: &[&str]) {
+    let args = [flags, &["-e", "while (true) {}"]].concat();
+    assert_eq!(rlox(&args, ""), SOFTWARE);
+}